@@ -15,11 +15,13 @@ use validator::Validate;
 use shared::models::{User, Report};
 use shared::crypto::KeyPair;
 use shared::errors::AppError;
+use shared::restrictions::{RestrictionCache, DEFAULT_CACHE_TTL};
 
 struct AppState {
     db_pool: PgPool,
     jwt_secret: String,
     refresh_token_secret: String,
+    restrictions: Arc<RestrictionCache>,
 }
 
 #[derive(Debug, Clone)]
@@ -103,12 +105,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .connect(&database_url)
         .await?;
     
-    sqlx::migrate!("./migrations").run(&db_pool).await?;
+    shared::migrations::MIGRATOR.run(&db_pool).await?;
+    
+    let kafka_brokers = std::env::var("KAFKA_BROKERS")
+        .unwrap_or_else(|_| "localhost:9092".to_string());
+    
+    let restrictions = Arc::new(RestrictionCache::new(db_pool.clone(), DEFAULT_CACHE_TTL));
+    let restrictions_clone = restrictions.clone();
+    tokio::spawn(async move {
+        if let Err(e) = restrictions_clone.run(kafka_brokers, "auth").await {
+            error!("Restriction cache consumer error: {}", e);
+        }
+    });
     
     let state = Arc::new(AppState {
         db_pool,
         jwt_secret,
         refresh_token_secret,
+        restrictions,
     });
     
     let app = Router::new()
@@ -176,15 +190,16 @@ async fn register(
         User,
         r#"
         INSERT INTO users 
-        (username, email, password_hash, salt, public_key, created_at, updated_at)
-        VALUES ($1, $2, $3, $4, $5, NOW(), NOW())
+        (username, email, password_hash, salt, public_key, dh_public_key, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, NOW(), NOW())
         RETURNING *
         "#,
         payload.username,
         payload.email,
         password_hash,
         salt,
-        payload.public_key
+        payload.public_key,
+        payload.dh_public_key
     )
     .fetch_one(&mut tx)
     .await?;
@@ -266,9 +281,14 @@ async fn login(
         return Err(AppError::Unauthorized("Invalid credentials".to_string()));
     }
     
+    // Checked after the password so a ban doesn't reveal that an account exists
+    if state.restrictions.is_banned(user.id).await? {
+        return Err(AppError::Forbidden("Account suspended".to_string()));
+    }
+    
     // Get user's public keys
     let keys = sqlx::query!(
-        "SELECT public_key FROM users WHERE id = $1",
+        "SELECT public_key, dh_public_key FROM users WHERE id = $1",
         user.id
     )
     .fetch_one(&state.db_pool)
@@ -336,7 +356,7 @@ async fn login(
             username: user.username,
             email: user.email,
             public_key: keys.public_key,
-            dh_public_key: String::new(), // Would need separate field
+            dh_public_key: keys.dh_public_key.unwrap_or_default(),
             created_at: user.created_at,
         },
     };
@@ -359,9 +379,83 @@ async fn refresh_token(
     // Find session by refresh token
     let session = sqlx::query!(
         r#"
-        SELECT s.*, u.*
+        SELECT s.id, s.user_id, s.device_id
         FROM sessions s
         JOIN users u ON s.user_id = u.id
         WHERE s.refresh_token_hash = $1 
         AND s.revoked_at IS NULL
-        AND s.expires_at > NOW
+        AND s.expires_at > NOW()
+        "#,
+        refresh_token_hash
+    )
+    .fetch_optional(&state.db_pool)
+    .await?
+    .ok_or(AppError::Unauthorized("Invalid refresh token".to_string()))?;
+    
+    // A ban has to end existing sessions too, not just refuse new logins
+    if state.restrictions.is_banned(session.user_id).await? {
+        sqlx::query!(
+            "UPDATE sessions SET revoked_at = NOW() WHERE id = $1",
+            session.id
+        )
+        .execute(&state.db_pool)
+        .await?;
+        
+        return Err(AppError::Forbidden("Account suspended".to_string()));
+    }
+    
+    let user = sqlx::query_as!(
+        User,
+        "SELECT * FROM users WHERE id = $1",
+        session.user_id
+    )
+    .fetch_one(&state.db_pool)
+    .await?;
+    
+    if !user.is_active {
+        return Err(AppError::Forbidden("Account deactivated".to_string()));
+    }
+    
+    let keys = sqlx::query!(
+        "SELECT public_key, dh_public_key FROM users WHERE id = $1",
+        user.id
+    )
+    .fetch_one(&state.db_pool)
+    .await?;
+    
+    // Rotate both tokens; the old refresh token stops working
+    let (access_token, refresh_token, expires_in) = 
+        generate_tokens(&user, &session.device_id, &session.id, &state)?;
+    
+    let access_token_hash = hash_token(&access_token);
+    let refresh_token_hash = hash_token(&refresh_token);
+    
+    sqlx::query!(
+        r#"
+        UPDATE sessions
+        SET refresh_token_hash = $2, access_token_hash = $3
+        WHERE id = $1
+        "#,
+        session.id,
+        refresh_token_hash,
+        access_token_hash
+    )
+    .execute(&state.db_pool)
+    .await?;
+    
+    let response = AuthResponse {
+        access_token,
+        refresh_token,
+        expires_in,
+        user: UserResponse {
+            id: user.id,
+            username: user.username,
+            email: user.email,
+            public_key: keys.public_key,
+            dh_public_key: keys.dh_public_key.unwrap_or_default(),
+            created_at: user.created_at,
+        },
+    };
+    
+    Ok(Json(response))
+}
@@ -231,6 +231,36 @@ impl MessagingClient {
                                 WsMessage::Typing(typing) => {
                                     let _ = message_tx.send(IncomingMessage::TypingIndicator(typing));
                                 }
+                                WsMessage::MessageAck(ack) => {
+                                    let _ = message_tx.send(IncomingMessage::MessageAck(ack));
+                                }
+                                WsMessage::MessageEdited(edited) => {
+                                    let _ = message_tx.send(IncomingMessage::MessageEdited(edited));
+                                }
+                                WsMessage::MessageDeleted(deleted) => {
+                                    let _ = message_tx.send(IncomingMessage::MessageDeleted(deleted));
+                                }
+                                WsMessage::ReactionsUpdated(reactions) => {
+                                    let _ = message_tx.send(IncomingMessage::ReactionsUpdated(reactions));
+                                }
+                                WsMessage::PinUpdated(pin) => {
+                                    let _ = message_tx.send(IncomingMessage::PinUpdated(pin));
+                                }
+                                WsMessage::ThreadUpdated(thread) => {
+                                    let _ = message_tx.send(IncomingMessage::ThreadUpdated(thread));
+                                }
+                                WsMessage::MessageRead(read) => {
+                                    let _ = message_tx.send(IncomingMessage::MessageRead(read));
+                                }
+                                WsMessage::ReadMarkerUpdated(marker) => {
+                                    let _ = message_tx.send(IncomingMessage::ReadMarkerUpdated(marker));
+                                }
+                                WsMessage::ConversationUpdated(update) => {
+                                    let _ = message_tx.send(IncomingMessage::ConversationUpdated(update));
+                                }
+                                WsMessage::MessagesExpired(expired) => {
+                                    let _ = message_tx.send(IncomingMessage::MessagesExpired(expired));
+                                }
                                 _ => {}
                             }
                         }
@@ -258,6 +288,18 @@ impl MessagingClient {
         conversation_id: Uuid,
         content: &str,
         reply_to: Option<Uuid>,
+    ) -> Result<Uuid, SdkError> {
+        self.send_message_with_mentions(conversation_id, content, reply_to, MessageMentions::default()).await
+    }
+    
+    /// Mentioning everyone needs the MentionEveryone permission; the server
+    /// drops the message otherwise.
+    pub async fn send_message_with_mentions(
+        &self,
+        conversation_id: Uuid,
+        content: &str,
+        reply_to: Option<Uuid>,
+        mentions: MessageMentions,
     ) -> Result<Uuid, SdkError> {
         let user_id = self.user_id.ok_or_else(|| 
             SdkError::InvalidState("Not authenticated".to_string()))?;
@@ -282,6 +324,10 @@ impl MessagingClient {
                 nonce,
                 reply_to,
                 timestamp,
+                mentions,
+                message_type: None,
+                expires_at: None,
+                signature: None,
             };
             
             let ws_message = WsMessage::Message(message);
@@ -301,14 +347,136 @@ impl MessagingClient {
         }
     }
     
+    pub async fn edit_message(
+        &self,
+        conversation_id: Uuid,
+        message_id: Uuid,
+        content: &str,
+    ) -> Result<(), SdkError> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        
+        let sessions = self.sessions.read().await;
+        let session = sessions.get(&conversation_id)
+            .ok_or_else(|| SdkError::InvalidState("No encryption session for conversation".to_string()))?;
+        
+        let (encrypted_content, nonce) = session.encrypt(content.as_bytes())
+            .map_err(|e| SdkError::EncryptionError(e.to_string()))?;
+        
+        let edit = EditMessage {
+            conversation_id,
+            message_id,
+            content: encrypted_content,
+            nonce,
+            timestamp,
+        };
+        
+        self.send_frame(&WsMessage::EditMessage(edit))
+    }
+    
+    pub async fn delete_message(
+        &self,
+        conversation_id: Uuid,
+        message_id: Uuid,
+        scope: DeleteScope,
+    ) -> Result<(), SdkError> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        
+        let delete = DeleteMessage {
+            conversation_id,
+            message_id,
+            scope,
+            timestamp,
+        };
+        
+        self.send_frame(&WsMessage::DeleteMessage(delete))
+    }
+    
+    pub async fn react(
+        &self,
+        conversation_id: Uuid,
+        message_id: Uuid,
+        emoji: &str,
+        add: bool,
+    ) -> Result<(), SdkError> {
+        let reaction = ReactionRequest {
+            conversation_id,
+            message_id,
+            emoji: emoji.to_string(),
+            add,
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64,
+        };
+        
+        self.send_frame(&WsMessage::React(reaction))
+    }
+    
+    pub async fn set_pinned(
+        &self,
+        conversation_id: Uuid,
+        message_id: Uuid,
+        pinned: bool,
+    ) -> Result<(), SdkError> {
+        let pin = PinRequest {
+            conversation_id,
+            message_id,
+            pinned,
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64,
+        };
+        
+        self.send_frame(&WsMessage::Pin(pin))
+    }
+    
+    pub async fn mark_read(
+        &self,
+        conversation_id: Uuid,
+        message_id: Uuid,
+    ) -> Result<(), SdkError> {
+        let receipt = ReadReceipt {
+            message_id,
+            conversation_id,
+            read_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64,
+        };
+        
+        self.send_frame(&WsMessage::ReadReceipt(receipt))
+    }
+    
+    fn send_frame(&self, frame: &WsMessage) -> Result<(), SdkError> {
+        let json = serde_json::to_string(frame)
+            .map_err(|e| SdkError::SerializationError(e.to_string()))?;
+        
+        let sender = self.ws_sender.as_ref()
+            .ok_or_else(|| SdkError::InvalidState("WebSocket not connected".to_string()))?;
+        
+        sender.send(Message::Text(json))
+            .map_err(|e| SdkError::WebSocketError(e.to_string()))
+    }
+    
+    /// Sets this device's status. Use `PresenceStatus::Invisible` to appear
+    /// offline while connected; `Offline` is set by disconnecting. Passing
+    /// no custom status keeps the current one; `CustomStatus::default()`
+    /// clears it.
     pub async fn update_presence(
         &self,
-        status: &str,
-        custom_status: Option<&str>,
+        status: PresenceStatus,
+        custom_status: Option<CustomStatus>,
     ) -> Result<(), SdkError> {
         let presence = PresenceUpdate {
-            status: status.to_string(),
-            custom_status: custom_status.map(|s| s.to_string()),
+            status,
+            custom_status,
             last_active: SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .unwrap()
@@ -326,6 +494,20 @@ impl MessagingClient {
             .map_err(|e| SdkError::WebSocketError(e.to_string()))
     }
     
+    /// Tells the server the user is actively using the app. Without it an
+    /// online user becomes idle after a few minutes; apps should call this
+    /// on input, at most every minute or so.
+    pub async fn report_activity(&self) -> Result<(), SdkError> {
+        let json = serde_json::to_string(&WsMessage::Activity)
+            .map_err(|e| SdkError::SerializationError(e.to_string()))?;
+        
+        let sender = self.ws_sender.as_ref()
+            .ok_or_else(|| SdkError::InvalidState("WebSocket not connected".to_string()))?;
+        
+        sender.send(Message::Text(json))
+            .map_err(|e| SdkError::WebSocketError(e.to_string()))
+    }
+    
     pub async fn send_typing_indicator(
         &self,
         conversation_id: Uuid,
@@ -352,6 +534,9 @@ impl MessagingClient {
         name: &str,
         user_ids: &[Uuid],
     ) -> Result<Uuid, SdkError> {
+        let token = self.auth_token.as_ref()
+            .ok_or_else(|| SdkError::InvalidState("Not authenticated".to_string()))?;
+        
         let request = CreateGroupRequest {
             name: name.to_string(),
             user_ids: user_ids.to_vec(),
@@ -360,7 +545,7 @@ impl MessagingClient {
         
         let response = self.http_client
             .post(&format!("{}/groups", self.base_url))
-            .bearer_auth(self.auth_token.as_ref().unwrap())
+            .bearer_auth(token)
             .json(&request)
             .send()
             .await
@@ -376,6 +561,313 @@ impl MessagingClient {
         Ok(group.id)
     }
     
+    /// Sets how long new messages in the conversation live. Members see a
+    /// system message recording the change.
+    pub async fn set_disappearing_timer(
+        &self,
+        conversation_id: Uuid,
+        timer: DisappearingTimer,
+    ) -> Result<(), SdkError> {
+        let token = self.auth_token.as_ref()
+            .ok_or_else(|| SdkError::InvalidState("Not authenticated".to_string()))?;
+        
+        let response = self.http_client
+            .put(&format!("{}/conversations/{}/disappearing", self.base_url, conversation_id))
+            .bearer_auth(token)
+            .json(&SetDisappearingTimerRequest { timer })
+            .send()
+            .await
+            .map_err(|e| SdkError::NetworkError(e.to_string()))?;
+        
+        if !response.status().is_success() {
+            return Err(SdkError::NetworkError(format!("Failed to set disappearing timer: {}", response.status())));
+        }
+        
+        Ok(())
+    }
+    
+    pub async fn fetch_history(
+        &self,
+        conversation_id: Uuid,
+        cursor: Option<&str>,
+        direction: HistoryDirection,
+        limit: usize,
+    ) -> Result<HistoryPage, SdkError> {
+        let token = self.auth_token.as_ref()
+            .ok_or_else(|| SdkError::InvalidState("Not authenticated".to_string()))?;
+        
+        let mut query = vec![
+            ("direction", match direction {
+                HistoryDirection::Before => "before".to_string(),
+                HistoryDirection::After => "after".to_string(),
+            }),
+            ("limit", limit.to_string()),
+        ];
+        if let Some(cursor) = cursor {
+            query.push(("cursor", cursor.to_string()));
+        }
+        
+        let response = self.http_client
+            .get(&format!("{}/conversations/{}/messages", self.base_url, conversation_id))
+            .bearer_auth(token)
+            .query(&query)
+            .send()
+            .await
+            .map_err(|e| SdkError::NetworkError(e.to_string()))?;
+        
+        if response.status() != StatusCode::OK {
+            return Err(SdkError::NetworkError(format!("Failed to fetch history: {}", response.status())));
+        }
+        
+        response.json().await
+            .map_err(|e| SdkError::SerializationError(e.to_string()))
+    }
+    
+    /// Direct messages and groups, most recently active first.
+    pub async fn fetch_inbox(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+        archived: bool,
+    ) -> Result<InboxPage, SdkError> {
+        let token = self.auth_token.as_ref()
+            .ok_or_else(|| SdkError::InvalidState("Not authenticated".to_string()))?;
+        
+        let mut query = vec![
+            ("limit", limit.to_string()),
+            ("archived", archived.to_string()),
+        ];
+        if let Some(cursor) = cursor {
+            query.push(("cursor", cursor.to_string()));
+        }
+        
+        let response = self.http_client
+            .get(&format!("{}/inbox", self.base_url))
+            .bearer_auth(token)
+            .query(&query)
+            .send()
+            .await
+            .map_err(|e| SdkError::NetworkError(e.to_string()))?;
+        
+        if response.status() != StatusCode::OK {
+            return Err(SdkError::NetworkError(format!("Failed to fetch inbox: {}", response.status())));
+        }
+        
+        response.json().await
+            .map_err(|e| SdkError::SerializationError(e.to_string()))
+    }
+    
+    pub async fn mute_conversation(
+        &self,
+        conversation_id: Uuid,
+        until: chrono::DateTime<chrono::Utc>,
+    ) -> Result<(), SdkError> {
+        let request = self.http_client
+            .put(&format!("{}/inbox/{}/mute", self.base_url, conversation_id))
+            .json(&MuteRequest { until });
+        
+        self.send_setting(request, "mute conversation").await
+    }
+    
+    pub async fn unmute_conversation(&self, conversation_id: Uuid) -> Result<(), SdkError> {
+        let request = self.http_client
+            .delete(&format!("{}/inbox/{}/mute", self.base_url, conversation_id));
+        
+        self.send_setting(request, "unmute conversation").await
+    }
+    
+    pub async fn archive_conversation(&self, conversation_id: Uuid) -> Result<(), SdkError> {
+        let request = self.http_client
+            .put(&format!("{}/inbox/{}/archive", self.base_url, conversation_id));
+        
+        self.send_setting(request, "archive conversation").await
+    }
+    
+    pub async fn unarchive_conversation(&self, conversation_id: Uuid) -> Result<(), SdkError> {
+        let request = self.http_client
+            .delete(&format!("{}/inbox/{}/archive", self.base_url, conversation_id));
+        
+        self.send_setting(request, "unarchive conversation").await
+    }
+    
+    pub async fn pin_conversation(&self, conversation_id: Uuid) -> Result<(), SdkError> {
+        let request = self.http_client
+            .put(&format!("{}/inbox/{}/pin", self.base_url, conversation_id));
+        
+        self.send_setting(request, "pin conversation").await
+    }
+    
+    pub async fn unpin_conversation(&self, conversation_id: Uuid) -> Result<(), SdkError> {
+        let request = self.http_client
+            .delete(&format!("{}/inbox/{}/pin", self.base_url, conversation_id));
+        
+        self.send_setting(request, "unpin conversation").await
+    }
+    
+    pub async fn list_friends(&self) -> Result<Vec<RelatedUser>, SdkError> {
+        self.fetch_related("friends").await
+    }
+    
+    /// Pending requests to and from this user.
+    pub async fn list_friend_requests(&self) -> Result<FriendRequestList, SdkError> {
+        let token = self.auth_token.as_ref()
+            .ok_or_else(|| SdkError::InvalidState("Not authenticated".to_string()))?;
+        
+        let response = self.http_client
+            .get(&format!("{}/relationships/friend-requests", self.base_url))
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(|e| SdkError::NetworkError(e.to_string()))?;
+        
+        if response.status() != StatusCode::OK {
+            return Err(SdkError::NetworkError(format!("Failed to list friend requests: {}", response.status())));
+        }
+        
+        response.json().await
+            .map_err(|e| SdkError::SerializationError(e.to_string()))
+    }
+    
+    /// Asks `user_id` to be friends. If they already asked, the returned
+    /// request is theirs, now accepted.
+    pub async fn send_friend_request(&self, user_id: Uuid) -> Result<FriendRequest, SdkError> {
+        let request = self.http_client
+            .post(&format!("{}/relationships/friend-requests", self.base_url))
+            .json(&SendFriendRequest { user_id });
+        
+        self.send_friend_request_action(request, "send friend request").await
+    }
+    
+    pub async fn accept_friend_request(&self, request_id: Uuid) -> Result<FriendRequest, SdkError> {
+        let request = self.http_client
+            .post(&format!("{}/relationships/friend-requests/{}/accept", self.base_url, request_id));
+        
+        self.send_friend_request_action(request, "accept friend request").await
+    }
+    
+    pub async fn decline_friend_request(&self, request_id: Uuid) -> Result<(), SdkError> {
+        let request = self.http_client
+            .post(&format!("{}/relationships/friend-requests/{}/decline", self.base_url, request_id));
+        
+        self.send_setting(request, "decline friend request").await
+    }
+    
+    pub async fn cancel_friend_request(&self, request_id: Uuid) -> Result<(), SdkError> {
+        let request = self.http_client
+            .delete(&format!("{}/relationships/friend-requests/{}", self.base_url, request_id));
+        
+        self.send_setting(request, "cancel friend request").await
+    }
+    
+    pub async fn remove_friend(&self, user_id: Uuid) -> Result<(), SdkError> {
+        let request = self.http_client
+            .delete(&format!("{}/relationships/friends/{}", self.base_url, user_id));
+        
+        self.send_setting(request, "remove friend").await
+    }
+    
+    pub async fn list_contacts(&self) -> Result<Vec<RelatedUser>, SdkError> {
+        self.fetch_related("contacts").await
+    }
+    
+    pub async fn add_contact(&self, user_id: Uuid) -> Result<(), SdkError> {
+        let request = self.http_client
+            .put(&format!("{}/relationships/contacts/{}", self.base_url, user_id));
+        
+        self.send_setting(request, "add contact").await
+    }
+    
+    pub async fn remove_contact(&self, user_id: Uuid) -> Result<(), SdkError> {
+        let request = self.http_client
+            .delete(&format!("{}/relationships/contacts/{}", self.base_url, user_id));
+        
+        self.send_setting(request, "remove contact").await
+    }
+    
+    pub async fn list_blocked_users(&self) -> Result<Vec<RelatedUser>, SdkError> {
+        self.fetch_related("blocks").await
+    }
+    
+    /// Blocks the user, which also ends any friendship with them. Neither
+    /// side can message, add or see the presence of the other.
+    pub async fn block_user(&self, user_id: Uuid) -> Result<(), SdkError> {
+        let request = self.http_client
+            .put(&format!("{}/relationships/blocks/{}", self.base_url, user_id));
+        
+        self.send_setting(request, "block user").await
+    }
+    
+    pub async fn unblock_user(&self, user_id: Uuid) -> Result<(), SdkError> {
+        let request = self.http_client
+            .delete(&format!("{}/relationships/blocks/{}", self.base_url, user_id));
+        
+        self.send_setting(request, "unblock user").await
+    }
+    
+    async fn fetch_related(&self, kind: &str) -> Result<Vec<RelatedUser>, SdkError> {
+        let token = self.auth_token.as_ref()
+            .ok_or_else(|| SdkError::InvalidState("Not authenticated".to_string()))?;
+        
+        let response = self.http_client
+            .get(&format!("{}/relationships/{}", self.base_url, kind))
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(|e| SdkError::NetworkError(e.to_string()))?;
+        
+        if response.status() != StatusCode::OK {
+            return Err(SdkError::NetworkError(format!("Failed to list {}: {}", kind, response.status())));
+        }
+        
+        let list: RelatedUserList = response.json().await
+            .map_err(|e| SdkError::SerializationError(e.to_string()))?;
+        
+        Ok(list.users)
+    }
+    
+    async fn send_friend_request_action(
+        &self,
+        request: reqwest::RequestBuilder,
+        action: &str,
+    ) -> Result<FriendRequest, SdkError> {
+        let token = self.auth_token.as_ref()
+            .ok_or_else(|| SdkError::InvalidState("Not authenticated".to_string()))?;
+        
+        let response = request
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(|e| SdkError::NetworkError(e.to_string()))?;
+        
+        if !response.status().is_success() {
+            return Err(SdkError::NetworkError(format!("Failed to {}: {}", action, response.status())));
+        }
+        
+        response.json().await
+            .map_err(|e| SdkError::SerializationError(e.to_string()))
+    }
+    
+    async fn send_setting(
+        &self,
+        request: reqwest::RequestBuilder,
+        action: &str,
+    ) -> Result<(), SdkError> {
+        let token = self.auth_token.as_ref()
+            .ok_or_else(|| SdkError::InvalidState("Not authenticated".to_string()))?;
+        
+        let response = request
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(|e| SdkError::NetworkError(e.to_string()))?;
+        
+        if !response.status().is_success() {
+            return Err(SdkError::NetworkError(format!("Failed to {}: {}", action, response.status())));
+        }
+        
+        Ok(())
+    }
+    
     pub fn add_message_handler<H: MessageHandler + Send + Sync + 'static>(
         &self,
         handler: H,
@@ -473,14 +965,32 @@ impl DoubleRatchetSession {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
 pub enum WsMessage {
     Heartbeat,
+    Activity,
     Message(ClientMessage),
+    MessageAck(MessageAck),
     Presence(PresenceUpdate),
     Typing(TypingIndicator),
     ReadReceipt(ReadReceipt),
+    EditMessage(EditMessage),
+    DeleteMessage(DeleteMessage),
+    React(ReactionRequest),
+    Pin(PinRequest),
+    MessageEdited(MessageEdited),
+    MessageDeleted(MessageDeleted),
+    ReactionsUpdated(ReactionsUpdated),
+    PinUpdated(PinUpdated),
+    ThreadUpdated(ThreadUpdated),
+    MessageRead(MessageRead),
+    ReadMarkerUpdated(ReadMarkerUpdated),
+    ConversationUpdated(ConversationUpdated),
+    MessagesExpired(MessagesExpired),
 }
 
+/// `message_type` and `expires_at` are only set on delivered messages. A
+/// `System` message's content is an unencrypted JSON `SystemEvent`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientMessage {
     pub conversation_id: Uuid,
@@ -489,12 +999,203 @@ pub struct ClientMessage {
     pub nonce: Vec<u8>,
     pub reply_to: Option<Uuid>,
     pub timestamp: i64,
+    #[serde(default, skip_serializing_if = "MessageMentions::is_empty")]
+    pub mentions: MessageMentions,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_type: Option<MessageType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    /// Server signature over a system message; see `verify_system_message`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<Vec<u8>>,
+}
+
+/// Who a message mentions. Sent unencrypted so the server can notify them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MessageMentions {
+    #[serde(default)]
+    pub user_ids: Vec<Uuid>,
+    #[serde(default)]
+    pub everyone: bool,
+}
+
+impl MessageMentions {
+    pub fn is_empty(&self) -> bool {
+        !self.everyone && self.user_ids.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceStatus {
+    Online,
+    Idle,
+    Away,
+    Dnd,
+    Invisible,
+    Offline,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceActivity {
+    InACall,
+    InAMeeting,
+    Focusing,
+    Commuting,
+    OnVacation,
+}
+
+/// A status the user wrote themselves, with at least one of text, emoji or
+/// activity. The server clears it once `clear_after` (unix seconds) passes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CustomStatus {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emoji: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub activity: Option<PresenceActivity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clear_after: Option<i64>,
+}
+
+impl CustomStatus {
+    pub fn text(text: &str) -> Self {
+        Self {
+            text: Some(text.to_string()),
+            ..Self::default()
+        }
+    }
+    
+    pub fn with_emoji(mut self, emoji: &str) -> Self {
+        self.emoji = Some(emoji.to_string());
+        self
+    }
+    
+    pub fn with_activity(mut self, activity: PresenceActivity) -> Self {
+        self.activity = Some(activity);
+        self
+    }
+    
+    /// Clears the status after `duration` from now.
+    pub fn clear_after(mut self, duration: Duration) -> Self {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        self.clear_after = Some((now + duration).as_secs() as i64);
+        self
+    }
+    
+    /// Statuses cached locally should be hidden once this is true, in case
+    /// the update clearing them was missed.
+    pub fn is_expired(&self) -> bool {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        self.clear_after.map_or(false, |clear_after| clear_after <= now)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MessageType {
+    Text,
+    Image,
+    Video,
+    File,
+    System,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum SystemEvent {
+    ConversationCreated {
+        created_by: Uuid,
+        name: Option<String>,
+    },
+    MembersAdded {
+        added_by: Uuid,
+        user_ids: Vec<Uuid>,
+    },
+    MemberRemoved {
+        removed_by: Uuid,
+        user_id: Uuid,
+    },
+    RoleChanged {
+        changed_by: Uuid,
+        user_id: Uuid,
+        role: GroupRole,
+    },
+    PermissionsChanged {
+        changed_by: Uuid,
+        user_id: Uuid,
+        granted: Vec<Permission>,
+        denied: Vec<Permission>,
+    },
+    InfoUpdated {
+        changed_by: Uuid,
+        name: Option<String>,
+        avatar_url: Option<String>,
+    },
+    SettingsUpdated {
+        changed_by: Uuid,
+        settings: ConversationSettings,
+    },
+    DisappearingTimerChanged {
+        changed_by: Uuid,
+        timer: DisappearingTimer,
+    },
+    KeysRotated {
+        triggered_by: Uuid,
+    },
+}
+
+/// Checks a system message against the server's key from
+/// `GET /system-messages/signing-key`. Anything that fails must not be shown
+/// as a system message.
+pub fn verify_system_message(
+    server_key: &VerifyingKey,
+    message: &ClientMessage,
+) -> Result<SystemEvent, SdkError> {
+    let signature = message.signature.as_deref()
+        .and_then(|bytes| Signature::from_slice(bytes).ok())
+        .ok_or_else(|| SdkError::EncryptionError("Missing system message signature".to_string()))?;
+    
+    let mut payload = Vec::with_capacity(32 + message.content.len());
+    payload.extend_from_slice(message.conversation_id.as_bytes());
+    payload.extend_from_slice(message.message_id.as_bytes());
+    payload.extend_from_slice(&message.content);
+    
+    server_key.verify_strict(&payload, &signature)
+        .map_err(|_| SdkError::EncryptionError("Invalid system message signature".to_string()))?;
+    
+    serde_json::from_slice(&message.content)
+        .map_err(|e| SdkError::SerializationError(e.to_string()))
+}
+
+/// Messages whose disappearing timer ran out. Delete any local copies.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessagesExpired {
+    pub conversation_id: Uuid,
+    pub message_ids: Vec<Uuid>,
+}
+
+/// Sent to the sender's devices once a message is stored. `message_id` is the
+/// server-assigned id that edits, reactions and replies must reference.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageAck {
+    pub conversation_id: Uuid,
+    pub client_message_id: Uuid,
+    pub message_id: Uuid,
+    pub timestamp: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PresenceUpdate {
-    pub status: String,
-    pub custom_status: Option<String>,
+    pub status: PresenceStatus,
+    // None once the status was cleared, including when it expired
+    pub custom_status: Option<CustomStatus>,
     pub last_active: i64,
 }
 
@@ -511,6 +1212,201 @@ pub struct ReadReceipt {
     pub read_at: i64,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DeleteScope {
+    ForMe,
+    ForEveryone,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditMessage {
+    pub conversation_id: Uuid,
+    pub message_id: Uuid,
+    pub content: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteMessage {
+    pub conversation_id: Uuid,
+    pub message_id: Uuid,
+    pub scope: DeleteScope,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageEdited {
+    pub conversation_id: Uuid,
+    pub message_id: Uuid,
+    pub editor_id: Uuid,
+    pub content: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub edited_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageDeleted {
+    pub conversation_id: Uuid,
+    pub message_id: Uuid,
+    pub deleted_by: Uuid,
+    pub scope: DeleteScope,
+    pub deleted_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactionRequest {
+    pub conversation_id: Uuid,
+    pub message_id: Uuid,
+    pub emoji: String,
+    pub add: bool,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinRequest {
+    pub conversation_id: Uuid,
+    pub message_id: Uuid,
+    pub pinned: bool,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactionsUpdated {
+    pub conversation_id: Uuid,
+    pub message_id: Uuid,
+    pub user_id: Uuid,
+    pub emoji: String,
+    pub added: bool,
+    pub counts: HashMap<String, i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinUpdated {
+    pub conversation_id: Uuid,
+    pub message_id: Uuid,
+    pub pinned_by: Uuid,
+    pub pinned: bool,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadUpdated {
+    pub conversation_id: Uuid,
+    pub root_id: Uuid,
+    pub reply_id: Uuid,
+    pub reply_count: i64,
+}
+
+/// A recipient read one of our messages.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageRead {
+    pub conversation_id: Uuid,
+    pub message_id: Uuid,
+    pub reader_id: Uuid,
+    pub read_at: i64,
+}
+
+/// Our read position moved, usually because another of our devices read
+/// the conversation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadMarkerUpdated {
+    pub conversation_id: Uuid,
+    pub last_read_message_id: Uuid,
+    pub read_at: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GroupRole {
+    Owner,
+    Admin,
+    Member,
+    Guest,
+}
+
+/// Membership or metadata change in a conversation we belong to (or were
+/// just removed from).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationUpdated {
+    pub conversation_id: Uuid,
+    pub actor_id: Uuid,
+    pub change: MembershipChange,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum MembershipChange {
+    Created {
+        name: Option<String>,
+        members: Vec<Uuid>,
+    },
+    MembersAdded {
+        user_ids: Vec<Uuid>,
+    },
+    MemberRemoved {
+        user_id: Uuid,
+    },
+    RoleChanged {
+        user_id: Uuid,
+        role: GroupRole,
+    },
+    InfoUpdated {
+        name: Option<String>,
+        avatar_url: Option<String>,
+    },
+    JoinRequested {
+        request_id: Uuid,
+        user_id: Uuid,
+    },
+    JoinRequestDenied {
+        request_id: Uuid,
+    },
+    PermissionsChanged {
+        user_id: Uuid,
+        granted: Vec<Permission>,
+        denied: Vec<Permission>,
+    },
+    SettingsUpdated {
+        settings: ConversationSettings,
+    },
+    DisappearingTimerChanged {
+        timer: DisappearingTimer,
+    },
+    KeysRotated {
+        member_count: u32,
+    },
+    // The conversation was deleted by moderation; drop it and its history
+    Deleted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "seconds")]
+pub enum DisappearingTimer {
+    Off,
+    OneHour,
+    OneDay,
+    SevenDays,
+    Custom(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Permission {
+    SendMessages,
+    DeleteMessages,
+    AddMembers,
+    RemoveMembers,
+    ChangeGroupInfo,
+    PinMessages,
+    MentionEveryone,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationSettings {
+    pub only_admins_can_send: bool,
+    pub slow_mode_seconds: u32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RegistrationRequest {
     pub username: String,
@@ -548,6 +1444,108 @@ pub struct UserResponse {
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistoryDirection {
+    Before,
+    After,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryMessage {
+    pub message_id: Uuid,
+    pub sender_id: Uuid,
+    pub message_type: String,
+    pub content: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub reply_to: Option<Uuid>,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub edited: bool,
+    pub deleted: bool,
+    pub encryption_version: i32,
+    #[serde(default)]
+    pub signature: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryPage {
+    pub messages: Vec<HistoryMessage>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessagePreview {
+    pub message_id: Uuid,
+    pub sender_id: Uuid,
+    pub message_type: String,
+    pub content: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub deleted: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InboxEntry {
+    pub conversation_id: Uuid,
+    pub conversation_type: String,
+    pub name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub last_message_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_message: Option<MessagePreview>,
+    pub unread_count: i64,
+    pub mention_count: i64,
+    pub muted_until: Option<chrono::DateTime<chrono::Utc>>,
+    pub pinned: bool,
+    pub archived: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InboxPage {
+    pub conversations: Vec<InboxEntry>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SendFriendRequest {
+    pub user_id: Uuid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FriendRequestStatus {
+    Pending,
+    Accepted,
+    Declined,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FriendRequest {
+    pub id: Uuid,
+    pub sender_id: Uuid,
+    pub recipient_id: Uuid,
+    pub status: FriendRequestStatus,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub responded_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FriendRequestList {
+    pub incoming: Vec<FriendRequest>,
+    pub outgoing: Vec<FriendRequest>,
+}
+
+/// A friend, contact or blocked user, and when they became one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelatedUser {
+    pub user_id: Uuid,
+    pub since: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RelatedUserList {
+    users: Vec<RelatedUser>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateGroupRequest {
     pub name: String,
@@ -555,6 +1553,16 @@ pub struct CreateGroupRequest {
     pub is_encrypted: bool,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MuteRequest {
+    pub until: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetDisappearingTimerRequest {
+    pub timer: DisappearingTimer,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GroupResponse {
     pub id: Uuid,
@@ -567,6 +1575,16 @@ pub enum IncomingMessage {
     ChatMessage(ClientMessage),
     TypingIndicator(TypingIndicator),
     PresenceUpdate(PresenceUpdate),
+    MessageAck(MessageAck),
+    MessageEdited(MessageEdited),
+    MessageDeleted(MessageDeleted),
+    ReactionsUpdated(ReactionsUpdated),
+    PinUpdated(PinUpdated),
+    ThreadUpdated(ThreadUpdated),
+    MessageRead(MessageRead),
+    ReadMarkerUpdated(ReadMarkerUpdated),
+    ConversationUpdated(ConversationUpdated),
+    MessagesExpired(MessagesExpired),
 }
 
 // Example usage
@@ -595,7 +1613,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let _handler_tx = client.add_message_handler(MyHandler);
     
     // Update presence
-    client.update_presence("online", Some("Available")).await?;
+    let custom_status = CustomStatus::text("Available")
+        .with_emoji("👋")
+        .clear_after(Duration::from_secs(3600));
+    client.update_presence(PresenceStatus::Online, Some(custom_status)).await?;
     
     // Send a message
     let conversation_id = Uuid::new_v4(); // Would be real conversation ID
@@ -0,0 +1,648 @@
+use axum::{
+    extract::{Json, Path, State},
+    http::{HeaderMap, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use redis::{AsyncCommands, RedisResult};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+use validator::Validate;
+
+use shared::errors::AppError;
+use shared::models::{ConversationType, GroupRole, Permission};
+use shared::types::{MembershipChange, MembershipEvent};
+
+use crate::{
+    authenticate, build_response, get_member_permissions, get_member_role, insert_member,
+    is_blocked_between, list_admin_ids, list_member_ids, load_conversation, publish_membership_event,
+    require_not_banned, require_permission, AppState, ConversationRecord, ConversationResponse,
+    MAX_GROUP_MEMBERS,
+};
+
+const INVITE_CODE_LENGTH: usize = 22;
+const JOIN_RATE_LIMIT: i64 = 30; // Join attempts per link per window
+const JOIN_RATE_WINDOW_SECONDS: usize = 60;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateInviteRequest {
+    #[validate(range(min = 60, max = 2592000))] // One minute to 30 days
+    pub expires_in_seconds: Option<i64>,
+    #[validate(range(min = 1, max = 1000))]
+    pub max_uses: Option<i32>,
+    #[serde(default)]
+    pub requires_approval: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InviteResponse {
+    pub id: Uuid,
+    pub code: String,
+    pub conversation_id: Uuid,
+    pub created_by: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<i32>,
+    pub use_count: i32,
+    pub requires_approval: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JoinRequestResponse {
+    pub id: Uuid,
+    pub conversation_id: Uuid,
+    pub user_id: Uuid,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JoinResponse {
+    Joined { conversation: ConversationResponse },
+    Pending { request: JoinRequestResponse },
+}
+
+struct InviteRecord {
+    id: Uuid,
+    group_id: Uuid,
+    created_by: Uuid,
+    expires_at: Option<DateTime<Utc>>,
+    max_uses: Option<i32>,
+    use_count: i32,
+    requires_approval: bool,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl InviteRecord {
+    fn is_usable(&self) -> bool {
+        self.revoked_at.is_none()
+            && self.expires_at.map_or(true, |expires_at| expires_at > Utc::now())
+            && self.max_uses.map_or(true, |max_uses| self.use_count < max_uses)
+    }
+}
+
+pub async fn create_invite(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+    Json(payload): Json<CreateInviteRequest>,
+) -> Result<(StatusCode, Json<InviteResponse>), AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    payload.validate()?;
+    
+    let conversation = load_conversation(&state.db_pool, conversation_id).await?;
+    require_group(&conversation)?;
+    
+    // A link adds people on the creator's behalf, so it needs the same right
+    require_permission(&state.db_pool, conversation_id, user_id, Permission::AddMembers).await?;
+    
+    let code: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(INVITE_CODE_LENGTH)
+        .map(char::from)
+        .collect();
+    
+    let expires_at = payload.expires_in_seconds
+        .map(|seconds| Utc::now() + chrono::Duration::seconds(seconds));
+    
+    let invite = sqlx::query!(
+        r#"
+        INSERT INTO group_invites
+        (id, group_id, code, created_by, expires_at, max_uses, requires_approval)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, created_at
+        "#,
+        Uuid::new_v4(),
+        conversation_id,
+        code,
+        user_id,
+        expires_at,
+        payload.max_uses,
+        payload.requires_approval
+    )
+    .fetch_one(&state.db_pool)
+    .await?;
+    
+    info!("Invite {} created for {} by user {}", invite.id, conversation_id, user_id);
+    
+    Ok((StatusCode::CREATED, Json(InviteResponse {
+        id: invite.id,
+        code,
+        conversation_id,
+        created_by: user_id,
+        created_at: invite.created_at,
+        expires_at,
+        max_uses: payload.max_uses,
+        use_count: 0,
+        requires_approval: payload.requires_approval,
+    })))
+}
+
+pub async fn list_invites(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+) -> Result<Json<Vec<InviteResponse>>, AppError> {
+    let user_id = authenticate(&headers)?;
+    
+    require_permission(&state.db_pool, conversation_id, user_id, Permission::AddMembers).await?;
+    
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, code, created_by, created_at, expires_at, max_uses, use_count, requires_approval
+        FROM group_invites
+        WHERE group_id = $1 AND revoked_at IS NULL
+        AND (expires_at IS NULL OR expires_at > NOW())
+        ORDER BY created_at DESC
+        "#,
+        conversation_id
+    )
+    .fetch_all(&state.db_pool)
+    .await?;
+    
+    let invites = rows.into_iter()
+        .map(|row| InviteResponse {
+            id: row.id,
+            code: row.code,
+            conversation_id,
+            created_by: row.created_by,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            max_uses: row.max_uses,
+            use_count: row.use_count,
+            requires_approval: row.requires_approval,
+        })
+        .collect();
+    
+    Ok(Json(invites))
+}
+
+pub async fn revoke_invite(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path((conversation_id, invite_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    let role = get_member_role(&state.db_pool, conversation_id, user_id)
+        .await?
+        .ok_or(AppError::Forbidden("Not a member of this conversation".to_string()))?;
+    
+    let invite = sqlx::query!(
+        "SELECT created_by FROM group_invites WHERE id = $1 AND group_id = $2",
+        invite_id,
+        conversation_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await?
+    .ok_or(AppError::NotFound("Invite not found".to_string()))?;
+    
+    // Creators can revoke their own links; admins can revoke anyone's
+    if invite.created_by != user_id && !role.is_admin() {
+        return Err(AppError::Forbidden("Cannot revoke this invite".to_string()));
+    }
+    
+    sqlx::query!(
+        "UPDATE group_invites SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL",
+        invite_id
+    )
+    .execute(&state.db_pool)
+    .await?;
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn join_via_invite(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(code): Path<String>,
+) -> Result<(StatusCode, Json<JoinResponse>), AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    check_join_rate_limit(&state.redis_client, &code).await?;
+    
+    let invite = load_invite(&state.db_pool, &code).await?;
+    
+    if !invite.is_usable() {
+        return Err(AppError::NotFound("Invite link is invalid or has expired".to_string()));
+    }
+    
+    // The link only carries the creator's authority while they still have it
+    let creator = get_member_permissions(&state.db_pool, invite.group_id, invite.created_by).await?;
+    if !creator.map_or(false, |creator| creator.has(&Permission::AddMembers)) {
+        return Err(AppError::NotFound("Invite link is invalid or has expired".to_string()));
+    }
+    
+    // Nor does it work between users who blocked each other; the joiner
+    // can't tell this apart from a dead link
+    if is_blocked_between(&state.db_pool, user_id, &[invite.created_by]).await? {
+        return Err(AppError::NotFound("Invite link is invalid or has expired".to_string()));
+    }
+    
+    let conversation = load_conversation(&state.db_pool, invite.group_id).await?;
+    
+    match membership_state(&state.db_pool, invite.group_id, user_id).await? {
+        Some(true) => {
+            return Err(AppError::Forbidden("You are banned from this group".to_string()));
+        }
+        Some(false) => {
+            // Already a member; nothing to do and no use consumed
+            let conversation = build_response(&state.db_pool, conversation).await?;
+            return Ok((StatusCode::OK, Json(JoinResponse::Joined { conversation })));
+        }
+        None => {}
+    }
+    
+    if invite.requires_approval {
+        return request_to_join(&state, &conversation, &invite, user_id).await;
+    }
+    
+    ensure_capacity(&state.db_pool, invite.group_id).await?;
+    
+    let mut tx = state.db_pool.begin().await?;
+    consume_invite_use(&mut tx, invite.id).await?;
+    insert_member(&mut tx, invite.group_id, user_id, &GroupRole::Member).await?;
+    tx.commit().await?;
+    
+    let recipients = list_member_ids(&state.db_pool, invite.group_id).await?;
+    
+    publish_membership_event(&state.kafka_producer, MembershipEvent {
+        conversation_id: invite.group_id,
+        conversation_type: conversation.conversation_type.clone(),
+        is_encrypted: conversation.is_encrypted,
+        actor_id: user_id,
+        change: MembershipChange::MembersAdded {
+            user_ids: vec![user_id],
+        },
+        recipients,
+        timestamp: Utc::now().timestamp(),
+    }).await?;
+    
+    info!("User {} joined {} via invite {}", user_id, invite.group_id, invite.id);
+    
+    let conversation = build_response(&state.db_pool, conversation).await?;
+    Ok((StatusCode::OK, Json(JoinResponse::Joined { conversation })))
+}
+
+async fn request_to_join(
+    state: &AppState,
+    conversation: &ConversationRecord,
+    invite: &InviteRecord,
+    user_id: Uuid,
+) -> Result<(StatusCode, Json<JoinResponse>), AppError> {
+    // Repeated clicks return the open request rather than using up the link
+    if let Some(existing) = sqlx::query!(
+        r#"
+        SELECT id, created_at FROM join_requests
+        WHERE group_id = $1 AND user_id = $2 AND status = 'pending'
+        "#,
+        conversation.id,
+        user_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await?
+    {
+        return Ok((StatusCode::ACCEPTED, Json(JoinResponse::Pending {
+            request: JoinRequestResponse {
+                id: existing.id,
+                conversation_id: conversation.id,
+                user_id,
+                status: "pending".to_string(),
+                created_at: existing.created_at,
+            },
+        })));
+    }
+    
+    let request_id = Uuid::new_v4();
+    
+    let mut tx = state.db_pool.begin().await?;
+    consume_invite_use(&mut tx, invite.id).await?;
+    
+    let created_at = sqlx::query!(
+        r#"
+        INSERT INTO join_requests (id, group_id, user_id, invite_id)
+        VALUES ($1, $2, $3, $4)
+        RETURNING created_at
+        "#,
+        request_id,
+        conversation.id,
+        user_id,
+        invite.id
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .created_at;
+    
+    tx.commit().await?;
+    
+    publish_membership_event(&state.kafka_producer, MembershipEvent {
+        conversation_id: conversation.id,
+        conversation_type: conversation.conversation_type.clone(),
+        is_encrypted: conversation.is_encrypted,
+        actor_id: user_id,
+        change: MembershipChange::JoinRequested { request_id, user_id },
+        recipients: list_admin_ids(&state.db_pool, conversation.id).await?,
+        timestamp: Utc::now().timestamp(),
+    }).await?;
+    
+    Ok((StatusCode::ACCEPTED, Json(JoinResponse::Pending {
+        request: JoinRequestResponse {
+            id: request_id,
+            conversation_id: conversation.id,
+            user_id,
+            status: "pending".to_string(),
+            created_at,
+        },
+    })))
+}
+
+pub async fn list_join_requests(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+) -> Result<Json<Vec<JoinRequestResponse>>, AppError> {
+    let user_id = authenticate(&headers)?;
+    
+    require_admin(&state.db_pool, conversation_id, user_id).await?;
+    
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, user_id, status, created_at FROM join_requests
+        WHERE group_id = $1 AND status = 'pending'
+        ORDER BY created_at
+        "#,
+        conversation_id
+    )
+    .fetch_all(&state.db_pool)
+    .await?;
+    
+    let requests = rows.into_iter()
+        .map(|row| JoinRequestResponse {
+            id: row.id,
+            conversation_id,
+            user_id: row.user_id,
+            status: row.status,
+            created_at: row.created_at,
+        })
+        .collect();
+    
+    Ok(Json(requests))
+}
+
+pub async fn approve_join_request(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path((conversation_id, request_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<JoinRequestResponse>, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    require_admin(&state.db_pool, conversation_id, user_id).await?;
+    
+    let conversation = load_conversation(&state.db_pool, conversation_id).await?;
+    let request = load_pending_request(&state.db_pool, conversation_id, request_id).await?;
+    
+    // The requester may have been banned while the request was waiting
+    if membership_state(&state.db_pool, conversation_id, request.user_id).await? == Some(true) {
+        return Err(AppError::Forbidden("User is banned from this group".to_string()));
+    }
+    
+    ensure_capacity(&state.db_pool, conversation_id).await?;
+    
+    let mut tx = state.db_pool.begin().await?;
+    
+    let decided = sqlx::query!(
+        r#"
+        UPDATE join_requests
+        SET status = 'approved', decided_by = $1, decided_at = NOW()
+        WHERE id = $2 AND status = 'pending'
+        "#,
+        user_id,
+        request_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    
+    if decided.rows_affected() == 0 {
+        return Err(AppError::Conflict("Join request was already decided".to_string()));
+    }
+    
+    insert_member(&mut tx, conversation_id, request.user_id, &GroupRole::Member).await?;
+    tx.commit().await?;
+    
+    let recipients = list_member_ids(&state.db_pool, conversation_id).await?;
+    
+    publish_membership_event(&state.kafka_producer, MembershipEvent {
+        conversation_id,
+        conversation_type: conversation.conversation_type,
+        is_encrypted: conversation.is_encrypted,
+        actor_id: user_id,
+        change: MembershipChange::MembersAdded {
+            user_ids: vec![request.user_id],
+        },
+        recipients,
+        timestamp: Utc::now().timestamp(),
+    }).await?;
+    
+    Ok(Json(JoinRequestResponse {
+        status: "approved".to_string(),
+        ..request
+    }))
+}
+
+pub async fn deny_join_request(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path((conversation_id, request_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<JoinRequestResponse>, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    require_admin(&state.db_pool, conversation_id, user_id).await?;
+    
+    let conversation = load_conversation(&state.db_pool, conversation_id).await?;
+    let request = load_pending_request(&state.db_pool, conversation_id, request_id).await?;
+    
+    let decided = sqlx::query!(
+        r#"
+        UPDATE join_requests
+        SET status = 'denied', decided_by = $1, decided_at = NOW()
+        WHERE id = $2 AND status = 'pending'
+        "#,
+        user_id,
+        request_id
+    )
+    .execute(&state.db_pool)
+    .await?;
+    
+    if decided.rows_affected() == 0 {
+        return Err(AppError::Conflict("Join request was already decided".to_string()));
+    }
+    
+    publish_membership_event(&state.kafka_producer, MembershipEvent {
+        conversation_id,
+        conversation_type: conversation.conversation_type,
+        is_encrypted: conversation.is_encrypted,
+        actor_id: user_id,
+        change: MembershipChange::JoinRequestDenied { request_id },
+        recipients: vec![request.user_id],
+        timestamp: Utc::now().timestamp(),
+    }).await?;
+    
+    Ok(Json(JoinRequestResponse {
+        status: "denied".to_string(),
+        ..request
+    }))
+}
+
+// Helper functions
+fn require_group(conversation: &ConversationRecord) -> Result<(), AppError> {
+    if conversation.conversation_type != ConversationType::Group {
+        return Err(AppError::ValidationError("Invite links are only available for groups".to_string()));
+    }
+    
+    Ok(())
+}
+
+async fn require_admin(db_pool: &PgPool, conversation_id: Uuid, user_id: Uuid) -> Result<(), AppError> {
+    let role = get_member_role(db_pool, conversation_id, user_id)
+        .await?
+        .ok_or(AppError::Forbidden("Not a member of this conversation".to_string()))?;
+    
+    if !role.is_admin() {
+        return Err(AppError::Forbidden("Only admins can manage join requests".to_string()));
+    }
+    
+    Ok(())
+}
+
+async fn load_invite(db_pool: &PgPool, code: &str) -> Result<InviteRecord, AppError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, group_id, created_by, expires_at, max_uses, use_count,
+               requires_approval, revoked_at
+        FROM group_invites WHERE code = $1
+        "#,
+        code
+    )
+    .fetch_optional(db_pool)
+    .await?
+    .ok_or(AppError::NotFound("Invite link is invalid or has expired".to_string()))?;
+    
+    Ok(InviteRecord {
+        id: row.id,
+        group_id: row.group_id,
+        created_by: row.created_by,
+        expires_at: row.expires_at,
+        max_uses: row.max_uses,
+        use_count: row.use_count,
+        requires_approval: row.requires_approval,
+        revoked_at: row.revoked_at,
+    })
+}
+
+async fn load_pending_request(
+    db_pool: &PgPool,
+    conversation_id: Uuid,
+    request_id: Uuid,
+) -> Result<JoinRequestResponse, AppError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, status, created_at FROM join_requests
+        WHERE id = $1 AND group_id = $2 AND status = 'pending'
+        "#,
+        request_id,
+        conversation_id
+    )
+    .fetch_optional(db_pool)
+    .await?
+    .ok_or(AppError::NotFound("Join request not found".to_string()))?;
+    
+    Ok(JoinRequestResponse {
+        id: request_id,
+        conversation_id,
+        user_id: row.user_id,
+        status: row.status,
+        created_at: row.created_at,
+    })
+}
+
+/// `Some(is_banned)` if the user has a membership row, `None` otherwise.
+async fn membership_state(
+    db_pool: &PgPool,
+    conversation_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<bool>, AppError> {
+    let row = sqlx::query!(
+        "SELECT is_banned FROM group_members WHERE group_id = $1 AND user_id = $2",
+        conversation_id,
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await?;
+    
+    Ok(row.map(|r| r.is_banned))
+}
+
+async fn ensure_capacity(db_pool: &PgPool, conversation_id: Uuid) -> Result<(), AppError> {
+    if list_member_ids(db_pool, conversation_id).await?.len() >= MAX_GROUP_MEMBERS {
+        return Err(AppError::ValidationError(format!(
+            "Groups are limited to {} members",
+            MAX_GROUP_MEMBERS
+        )));
+    }
+    
+    Ok(())
+}
+
+/// Counts one use of the link, re-checking its limits in the same statement so
+/// concurrent joins can't exceed `max_uses`.
+async fn consume_invite_use(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    invite_id: Uuid,
+) -> Result<(), AppError> {
+    let consumed = sqlx::query!(
+        r#"
+        UPDATE group_invites SET use_count = use_count + 1
+        WHERE id = $1 AND revoked_at IS NULL
+        AND (expires_at IS NULL OR expires_at > NOW())
+        AND (max_uses IS NULL OR use_count < max_uses)
+        "#,
+        invite_id
+    )
+    .execute(&mut **tx)
+    .await?;
+    
+    if consumed.rows_affected() == 0 {
+        return Err(AppError::NotFound("Invite link is invalid or has expired".to_string()));
+    }
+    
+    Ok(())
+}
+
+async fn check_join_rate_limit(redis_client: &redis::Client, code: &str) -> Result<(), AppError> {
+    let mut conn = redis_client.get_async_connection().await
+        .map_err(|e| AppError::ExternalServiceError(e.to_string()))?;
+    
+    let key = format!("invite_rate:{}", code);
+    let attempts: i64 = conn.incr(&key, 1).await
+        .map_err(|e| AppError::ExternalServiceError(e.to_string()))?;
+    
+    if attempts == 1 {
+        let _: RedisResult<()> = conn.expire(&key, JOIN_RATE_WINDOW_SECONDS).await;
+    }
+    
+    if attempts > JOIN_RATE_LIMIT {
+        return Err(AppError::Forbidden("Too many join attempts for this link, try again later".to_string()));
+    }
+    
+    Ok(())
+}
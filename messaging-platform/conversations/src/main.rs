@@ -0,0 +1,1402 @@
+use axum::{
+    extract::{Json, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::{delete, get, post, put},
+    Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use std::{sync::Arc, time::Duration};
+use tracing::{error, info};
+use uuid::Uuid;
+use validator::Validate;
+
+use shared::errors::AppError;
+use shared::models::{ConversationType, DisappearingTimer, GroupRole, Permission};
+use shared::permissions::{
+    parse_permissions, ConversationSettings, MemberPermissions, MAX_SLOW_MODE_SECONDS,
+};
+use shared::restrictions::{RestrictionCache, DEFAULT_CACHE_TTL};
+use shared::types::{MembershipChange, MembershipEvent};
+
+mod invites;
+
+const MAX_GROUP_MEMBERS: usize = 1000;
+
+struct AppState {
+    db_pool: PgPool,
+    redis_client: redis::Client,
+    kafka_producer: rdkafka::producer::FutureProducer,
+    restrictions: Arc<RestrictionCache>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateGroupRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    pub user_ids: Vec<Uuid>,
+    pub is_encrypted: bool,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateChannelRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    #[validate(url)]
+    pub avatar_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateDirectMessageRequest {
+    pub user_id: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddMembersRequest {
+    pub user_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeRoleRequest {
+    pub role: GroupRole,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdatePermissionsRequest {
+    pub granted: Vec<Permission>,
+    pub denied: Vec<Permission>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetDisappearingTimerRequest {
+    pub timer: DisappearingTimer,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateConversationRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: Option<String>,
+    #[validate(url)]
+    pub avatar_url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConversationResponse {
+    pub id: Uuid,
+    pub conversation_type: ConversationType,
+    pub name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub is_encrypted: bool,
+    pub created_at: DateTime<Utc>,
+    pub settings: ConversationSettings,
+    pub disappearing_timer: DisappearingTimer,
+    pub member_count: i64,
+    // For channels only admins are listed; subscribers are private
+    pub members: Vec<MemberResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberResponse {
+    pub user_id: Uuid,
+    pub role: GroupRole,
+    pub joined_at: DateTime<Utc>,
+    pub granted_permissions: Vec<Permission>,
+    pub denied_permissions: Vec<Permission>,
+}
+
+struct ConversationRecord {
+    id: Uuid,
+    conversation_type: ConversationType,
+    name: Option<String>,
+    avatar_url: Option<String>,
+    is_encrypted: bool,
+    created_at: DateTime<Utc>,
+    settings: ConversationSettings,
+    disappearing_timer: DisappearingTimer,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt::init();
+    
+    dotenv::dotenv().ok();
+    
+    let database_url = std::env::var("DATABASE_URL")
+        .expect("DATABASE_URL must be set");
+    let redis_url = std::env::var("REDIS_URL")
+        .expect("REDIS_URL must be set");
+    
+    let db_pool = PgPoolOptions::new()
+        .max_connections(20)
+        .connect(&database_url)
+        .await?;
+    
+    let redis_client = redis::Client::open(redis_url)?;
+    
+    let kafka_brokers = std::env::var("KAFKA_BROKERS")
+        .unwrap_or_else(|_| "localhost:9092".to_string());
+    
+    let kafka_producer: rdkafka::producer::FutureProducer = rdkafka::config::ClientConfig::new()
+        .set("bootstrap.servers", &kafka_brokers)
+        .set("message.timeout.ms", "5000")
+        .create()?;
+    
+    // Run migrations
+    shared::migrations::MIGRATOR.run(&db_pool).await?;
+    
+    let restrictions = Arc::new(RestrictionCache::new(db_pool.clone(), DEFAULT_CACHE_TTL));
+    let restrictions_clone = restrictions.clone();
+    tokio::spawn(async move {
+        if let Err(e) = restrictions_clone.run(kafka_brokers, "conversations").await {
+            error!("Restriction cache consumer error: {}", e);
+        }
+    });
+    
+    let state = Arc::new(AppState {
+        db_pool,
+        redis_client,
+        kafka_producer,
+        restrictions,
+    });
+    
+    let app = Router::new()
+        .route("/groups", post(create_group))
+        .route("/channels", post(create_channel))
+        .route("/channels/:conversation_id/subscription", post(subscribe_channel))
+        .route("/channels/:conversation_id/subscription", delete(unsubscribe_channel))
+        .route("/direct-messages", post(create_direct_message))
+        .route("/conversations/:conversation_id", get(get_conversation))
+        .route("/conversations/:conversation_id", put(update_conversation))
+        .route("/conversations/:conversation_id/members", post(add_members))
+        .route("/conversations/:conversation_id/members/:user_id", delete(remove_member))
+        .route("/conversations/:conversation_id/members/:user_id/role", put(change_role))
+        .route(
+            "/conversations/:conversation_id/members/:user_id/permissions",
+            put(update_member_permissions),
+        )
+        .route("/conversations/:conversation_id/settings", put(update_settings))
+        .route("/conversations/:conversation_id/disappearing", put(set_disappearing_timer))
+        .route("/conversations/:conversation_id/invites", post(invites::create_invite))
+        .route("/conversations/:conversation_id/invites", get(invites::list_invites))
+        .route("/conversations/:conversation_id/invites/:invite_id", delete(invites::revoke_invite))
+        .route("/conversations/:conversation_id/join-requests", get(invites::list_join_requests))
+        .route(
+            "/conversations/:conversation_id/join-requests/:request_id/approve",
+            post(invites::approve_join_request),
+        )
+        .route(
+            "/conversations/:conversation_id/join-requests/:request_id/deny",
+            post(invites::deny_join_request),
+        )
+        .route("/invites/:code/join", post(invites::join_via_invite))
+        .route("/health", get(health_check))
+        .with_state(state)
+        .layer(tower_http::trace::TraceLayer::new_for_http())
+        .layer(tower_http::cors::CorsLayer::permissive());
+    
+    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], 3003));
+    info!("Conversations service listening on {}", addr);
+    
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await?;
+    
+    Ok(())
+}
+
+async fn health_check() -> impl IntoResponse {
+    (StatusCode::OK, "Conversations service healthy")
+}
+
+async fn create_group(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateGroupRequest>,
+) -> Result<(StatusCode, Json<ConversationResponse>), AppError> {
+    let user_id = authenticate(&headers)?;
+    require_can_send(&state.restrictions, user_id).await?;
+    
+    payload.validate()?;
+    
+    let mut member_ids: Vec<Uuid> = payload.user_ids
+        .into_iter()
+        .filter(|id| *id != user_id)
+        .collect();
+    member_ids.sort();
+    member_ids.dedup();
+    
+    if member_ids.len() + 1 > MAX_GROUP_MEMBERS {
+        return Err(AppError::ValidationError(format!(
+            "Groups are limited to {} members",
+            MAX_GROUP_MEMBERS
+        )));
+    }
+    
+    verify_users_exist(&state.db_pool, &member_ids).await?;
+    
+    // Nobody can be put in a group by someone they blocked, or who blocked them
+    if is_blocked_between(&state.db_pool, user_id, &member_ids).await? {
+        return Err(AppError::Forbidden("One or more users cannot be added".to_string()));
+    }
+    
+    let conversation_id = Uuid::new_v4();
+    let mut tx = state.db_pool.begin().await?;
+    
+    let created_at = sqlx::query!(
+        r#"
+        INSERT INTO conversations (id, conversation_type, name, created_by, is_encrypted)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING created_at
+        "#,
+        conversation_id,
+        ConversationType::Group.as_str(),
+        payload.name,
+        user_id,
+        payload.is_encrypted
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .created_at;
+    
+    insert_member(&mut tx, conversation_id, user_id, &GroupRole::Owner).await?;
+    for member_id in &member_ids {
+        insert_member(&mut tx, conversation_id, *member_id, &GroupRole::Member).await?;
+    }
+    
+    tx.commit().await?;
+    
+    let mut members = vec![user_id];
+    members.extend(member_ids.iter().copied());
+    
+    publish_membership_event(&state.kafka_producer, MembershipEvent {
+        conversation_id,
+        conversation_type: ConversationType::Group,
+        is_encrypted: payload.is_encrypted,
+        actor_id: user_id,
+        change: MembershipChange::Created {
+            name: Some(payload.name.clone()),
+            members: members.clone(),
+        },
+        recipients: members,
+        timestamp: Utc::now().timestamp(),
+    }).await?;
+    
+    info!("Group {} created by user {}", conversation_id, user_id);
+    
+    let conversation = ConversationRecord {
+        id: conversation_id,
+        conversation_type: ConversationType::Group,
+        name: Some(payload.name),
+        avatar_url: None,
+        is_encrypted: payload.is_encrypted,
+        created_at,
+        settings: ConversationSettings::default(),
+        disappearing_timer: DisappearingTimer::Off,
+    };
+    
+    let response = build_response(&state.db_pool, conversation).await?;
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+async fn create_channel(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateChannelRequest>,
+) -> Result<(StatusCode, Json<ConversationResponse>), AppError> {
+    let user_id = authenticate(&headers)?;
+    require_can_send(&state.restrictions, user_id).await?;
+    
+    payload.validate()?;
+    
+    let conversation_id = Uuid::new_v4();
+    let mut tx = state.db_pool.begin().await?;
+    
+    // Channels are broadcast to an open audience, so they are not end-to-end encrypted
+    let created_at = sqlx::query!(
+        r#"
+        INSERT INTO conversations
+        (id, conversation_type, name, avatar_url, created_by, is_encrypted, only_admins_can_send)
+        VALUES ($1, $2, $3, $4, $5, false, true)
+        RETURNING created_at
+        "#,
+        conversation_id,
+        ConversationType::Channel.as_str(),
+        payload.name,
+        payload.avatar_url,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .created_at;
+    
+    insert_member(&mut tx, conversation_id, user_id, &GroupRole::Owner).await?;
+    
+    tx.commit().await?;
+    
+    publish_membership_event(&state.kafka_producer, MembershipEvent {
+        conversation_id,
+        conversation_type: ConversationType::Channel,
+        is_encrypted: false,
+        actor_id: user_id,
+        change: MembershipChange::Created {
+            name: Some(payload.name.clone()),
+            members: vec![user_id],
+        },
+        recipients: vec![user_id],
+        timestamp: Utc::now().timestamp(),
+    }).await?;
+    
+    info!("Channel {} created by user {}", conversation_id, user_id);
+    
+    let conversation = ConversationRecord {
+        id: conversation_id,
+        conversation_type: ConversationType::Channel,
+        name: Some(payload.name),
+        avatar_url: payload.avatar_url,
+        is_encrypted: false,
+        created_at,
+        settings: ConversationSettings {
+            only_admins_can_send: true,
+            slow_mode_seconds: 0,
+        },
+        disappearing_timer: DisappearingTimer::Off,
+    };
+    
+    let response = build_response(&state.db_pool, conversation).await?;
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+async fn create_direct_message(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateDirectMessageRequest>,
+) -> Result<(StatusCode, Json<ConversationResponse>), AppError> {
+    let user_id = authenticate(&headers)?;
+    require_can_send(&state.restrictions, user_id).await?;
+    
+    if payload.user_id == user_id {
+        return Err(AppError::ValidationError("Cannot start a direct message with yourself".to_string()));
+    }
+    
+    verify_users_exist(&state.db_pool, &[payload.user_id]).await?;
+    
+    if is_blocked_between(&state.db_pool, user_id, &[payload.user_id]).await? {
+        return Err(AppError::Forbidden("Cannot start a direct message with this user".to_string()));
+    }
+    
+    let (low, high) = if user_id < payload.user_id {
+        (user_id, payload.user_id)
+    } else {
+        (payload.user_id, user_id)
+    };
+    let dm_key = format!("{}:{}", low, high);
+    
+    // A pair only ever has one direct message; return it if it already exists
+    if let Some(existing) = sqlx::query!(
+        "SELECT id FROM conversations WHERE dm_key = $1",
+        dm_key
+    )
+    .fetch_optional(&state.db_pool)
+    .await?
+    {
+        let conversation = load_conversation(&state.db_pool, existing.id).await?;
+        let response = build_response(&state.db_pool, conversation).await?;
+        return Ok((StatusCode::OK, Json(response)));
+    }
+    
+    let conversation_id = Uuid::new_v4();
+    let mut tx = state.db_pool.begin().await?;
+    
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO conversations (id, conversation_type, created_by, is_encrypted, dm_key)
+        VALUES ($1, $2, $3, true, $4)
+        ON CONFLICT (dm_key) DO NOTHING
+        RETURNING created_at
+        "#,
+        conversation_id,
+        ConversationType::DirectMessage.as_str(),
+        user_id,
+        dm_key
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    
+    let created_at = match inserted {
+        Some(row) => row.created_at,
+        None => {
+            // Lost a race with the other participant creating the same DM
+            tx.rollback().await?;
+            let existing = sqlx::query!("SELECT id FROM conversations WHERE dm_key = $1", dm_key)
+                .fetch_one(&state.db_pool)
+                .await?;
+            let conversation = load_conversation(&state.db_pool, existing.id).await?;
+            let response = build_response(&state.db_pool, conversation).await?;
+            return Ok((StatusCode::OK, Json(response)));
+        }
+    };
+    
+    insert_member(&mut tx, conversation_id, user_id, &GroupRole::Member).await?;
+    insert_member(&mut tx, conversation_id, payload.user_id, &GroupRole::Member).await?;
+    
+    tx.commit().await?;
+    
+    let members = vec![user_id, payload.user_id];
+    
+    publish_membership_event(&state.kafka_producer, MembershipEvent {
+        conversation_id,
+        conversation_type: ConversationType::DirectMessage,
+        is_encrypted: true,
+        actor_id: user_id,
+        change: MembershipChange::Created {
+            name: None,
+            members: members.clone(),
+        },
+        recipients: members,
+        timestamp: Utc::now().timestamp(),
+    }).await?;
+    
+    let conversation = ConversationRecord {
+        id: conversation_id,
+        conversation_type: ConversationType::DirectMessage,
+        name: None,
+        avatar_url: None,
+        is_encrypted: true,
+        created_at,
+        settings: ConversationSettings::default(),
+        disappearing_timer: DisappearingTimer::Off,
+    };
+    
+    let response = build_response(&state.db_pool, conversation).await?;
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+async fn subscribe_channel(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+) -> Result<Json<ConversationResponse>, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    let conversation = load_conversation(&state.db_pool, conversation_id).await?;
+    if conversation.conversation_type != ConversationType::Channel {
+        return Err(AppError::NotFound("Channel not found".to_string()));
+    }
+    
+    let banned = sqlx::query!(
+        "SELECT 1 as banned FROM group_members WHERE group_id = $1 AND user_id = $2 AND is_banned = true",
+        conversation_id,
+        user_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await?;
+    
+    if banned.is_some() {
+        return Err(AppError::Forbidden("You are banned from this channel".to_string()));
+    }
+    
+    // Subscribers are guests: they can read but not post
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO group_members (group_id, user_id, role)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (group_id, user_id) DO NOTHING
+        "#,
+        conversation_id,
+        user_id,
+        GroupRole::Guest.as_str()
+    )
+    .execute(&state.db_pool)
+    .await?;
+    
+    if inserted.rows_affected() > 0 {
+        publish_membership_event(&state.kafka_producer, MembershipEvent {
+            conversation_id,
+            conversation_type: ConversationType::Channel,
+            is_encrypted: false,
+            actor_id: user_id,
+            change: MembershipChange::MembersAdded {
+                user_ids: vec![user_id],
+            },
+            recipients: vec![user_id],
+            timestamp: Utc::now().timestamp(),
+        }).await?;
+    }
+    
+    Ok(Json(build_response(&state.db_pool, conversation).await?))
+}
+
+async fn unsubscribe_channel(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    
+    let conversation = load_conversation(&state.db_pool, conversation_id).await?;
+    if conversation.conversation_type != ConversationType::Channel {
+        return Err(AppError::NotFound("Channel not found".to_string()));
+    }
+    
+    match get_member_role(&state.db_pool, conversation_id, user_id).await? {
+        None => return Ok(StatusCode::NO_CONTENT),
+        Some(GroupRole::Owner) => {
+            return Err(AppError::Forbidden("The owner cannot leave the conversation".to_string()));
+        }
+        Some(_) => {}
+    }
+    
+    sqlx::query!(
+        "DELETE FROM group_members WHERE group_id = $1 AND user_id = $2",
+        conversation_id,
+        user_id
+    )
+    .execute(&state.db_pool)
+    .await?;
+    
+    publish_membership_event(&state.kafka_producer, MembershipEvent {
+        conversation_id,
+        conversation_type: ConversationType::Channel,
+        is_encrypted: false,
+        actor_id: user_id,
+        change: MembershipChange::MemberRemoved { user_id },
+        recipients: vec![user_id],
+        timestamp: Utc::now().timestamp(),
+    }).await?;
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_conversation(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+) -> Result<Json<ConversationResponse>, AppError> {
+    let user_id = authenticate(&headers)?;
+    
+    let conversation = load_conversation(&state.db_pool, conversation_id).await?;
+    
+    // Channels are discoverable so people can subscribe; everything else is members-only
+    if conversation.conversation_type != ConversationType::Channel
+        && get_member_role(&state.db_pool, conversation_id, user_id).await?.is_none()
+    {
+        return Err(AppError::Forbidden("Not a member of this conversation".to_string()));
+    }
+    
+    Ok(Json(build_response(&state.db_pool, conversation).await?))
+}
+
+async fn update_conversation(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+    Json(payload): Json<UpdateConversationRequest>,
+) -> Result<Json<ConversationResponse>, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_can_send(&state.restrictions, user_id).await?;
+    
+    payload.validate()?;
+    
+    let conversation = load_conversation(&state.db_pool, conversation_id).await?;
+    reject_direct_message(&conversation)?;
+    
+    require_permission(&state.db_pool, conversation_id, user_id, Permission::ChangeGroupInfo).await?;
+    
+    let updated = sqlx::query!(
+        r#"
+        UPDATE conversations
+        SET name = COALESCE($1, name), avatar_url = COALESCE($2, avatar_url)
+        WHERE id = $3
+        RETURNING name, avatar_url
+        "#,
+        payload.name,
+        payload.avatar_url,
+        conversation_id
+    )
+    .fetch_one(&state.db_pool)
+    .await?;
+    
+    let recipients = list_member_ids(&state.db_pool, conversation_id).await?;
+    
+    publish_membership_event(&state.kafka_producer, MembershipEvent {
+        conversation_id,
+        conversation_type: conversation.conversation_type.clone(),
+        is_encrypted: conversation.is_encrypted,
+        actor_id: user_id,
+        change: MembershipChange::InfoUpdated {
+            name: updated.name.clone(),
+            avatar_url: updated.avatar_url.clone(),
+        },
+        recipients,
+        timestamp: Utc::now().timestamp(),
+    }).await?;
+    
+    let conversation = ConversationRecord {
+        name: updated.name,
+        avatar_url: updated.avatar_url,
+        ..conversation
+    };
+    
+    Ok(Json(build_response(&state.db_pool, conversation).await?))
+}
+
+async fn add_members(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+    Json(payload): Json<AddMembersRequest>,
+) -> Result<Json<ConversationResponse>, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_can_send(&state.restrictions, user_id).await?;
+    
+    let conversation = load_conversation(&state.db_pool, conversation_id).await?;
+    reject_direct_message(&conversation)?;
+    
+    if conversation.conversation_type == ConversationType::Channel {
+        return Err(AppError::ValidationError("Channel subscribers join by subscribing".to_string()));
+    }
+    
+    require_permission(&state.db_pool, conversation_id, user_id, Permission::AddMembers).await?;
+    
+    let existing = list_member_ids(&state.db_pool, conversation_id).await?;
+    
+    let mut new_members: Vec<Uuid> = payload.user_ids
+        .into_iter()
+        .filter(|id| !existing.contains(id))
+        .collect();
+    new_members.sort();
+    new_members.dedup();
+    
+    if new_members.is_empty() {
+        return Ok(Json(build_response(&state.db_pool, conversation).await?));
+    }
+    
+    if existing.len() + new_members.len() > MAX_GROUP_MEMBERS {
+        return Err(AppError::ValidationError(format!(
+            "Groups are limited to {} members",
+            MAX_GROUP_MEMBERS
+        )));
+    }
+    
+    verify_users_exist(&state.db_pool, &new_members).await?;
+    
+    // Nobody can be put in a group by someone they blocked, or who blocked them
+    if is_blocked_between(&state.db_pool, user_id, &new_members).await? {
+        return Err(AppError::Forbidden("One or more users cannot be added".to_string()));
+    }
+    
+    // Banned users keep their membership row, so inserting them would be a
+    // no-op while the event still announced them as members
+    let banned = sqlx::query!(
+        r#"
+        SELECT user_id FROM group_members
+        WHERE group_id = $1 AND user_id = ANY($2) AND is_banned = true
+        "#,
+        conversation_id,
+        &new_members
+    )
+    .fetch_optional(&state.db_pool)
+    .await?;
+    
+    if banned.is_some() {
+        return Err(AppError::Forbidden("One or more users cannot be added".to_string()));
+    }
+    
+    let mut tx = state.db_pool.begin().await?;
+    for member_id in &new_members {
+        insert_member(&mut tx, conversation_id, *member_id, &GroupRole::Member).await?;
+    }
+    tx.commit().await?;
+    
+    let mut recipients = existing;
+    recipients.extend(new_members.iter().copied());
+    
+    publish_membership_event(&state.kafka_producer, MembershipEvent {
+        conversation_id,
+        conversation_type: conversation.conversation_type.clone(),
+        is_encrypted: conversation.is_encrypted,
+        actor_id: user_id,
+        change: MembershipChange::MembersAdded {
+            user_ids: new_members.clone(),
+        },
+        recipients,
+        timestamp: Utc::now().timestamp(),
+    }).await?;
+    
+    info!("User {} added {} members to {}", user_id, new_members.len(), conversation_id);
+    
+    Ok(Json(build_response(&state.db_pool, conversation).await?))
+}
+
+async fn remove_member(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path((conversation_id, target_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    
+    let conversation = load_conversation(&state.db_pool, conversation_id).await?;
+    reject_direct_message(&conversation)?;
+    
+    let target_role = get_member_role(&state.db_pool, conversation_id, target_id)
+        .await?
+        .ok_or(AppError::NotFound("Member not found".to_string()))?;
+    
+    if target_id != user_id {
+        require_not_banned(&state.restrictions, user_id).await?;
+    }
+    
+    if target_id == user_id {
+        // Leaving; the owner has to stay so the group keeps someone with full rights
+        if target_role == GroupRole::Owner {
+            return Err(AppError::Forbidden("The owner cannot leave the conversation".to_string()));
+        }
+    } else {
+        let actor_role = require_permission(
+            &state.db_pool,
+            conversation_id,
+            user_id,
+            Permission::RemoveMembers,
+        ).await?;
+        
+        if !outranks(&actor_role, &target_role) {
+            return Err(AppError::Forbidden("Cannot remove a member with an equal or higher role".to_string()));
+        }
+    }
+    
+    sqlx::query!(
+        "DELETE FROM group_members WHERE group_id = $1 AND user_id = $2",
+        conversation_id,
+        target_id
+    )
+    .execute(&state.db_pool)
+    .await?;
+    
+    let mut recipients = event_recipients(&state.db_pool, &conversation).await?;
+    recipients.push(target_id);
+    
+    publish_membership_event(&state.kafka_producer, MembershipEvent {
+        conversation_id,
+        conversation_type: conversation.conversation_type,
+        is_encrypted: conversation.is_encrypted,
+        actor_id: user_id,
+        change: MembershipChange::MemberRemoved { user_id: target_id },
+        recipients,
+        timestamp: Utc::now().timestamp(),
+    }).await?;
+    
+    info!("User {} removed {} from {}", user_id, target_id, conversation_id);
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn change_role(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path((conversation_id, target_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<ChangeRoleRequest>,
+) -> Result<Json<MemberResponse>, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    let conversation = load_conversation(&state.db_pool, conversation_id).await?;
+    reject_direct_message(&conversation)?;
+    
+    if payload.role == GroupRole::Owner {
+        return Err(AppError::ValidationError("Ownership cannot be assigned".to_string()));
+    }
+    
+    let actor_role = get_member_role(&state.db_pool, conversation_id, user_id)
+        .await?
+        .ok_or(AppError::Forbidden("Not a member of this conversation".to_string()))?;
+    
+    if !actor_role.is_admin() {
+        return Err(AppError::Forbidden("Only admins can change roles".to_string()));
+    }
+    
+    let target_role = get_member_role(&state.db_pool, conversation_id, target_id)
+        .await?
+        .ok_or(AppError::NotFound("Member not found".to_string()))?;
+    
+    // Admins manage members and guests; only the owner appoints or demotes admins
+    if !outranks(&actor_role, &target_role) || !outranks(&actor_role, &payload.role) {
+        return Err(AppError::Forbidden("Cannot assign a role equal to or above your own".to_string()));
+    }
+    
+    let updated = sqlx::query!(
+        r#"
+        UPDATE group_members SET role = $1
+        WHERE group_id = $2 AND user_id = $3
+        RETURNING joined_at, granted_permissions, denied_permissions
+        "#,
+        payload.role.as_str(),
+        conversation_id,
+        target_id
+    )
+    .fetch_one(&state.db_pool)
+    .await?;
+    
+    let mut recipients = event_recipients(&state.db_pool, &conversation).await?;
+    if !recipients.contains(&target_id) {
+        recipients.push(target_id);
+    }
+    
+    publish_membership_event(&state.kafka_producer, MembershipEvent {
+        conversation_id,
+        conversation_type: conversation.conversation_type,
+        is_encrypted: conversation.is_encrypted,
+        actor_id: user_id,
+        change: MembershipChange::RoleChanged {
+            user_id: target_id,
+            role: payload.role.clone(),
+        },
+        recipients,
+        timestamp: Utc::now().timestamp(),
+    }).await?;
+    
+    Ok(Json(MemberResponse {
+        user_id: target_id,
+        role: payload.role,
+        joined_at: updated.joined_at,
+        granted_permissions: parse_permissions(&updated.granted_permissions),
+        denied_permissions: parse_permissions(&updated.denied_permissions),
+    }))
+}
+
+async fn update_member_permissions(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path((conversation_id, target_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<UpdatePermissionsRequest>,
+) -> Result<Json<MemberResponse>, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    let conversation = load_conversation(&state.db_pool, conversation_id).await?;
+    reject_direct_message(&conversation)?;
+    
+    let actor = get_member_permissions(&state.db_pool, conversation_id, user_id)
+        .await?
+        .ok_or(AppError::Forbidden("Not a member of this conversation".to_string()))?;
+    
+    if !actor.role.is_admin() {
+        return Err(AppError::Forbidden("Only admins can change member permissions".to_string()));
+    }
+    
+    let target = get_member_permissions(&state.db_pool, conversation_id, target_id)
+        .await?
+        .ok_or(AppError::NotFound("Member not found".to_string()))?;
+    
+    if !outranks(&actor.role, &target.role) {
+        return Err(AppError::Forbidden("Cannot change permissions of an equal or higher role".to_string()));
+    }
+    
+    // Nobody can hand out a permission they don't hold themselves
+    if let Some(permission) = payload.granted.iter().find(|p| !actor.has(p)) {
+        return Err(AppError::Forbidden(format!("Cannot grant {:?} without holding it", permission)));
+    }
+    
+    let granted: Vec<String> = payload.granted.iter().map(|p| p.as_str().to_string()).collect();
+    let denied: Vec<String> = payload.denied.iter().map(|p| p.as_str().to_string()).collect();
+    
+    let updated = sqlx::query!(
+        r#"
+        UPDATE group_members
+        SET granted_permissions = $1, denied_permissions = $2
+        WHERE group_id = $3 AND user_id = $4
+        RETURNING joined_at
+        "#,
+        &granted,
+        &denied,
+        conversation_id,
+        target_id
+    )
+    .fetch_one(&state.db_pool)
+    .await?;
+    
+    let mut recipients = event_recipients(&state.db_pool, &conversation).await?;
+    if !recipients.contains(&target_id) {
+        recipients.push(target_id);
+    }
+    
+    publish_membership_event(&state.kafka_producer, MembershipEvent {
+        conversation_id,
+        conversation_type: conversation.conversation_type,
+        is_encrypted: conversation.is_encrypted,
+        actor_id: user_id,
+        change: MembershipChange::PermissionsChanged {
+            user_id: target_id,
+            granted: payload.granted.clone(),
+            denied: payload.denied.clone(),
+        },
+        recipients,
+        timestamp: Utc::now().timestamp(),
+    }).await?;
+    
+    Ok(Json(MemberResponse {
+        user_id: target_id,
+        role: target.role,
+        joined_at: updated.joined_at,
+        granted_permissions: payload.granted,
+        denied_permissions: payload.denied,
+    }))
+}
+
+async fn update_settings(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+    Json(payload): Json<ConversationSettings>,
+) -> Result<Json<ConversationResponse>, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    let conversation = load_conversation(&state.db_pool, conversation_id).await?;
+    reject_direct_message(&conversation)?;
+    
+    require_permission(&state.db_pool, conversation_id, user_id, Permission::ChangeGroupInfo).await?;
+    
+    if payload.slow_mode_seconds > MAX_SLOW_MODE_SECONDS {
+        return Err(AppError::ValidationError(format!(
+            "Slow mode is limited to {} seconds",
+            MAX_SLOW_MODE_SECONDS
+        )));
+    }
+    
+    // Channels are broadcast-only by definition
+    if conversation.conversation_type == ConversationType::Channel && !payload.only_admins_can_send {
+        return Err(AppError::ValidationError("Only admins can post in channels".to_string()));
+    }
+    
+    sqlx::query!(
+        r#"
+        UPDATE conversations
+        SET only_admins_can_send = $1, slow_mode_seconds = $2
+        WHERE id = $3
+        "#,
+        payload.only_admins_can_send,
+        payload.slow_mode_seconds as i32,
+        conversation_id
+    )
+    .execute(&state.db_pool)
+    .await?;
+    
+    let recipients = list_member_ids(&state.db_pool, conversation_id).await?;
+    
+    publish_membership_event(&state.kafka_producer, MembershipEvent {
+        conversation_id,
+        conversation_type: conversation.conversation_type.clone(),
+        is_encrypted: conversation.is_encrypted,
+        actor_id: user_id,
+        change: MembershipChange::SettingsUpdated {
+            settings: payload.clone(),
+        },
+        recipients,
+        timestamp: Utc::now().timestamp(),
+    }).await?;
+    
+    let conversation = ConversationRecord {
+        settings: payload,
+        ..conversation
+    };
+    
+    Ok(Json(build_response(&state.db_pool, conversation).await?))
+}
+
+async fn set_disappearing_timer(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+    Json(payload): Json<SetDisappearingTimerRequest>,
+) -> Result<Json<ConversationResponse>, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    if !payload.timer.is_valid() {
+        return Err(AppError::ValidationError(format!(
+            "Custom timers must be between {} and {} seconds",
+            DisappearingTimer::MIN_CUSTOM_SECONDS,
+            DisappearingTimer::MAX_CUSTOM_SECONDS
+        )));
+    }
+    
+    let conversation = load_conversation(&state.db_pool, conversation_id).await?;
+    
+    // Either side of a DM may set the timer; elsewhere it is group info
+    if conversation.conversation_type == ConversationType::DirectMessage {
+        if get_member_role(&state.db_pool, conversation_id, user_id).await?.is_none() {
+            return Err(AppError::Forbidden("Not a member of this conversation".to_string()));
+        }
+    } else {
+        require_permission(&state.db_pool, conversation_id, user_id, Permission::ChangeGroupInfo).await?;
+    }
+    
+    if conversation.disappearing_timer == payload.timer {
+        return Ok(Json(build_response(&state.db_pool, conversation).await?));
+    }
+    
+    sqlx::query!(
+        "UPDATE conversations SET message_ttl_seconds = $1 WHERE id = $2",
+        payload.timer.seconds() as i32,
+        conversation_id
+    )
+    .execute(&state.db_pool)
+    .await?;
+    
+    // The message processor turns this into a system message in the conversation
+    publish_membership_event(&state.kafka_producer, MembershipEvent {
+        conversation_id,
+        conversation_type: conversation.conversation_type.clone(),
+        is_encrypted: conversation.is_encrypted,
+        actor_id: user_id,
+        change: MembershipChange::DisappearingTimerChanged {
+            timer: payload.timer,
+        },
+        recipients: event_recipients(&state.db_pool, &conversation).await?,
+        timestamp: Utc::now().timestamp(),
+    }).await?;
+    
+    info!("User {} set disappearing timer of {} to {:?}", user_id, conversation_id, payload.timer);
+    
+    let conversation = ConversationRecord {
+        disappearing_timer: payload.timer,
+        ..conversation
+    };
+    
+    Ok(Json(build_response(&state.db_pool, conversation).await?))
+}
+
+// Helper functions
+fn outranks(actor: &GroupRole, target: &GroupRole) -> bool {
+    fn rank(role: &GroupRole) -> u8 {
+        match role {
+            GroupRole::Owner => 3,
+            GroupRole::Admin => 2,
+            GroupRole::Member => 1,
+            GroupRole::Guest => 0,
+        }
+    }
+    
+    rank(actor) > rank(target)
+}
+
+fn reject_direct_message(conversation: &ConversationRecord) -> Result<(), AppError> {
+    if conversation.conversation_type == ConversationType::DirectMessage {
+        return Err(AppError::ValidationError("Direct messages cannot be modified".to_string()));
+    }
+    
+    Ok(())
+}
+
+async fn load_conversation(db_pool: &PgPool, conversation_id: Uuid) -> Result<ConversationRecord, AppError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, conversation_type, name, avatar_url, is_encrypted, created_at,
+               only_admins_can_send, slow_mode_seconds, message_ttl_seconds
+        FROM conversations WHERE id = $1
+        "#,
+        conversation_id
+    )
+    .fetch_optional(db_pool)
+    .await?
+    .ok_or(AppError::NotFound("Conversation not found".to_string()))?;
+    
+    Ok(ConversationRecord {
+        id: row.id,
+        conversation_type: row.conversation_type.parse().map_err(AppError::DatabaseError)?,
+        name: row.name,
+        avatar_url: row.avatar_url,
+        is_encrypted: row.is_encrypted,
+        created_at: row.created_at,
+        settings: ConversationSettings {
+            only_admins_can_send: row.only_admins_can_send,
+            slow_mode_seconds: row.slow_mode_seconds as u32,
+        },
+        disappearing_timer: DisappearingTimer::from_seconds(row.message_ttl_seconds as u32),
+    })
+}
+
+async fn build_response(
+    db_pool: &PgPool,
+    conversation: ConversationRecord,
+) -> Result<ConversationResponse, AppError> {
+    let admins_only = conversation.conversation_type == ConversationType::Channel;
+    
+    let rows = sqlx::query!(
+        r#"
+        SELECT user_id, role, joined_at, granted_permissions, denied_permissions
+        FROM group_members
+        WHERE group_id = $1 AND is_banned = false
+        AND (NOT $2 OR role IN ('owner', 'admin'))
+        ORDER BY joined_at
+        "#,
+        conversation.id,
+        admins_only
+    )
+    .fetch_all(db_pool)
+    .await?;
+    
+    let member_count = sqlx::query!(
+        r#"
+        SELECT COUNT(*) as count FROM group_members
+        WHERE group_id = $1 AND is_banned = false
+        "#,
+        conversation.id
+    )
+    .fetch_one(db_pool)
+    .await?
+    .count
+    .unwrap_or(0);
+    
+    let mut members = Vec::with_capacity(rows.len());
+    for row in rows {
+        members.push(MemberResponse {
+            user_id: row.user_id,
+            role: row.role.parse().map_err(AppError::DatabaseError)?,
+            joined_at: row.joined_at,
+            granted_permissions: parse_permissions(&row.granted_permissions),
+            denied_permissions: parse_permissions(&row.denied_permissions),
+        });
+    }
+    
+    Ok(ConversationResponse {
+        id: conversation.id,
+        conversation_type: conversation.conversation_type,
+        name: conversation.name,
+        avatar_url: conversation.avatar_url,
+        is_encrypted: conversation.is_encrypted,
+        created_at: conversation.created_at,
+        settings: conversation.settings,
+        disappearing_timer: conversation.disappearing_timer,
+        member_count,
+        members,
+    })
+}
+
+async fn insert_member(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    conversation_id: Uuid,
+    user_id: Uuid,
+    role: &GroupRole,
+) -> Result<(), AppError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO group_members (group_id, user_id, role)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (group_id, user_id) DO NOTHING
+        "#,
+        conversation_id,
+        user_id,
+        role.as_str()
+    )
+    .execute(&mut **tx)
+    .await?;
+    
+    Ok(())
+}
+
+async fn get_member_role(
+    db_pool: &PgPool,
+    conversation_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<GroupRole>, AppError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT role FROM group_members
+        WHERE group_id = $1 AND user_id = $2 AND is_banned = false
+        "#,
+        conversation_id,
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await?;
+    
+    row.map(|r| r.role.parse().map_err(AppError::DatabaseError))
+        .transpose()
+}
+
+async fn get_member_permissions(
+    db_pool: &PgPool,
+    conversation_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<MemberPermissions>, AppError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT role, granted_permissions, denied_permissions FROM group_members
+        WHERE group_id = $1 AND user_id = $2 AND is_banned = false
+        "#,
+        conversation_id,
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await?;
+    
+    match row {
+        Some(row) => Ok(Some(MemberPermissions::new(
+            row.role.parse().map_err(AppError::DatabaseError)?,
+            parse_permissions(&row.granted_permissions),
+            parse_permissions(&row.denied_permissions),
+        ))),
+        None => Ok(None),
+    }
+}
+
+async fn require_permission(
+    db_pool: &PgPool,
+    conversation_id: Uuid,
+    user_id: Uuid,
+    permission: Permission,
+) -> Result<GroupRole, AppError> {
+    let member = get_member_permissions(db_pool, conversation_id, user_id)
+        .await?
+        .ok_or(AppError::Forbidden("Not a member of this conversation".to_string()))?;
+    
+    if !member.has(&permission) {
+        return Err(AppError::Forbidden(format!("Missing permission: {:?}", permission)));
+    }
+    
+    Ok(member.role)
+}
+
+/// Banned users can't change anything here, except to leave.
+async fn require_not_banned(restrictions: &RestrictionCache, user_id: Uuid) -> Result<(), AppError> {
+    if restrictions.is_banned(user_id).await? {
+        return Err(AppError::Forbidden("Account suspended".to_string()));
+    }
+    
+    Ok(())
+}
+
+/// For changes other users see. Muted users can still manage the
+/// conversations they are in, but can't start new ones or pull people in.
+async fn require_can_send(restrictions: &RestrictionCache, user_id: Uuid) -> Result<(), AppError> {
+    require_not_banned(restrictions, user_id).await?;
+    
+    if !restrictions.can_send(user_id).await? {
+        return Err(AppError::Forbidden("You are muted".to_string()));
+    }
+    
+    Ok(())
+}
+
+async fn list_member_ids(db_pool: &PgPool, conversation_id: Uuid) -> Result<Vec<Uuid>, AppError> {
+    let rows = sqlx::query!(
+        "SELECT user_id FROM group_members WHERE group_id = $1 AND is_banned = false",
+        conversation_id
+    )
+    .fetch_all(db_pool)
+    .await?;
+    
+    Ok(rows.into_iter().map(|r| r.user_id).collect())
+}
+
+/// Who hears about membership changes. Channel subscribers never learn about
+/// each other, so in a channel only the admins are told.
+async fn event_recipients(db_pool: &PgPool, conversation: &ConversationRecord) -> Result<Vec<Uuid>, AppError> {
+    if conversation.conversation_type == ConversationType::Channel {
+        list_admin_ids(db_pool, conversation.id).await
+    } else {
+        list_member_ids(db_pool, conversation.id).await
+    }
+}
+
+async fn list_admin_ids(db_pool: &PgPool, conversation_id: Uuid) -> Result<Vec<Uuid>, AppError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT user_id FROM group_members
+        WHERE group_id = $1 AND is_banned = false AND role IN ('owner', 'admin')
+        "#,
+        conversation_id
+    )
+    .fetch_all(db_pool)
+    .await?;
+    
+    Ok(rows.into_iter().map(|r| r.user_id).collect())
+}
+
+async fn verify_users_exist(db_pool: &PgPool, user_ids: &[Uuid]) -> Result<(), AppError> {
+    if user_ids.is_empty() {
+        return Ok(());
+    }
+    
+    let found = sqlx::query!(
+        "SELECT COUNT(*) as count FROM users WHERE id = ANY($1) AND is_active = true",
+        user_ids
+    )
+    .fetch_one(db_pool)
+    .await?;
+    
+    if found.count.unwrap_or(0) as usize != user_ids.len() {
+        return Err(AppError::ValidationError("One or more users do not exist".to_string()));
+    }
+    
+    Ok(())
+}
+
+/// Whether `user_id` and any of `others` have blocked one another.
+async fn is_blocked_between(db_pool: &PgPool, user_id: Uuid, others: &[Uuid]) -> Result<bool, AppError> {
+    let blocked = sqlx::query!(
+        r#"
+        SELECT 1 AS "blocked!" FROM user_relationships
+        WHERE relationship_type = 'blocked'
+          AND ((user_id = $1 AND related_user_id = ANY($2))
+            OR (user_id = ANY($2) AND related_user_id = $1))
+        LIMIT 1
+        "#,
+        user_id,
+        others
+    )
+    .fetch_optional(db_pool)
+    .await?;
+    
+    Ok(blocked.is_some())
+}
+
+async fn publish_membership_event(
+    producer: &rdkafka::producer::FutureProducer,
+    event: MembershipEvent,
+) -> Result<(), AppError> {
+    let payload = serde_json::to_vec(&event)
+        .map_err(|e| AppError::SerializationError(e.to_string()))?;
+    
+    let record = rdkafka::producer::FutureRecord::to("membership-events")
+        .key(&event.conversation_id.to_string())
+        .payload(&payload);
+    
+    producer.send(record, Duration::from_secs(5)).await
+        .map_err(|(e, _)| AppError::ExternalServiceError(e.to_string()))?;
+    
+    Ok(())
+}
+
+fn authenticate(headers: &HeaderMap) -> Result<Uuid, AppError> {
+    use jsonwebtoken::{decode, DecodingKey, Validation};
+    
+    let token = headers
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .ok_or(AppError::Unauthorized("Missing token".to_string()))?;
+    
+    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    let token_data = decode::<TokenClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::default(),
+    )
+    .map_err(|_| AppError::Unauthorized("Invalid token".to_string()))?;
+    
+    Ok(token_data.claims.sub)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenClaims {
+    sub: Uuid,
+    exp: usize,
+    iat: usize,
+    device_id: String,
+    session_id: Uuid,
+}
@@ -1,19 +1,200 @@
+use base64::{engine::general_purpose, Engine as _};
 use tonic::{transport::Server, Request, Response, Status};
 use encryption_proto::{
     encryption_server::{Encryption, EncryptionServer},
     *,
 };
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::producer::FutureProducer;
+use rdkafka::Message as KafkaMessage;
 use sqlx::{postgres::PgPoolOptions, PgPool};
 use std::sync::Arc;
 use uuid::Uuid;
-use tracing::{info, error};
+use tracing::{info, error, warn};
+use x25519_dalek::PublicKey;
+
+use shared::crypto::{generate_group_key, wrap_key};
+use shared::models::ConversationType;
+use shared::types::{MembershipChange, MembershipEvent};
 
 mod encryption_proto {
     tonic::include_proto!("encryption");
 }
 
+#[derive(Clone)]
 struct EncryptionService {
     db_pool: PgPool,
+    kafka_producer: FutureProducer,
+}
+
+impl EncryptionService {
+    /// Generates a new group key and stores it wrapped to each member's
+    /// X25519 key, as the group's next key version. The key itself is never
+    /// stored. Returns the members who received it; members who never
+    /// uploaded a key are skipped.
+    async fn rotate_keys_for_group(&self, group_id: Uuid) -> Result<Vec<Uuid>, Box<dyn std::error::Error + Send + Sync>> {
+        let members = sqlx::query!(
+            r#"
+            SELECT gm.user_id, u.dh_public_key
+            FROM group_members gm
+            JOIN users u ON u.id = gm.user_id
+            WHERE gm.group_id = $1 AND gm.is_banned = false
+            "#,
+            group_id
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+        
+        let group_key = generate_group_key();
+        let mut wrapped_keys = Vec::with_capacity(members.len());
+        
+        for member in members {
+            let public_key = match member.dh_public_key.as_deref().and_then(parse_dh_public_key) {
+                Some(public_key) => public_key,
+                None => {
+                    warn!("User {} has no usable key; not sending them the key for {}", member.user_id, group_id);
+                    continue;
+                }
+            };
+            
+            wrapped_keys.push((member.user_id, wrap_key(&group_key, &public_key)?));
+        }
+        
+        let mut tx = self.db_pool.begin().await?;
+        
+        // Serializes rotations of the same group
+        sqlx::query!("SELECT id FROM conversations WHERE id = $1 FOR UPDATE", group_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        
+        let key_version = sqlx::query_scalar!(
+            r#"SELECT COALESCE(MAX(key_version), 0) + 1 AS "version!" FROM group_keys WHERE group_id = $1"#,
+            group_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        
+        for (user_id, wrapped) in &wrapped_keys {
+            sqlx::query!(
+                r#"
+                INSERT INTO group_keys
+                (group_id, key_version, user_id, ephemeral_public_key, wrapped_key, nonce)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                "#,
+                group_id,
+                key_version,
+                user_id,
+                &wrapped.ephemeral_public[..],
+                wrapped.ciphertext,
+                &wrapped.nonce[..]
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        
+        tx.commit().await?;
+        
+        Ok(wrapped_keys.into_iter().map(|(user_id, _)| user_id).collect())
+    }
+    
+    /// Lets members know to fetch the new group key; the message processor
+    /// also records the rotation in the conversation's timeline.
+    async fn announce_rotation(
+        &self,
+        conversation_id: Uuid,
+        conversation_type: ConversationType,
+        is_encrypted: bool,
+        actor_id: Uuid,
+        members: Vec<Uuid>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let event = MembershipEvent {
+            conversation_id,
+            conversation_type,
+            is_encrypted,
+            actor_id,
+            change: MembershipChange::KeysRotated {
+                member_count: members.len() as u32,
+            },
+            recipients: members,
+            timestamp: chrono::Utc::now().timestamp(),
+        };
+        
+        let payload = serde_json::to_vec(&event)?;
+        
+        let record = rdkafka::producer::FutureRecord::to("membership-events")
+            .key(&event.conversation_id.to_string())
+            .payload(&payload);
+        
+        self.kafka_producer
+            .send(record, std::time::Duration::from_secs(5))
+            .await
+            .map_err(|(e, _)| e)?;
+        
+        Ok(())
+    }
+    
+    async fn consume_membership_events(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let kafka_brokers = std::env::var("KAFKA_BROKERS")
+            .unwrap_or_else(|_| "localhost:9092".to_string());
+        
+        let consumer: StreamConsumer = rdkafka::config::ClientConfig::new()
+            .set("group.id", "encryption-service")
+            .set("bootstrap.servers", &kafka_brokers)
+            .set("enable.partition.eof", "false")
+            .set("enable.auto.commit", "true")
+            .create()?;
+        
+        consumer.subscribe(&["membership-events"])?;
+        
+        loop {
+            let message = match consumer.recv().await {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Failed to receive membership event: {}", e);
+                    continue;
+                }
+            };
+            
+            let event = match message.payload()
+                .and_then(|payload| serde_json::from_slice::<MembershipEvent>(payload).ok())
+            {
+                Some(event) => event,
+                None => continue,
+            };
+            
+            // Anyone joining or leaving an encrypted group gets a fresh group key,
+            // so newcomers can't read history and leavers can't read what follows
+            if !event.is_encrypted || !event.change.changes_readers() {
+                continue;
+            }
+            
+            let members = match self.rotate_keys_for_group(event.conversation_id).await {
+                Ok(members) => members,
+                Err(e) => {
+                    error!("Failed to rotate keys for {}: {}", event.conversation_id, e);
+                    continue;
+                }
+            };
+            
+            info!("Rotated group keys for {} ({} members)", event.conversation_id, members.len());
+            
+            if members.is_empty() {
+                continue;
+            }
+            
+            let announced = self.announce_rotation(
+                event.conversation_id,
+                event.conversation_type,
+                event.is_encrypted,
+                event.actor_id,
+                members,
+            ).await;
+            
+            if let Err(e) = announced {
+                error!("Failed to announce key rotation for {}: {}", event.conversation_id, e);
+            }
+        }
+    }
 }
 
 #[tonic::async_trait]
@@ -153,34 +334,61 @@ impl Encryption for EncryptionService {
         let group_id = Uuid::parse_str(&req.group_id)
             .map_err(|_| Status::invalid_argument("Invalid group ID"))?;
         
-        // Get all group members
-        let members = sqlx::query!(
-            r#"
-            SELECT user_id FROM group_members 
-            WHERE group_id = $1 AND is_banned = false
-            "#,
+        let group = sqlx::query!(
+            "SELECT conversation_type, is_encrypted FROM conversations WHERE id = $1",
             group_id
         )
-        .fetch_all(&self.db_pool)
+        .fetch_optional(&self.db_pool)
         .await
         .map_err(|e| {
-            error!("Failed to fetch group members: {}", e);
-            Status::internal("Failed to fetch group members")
-        })?;
+            error!("Failed to load group {}: {}", group_id, e);
+            Status::internal("Failed to rotate group keys")
+        })?
+        .ok_or_else(|| Status::not_found("Group not found"))?;
+        
+        let conversation_type = group.conversation_type.parse::<ConversationType>()
+            .map_err(|e| {
+                error!("Group {} has an invalid type: {}", group_id, e);
+                Status::internal("Failed to rotate group keys")
+            })?;
+        
+        let members = self.rotate_keys_for_group(group_id)
+            .await
+            .map_err(|e| {
+                error!("Failed to rotate keys for {}: {}", group_id, e);
+                Status::internal("Failed to rotate group keys")
+            })?;
+        
+        let rotated_for_users = members.len() as u32;
         
-        // Generate new encrypted key for each member
-        // This is a simplified version - in reality, we'd use
-        // each member's public key to encrypt the new group key
+        info!("Rotated group keys for {} on request ({} members)", group_id, rotated_for_users);
+        
+        // Audited like rotations on membership changes. No member asked for
+        // this one, so the service is the actor.
+        if !members.is_empty() {
+            self.announce_rotation(group_id, conversation_type, group.is_encrypted, Uuid::nil(), members)
+                .await
+                .map_err(|e| {
+                    error!("Failed to announce key rotation for {}: {}", group_id, e);
+                    Status::internal("Rotated group keys but failed to announce the rotation")
+                })?;
+        }
         
         let response = RotateGroupKeysResponse {
             success: true,
-            rotated_for_users: members.len() as u32,
+            rotated_for_users,
         };
         
         Ok(Response::new(response))
     }
 }
 
+fn parse_dh_public_key(encoded: &str) -> Option<PublicKey> {
+    let bytes = general_purpose::STANDARD.decode(encoded).ok()?;
+    let bytes: [u8; 32] = bytes.try_into().ok()?;
+    Some(PublicKey::from(bytes))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt::init();
@@ -196,7 +404,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .await?;
     
     let addr = "[::1]:50051".parse()?;
-    let service = EncryptionService { db_pool };
+    let kafka_brokers = std::env::var("KAFKA_BROKERS")
+        .unwrap_or_else(|_| "localhost:9092".to_string());
+    
+    let kafka_producer: FutureProducer = rdkafka::config::ClientConfig::new()
+        .set("bootstrap.servers", &kafka_brokers)
+        .set("message.timeout.ms", "5000")
+        .create()?;
+    
+    let service = EncryptionService { db_pool, kafka_producer };
+    
+    // Rotate group keys as membership changes
+    let consumer = service.clone();
+    tokio::spawn(async move {
+        if let Err(e) = consumer.consume_membership_events().await {
+            error!("Membership event consumer error: {}", e);
+        }
+    });
     
     info!("Encryption service listening on {}", addr);
     
@@ -0,0 +1,7 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The gateway only talks to the presence service as a client
+    tonic_build::configure()
+        .build_server(false)
+        .compile(&["../presence/src/presence.proto"], &["../presence/src"])?;
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use std::collections::HashSet;
+
+use dashmap::DashMap;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Which of this gateway's connected users subscribe to which channels.
+/// Channel posts carry no recipient list, since a channel can have far more
+/// subscribers than is worth listing per post; each gateway delivers them to
+/// the subscribers it has connected instead. Loaded when a user connects and
+/// kept current from membership events.
+#[derive(Default)]
+pub struct ChannelSubscriptions {
+    by_channel: DashMap<Uuid, HashSet<Uuid>>,
+    by_user: DashMap<Uuid, HashSet<Uuid>>,
+}
+
+impl ChannelSubscriptions {
+    /// Loads the channels a newly connected user subscribes to.
+    pub async fn load_user(&self, db_pool: &PgPool, user_id: Uuid) -> Result<(), sqlx::Error> {
+        let channels = sqlx::query!(
+            r#"
+            SELECT gm.group_id FROM group_members gm
+            JOIN conversations c ON c.id = gm.group_id
+            WHERE gm.user_id = $1 AND c.conversation_type = 'channel' AND gm.is_banned = false
+            "#,
+            user_id
+        )
+        .fetch_all(db_pool)
+        .await?;
+        
+        for channel in channels {
+            self.subscribe(channel.group_id, user_id);
+        }
+        
+        Ok(())
+    }
+    
+    pub fn subscribe(&self, channel_id: Uuid, user_id: Uuid) {
+        self.by_channel.entry(channel_id).or_default().insert(user_id);
+        self.by_user.entry(user_id).or_default().insert(channel_id);
+    }
+    
+    pub fn unsubscribe(&self, channel_id: Uuid, user_id: Uuid) {
+        self.by_channel.remove_if_mut(&channel_id, |_, users| {
+            users.remove(&user_id);
+            users.is_empty()
+        });
+        self.by_user.remove_if_mut(&user_id, |_, channels| {
+            channels.remove(&channel_id);
+            channels.is_empty()
+        });
+    }
+    
+    /// Forgets a user whose last connection to this gateway closed.
+    pub fn forget_user(&self, user_id: Uuid) {
+        if let Some((_, channels)) = self.by_user.remove(&user_id) {
+            for channel_id in channels {
+                self.by_channel.remove_if_mut(&channel_id, |_, users| {
+                    users.remove(&user_id);
+                    users.is_empty()
+                });
+            }
+        }
+    }
+    
+    /// Forgets a deleted channel.
+    pub fn forget_channel(&self, channel_id: Uuid) {
+        if let Some((_, users)) = self.by_channel.remove(&channel_id) {
+            for user_id in users {
+                self.by_user.remove_if_mut(&user_id, |_, channels| {
+                    channels.remove(&channel_id);
+                    channels.is_empty()
+                });
+            }
+        }
+    }
+    
+    pub fn subscribers(&self, channel_id: Uuid) -> Vec<Uuid> {
+        self.by_channel
+            .get(&channel_id)
+            .map(|users| users.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
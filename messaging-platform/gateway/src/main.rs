@@ -11,7 +11,6 @@ use axum::{
 };
 use dashmap::DashMap;
 use futures::{stream::SplitSink, SinkExt, StreamExt};
-use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
@@ -24,10 +23,66 @@ use tokio::{
     sync::{mpsc, RwLock},
     time,
 };
+use tonic::transport::Channel;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
-use shared::models::User;
+use shared::models::{
+    ConversationType, CustomStatus, DeleteScope, MessageMentions, MessageType, ModerationAction, ModerationTarget,
+    PresenceActivity, PresenceStatus, User,
+};
+use shared::restrictions::{RestrictionCache, DEFAULT_CACHE_TTL};
+use shared::types::{
+    MembershipChange, MembershipEvent, MessageOperation, MessageOperationEnvelope, MessageUpdate,
+    MessageUpdateEvent, ModerationActionEvent, PresenceEvent, ReadReceiptEnvelope,
+};
+
+mod channels;
+
+use channels::ChannelSubscriptions;
+
+mod presence_proto {
+    tonic::include_proto!("presence");
+}
+
+use presence_proto::{presence_client::PresenceClient, HeartbeatRequest, UpdatePresenceRequest};
+
+impl From<PresenceStatus> for presence_proto::PresenceStatus {
+    fn from(status: PresenceStatus) -> Self {
+        match status {
+            PresenceStatus::Online => presence_proto::PresenceStatus::Online,
+            PresenceStatus::Idle => presence_proto::PresenceStatus::Idle,
+            PresenceStatus::Away => presence_proto::PresenceStatus::Away,
+            PresenceStatus::Dnd => presence_proto::PresenceStatus::Dnd,
+            PresenceStatus::Invisible => presence_proto::PresenceStatus::Invisible,
+            PresenceStatus::Offline => presence_proto::PresenceStatus::Offline,
+        }
+    }
+}
+
+impl From<PresenceActivity> for presence_proto::PresenceActivity {
+    fn from(activity: PresenceActivity) -> Self {
+        match activity {
+            PresenceActivity::InACall => presence_proto::PresenceActivity::InACall,
+            PresenceActivity::InAMeeting => presence_proto::PresenceActivity::InAMeeting,
+            PresenceActivity::Focusing => presence_proto::PresenceActivity::Focusing,
+            PresenceActivity::Commuting => presence_proto::PresenceActivity::Commuting,
+            PresenceActivity::OnVacation => presence_proto::PresenceActivity::OnVacation,
+        }
+    }
+}
+
+impl From<CustomStatus> for presence_proto::CustomStatus {
+    fn from(custom_status: CustomStatus) -> Self {
+        Self {
+            text: custom_status.text,
+            emoji: custom_status.emoji,
+            activity: custom_status.activity
+                .map_or(presence_proto::PresenceActivity::Unspecified, Into::into) as i32,
+            clear_after: custom_status.clear_after,
+        }
+    }
+}
 
 type Tx = mpsc::UnboundedSender<Message>;
 type Rx = mpsc::UnboundedReceiver<Message>;
@@ -41,8 +96,13 @@ struct Connection {
 
 struct AppState {
     connections: Arc<DashMap<Uuid, Vec<Connection>>>,
-    redis_client: redis::Client,
+    // Presence is owned by the presence service; the gateway only reports
+    // connects, disconnects and status changes to it
+    presence_client: PresenceClient<Channel>,
     kafka_producer: rdkafka::producer::FutureProducer,
+    restrictions: Arc<RestrictionCache>,
+    db_pool: sqlx::PgPool,
+    channel_subscriptions: ChannelSubscriptions,
 }
 
 #[derive(Debug, Deserialize)]
@@ -55,10 +115,26 @@ struct WsQuery {
 #[serde(tag = "type", content = "data")]
 enum WsMessage {
     Heartbeat,
+    // Sent by clients when the user interacts; keeps them from going idle
+    Activity,
     Message(ClientMessage),
+    MessageAck(MessageAck),
     Presence(PresenceUpdate),
     Typing(TypingIndicator),
     ReadReceipt(ReadReceipt),
+    EditMessage(EditMessage),
+    DeleteMessage(DeleteMessage),
+    React(ReactionRequest),
+    Pin(PinRequest),
+    MessageEdited(MessageEdited),
+    MessageDeleted(MessageDeleted),
+    ReactionsUpdated(ReactionsUpdated),
+    PinUpdated(PinUpdated),
+    ThreadUpdated(ThreadUpdated),
+    MessageRead(MessageRead),
+    ReadMarkerUpdated(ReadMarkerUpdated),
+    ConversationUpdated(ConversationUpdated),
+    MessagesExpired(MessagesExpired),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -69,12 +145,33 @@ struct ClientMessage {
     nonce: Vec<u8>,
     reply_to: Option<Uuid>,
     timestamp: i64,
+    // Cleartext so the server can route notifications; content stays encrypted
+    #[serde(default, skip_serializing_if = "MessageMentions::is_empty")]
+    mentions: MessageMentions,
+    // Set by the server on delivery; ignored when a client sends a message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message_type: Option<MessageType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<i64>,
+    // Server signature on system messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MessageAck {
+    conversation_id: Uuid,
+    client_message_id: Uuid,
+    message_id: Uuid,
+    timestamp: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct PresenceUpdate {
-    status: String,
-    custom_status: Option<String>,
+    status: PresenceStatus,
+    // From clients, null keeps the current one and an empty one clears it.
+    // To clients, null once it has been cleared, including when it expires
+    custom_status: Option<CustomStatus>,
     last_active: i64,
 }
 
@@ -91,15 +188,125 @@ struct ReadReceipt {
     read_at: i64,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct EditMessage {
+    conversation_id: Uuid,
+    message_id: Uuid,
+    content: Vec<u8>, // Client-encrypted
+    nonce: Vec<u8>,
+    timestamp: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DeleteMessage {
+    conversation_id: Uuid,
+    message_id: Uuid,
+    scope: DeleteScope,
+    timestamp: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MessageEdited {
+    conversation_id: Uuid,
+    message_id: Uuid,
+    editor_id: Uuid,
+    content: Vec<u8>,
+    nonce: Vec<u8>,
+    edited_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MessageDeleted {
+    conversation_id: Uuid,
+    message_id: Uuid,
+    deleted_by: Uuid,
+    scope: DeleteScope,
+    deleted_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReactionRequest {
+    conversation_id: Uuid,
+    message_id: Uuid,
+    emoji: String,
+    add: bool,
+    timestamp: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PinRequest {
+    conversation_id: Uuid,
+    message_id: Uuid,
+    pinned: bool,
+    timestamp: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReactionsUpdated {
+    conversation_id: Uuid,
+    message_id: Uuid,
+    user_id: Uuid,
+    emoji: String,
+    added: bool,
+    counts: HashMap<String, i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PinUpdated {
+    conversation_id: Uuid,
+    message_id: Uuid,
+    pinned_by: Uuid,
+    pinned: bool,
+    updated_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ThreadUpdated {
+    conversation_id: Uuid,
+    root_id: Uuid,
+    reply_id: Uuid,
+    reply_count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MessageRead {
+    conversation_id: Uuid,
+    message_id: Uuid,
+    reader_id: Uuid,
+    read_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReadMarkerUpdated {
+    conversation_id: Uuid,
+    last_read_message_id: Uuid,
+    read_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MessagesExpired {
+    conversation_id: Uuid,
+    message_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ConversationUpdated {
+    conversation_id: Uuid,
+    actor_id: Uuid,
+    change: MembershipChange,
+    timestamp: i64,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt::init();
     
     dotenv::dotenv().ok();
     
-    let redis_url = std::env::var("REDIS_URL")
-        .expect("REDIS_URL must be set");
-    let redis_client = redis::Client::open(redis_url)?;
+    let presence_url = std::env::var("PRESENCE_SERVICE_URL")
+        .unwrap_or_else(|_| "http://[::1]:50052".to_string());
+    // Connect lazily so the gateway can start before the presence service
+    let presence_client = PresenceClient::new(Channel::from_shared(presence_url)?.connect_lazy());
     
     let kafka_brokers = std::env::var("KAFKA_BROKERS")
         .unwrap_or_else(|_| "localhost:9092".to_string());
@@ -110,10 +317,27 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .set("queue.buffering.max.ms", "0")
         .create()?;
     
+    let database_url = std::env::var("DATABASE_URL")
+        .expect("DATABASE_URL must be set");
+    let db_pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await?;
+    let restrictions = Arc::new(RestrictionCache::new(db_pool.clone(), DEFAULT_CACHE_TTL));
+    let restrictions_clone = restrictions.clone();
+    tokio::spawn(async move {
+        if let Err(e) = restrictions_clone.run(kafka_brokers, "gateway").await {
+            error!("Restriction cache consumer error: {}", e);
+        }
+    });
+    
     let state = Arc::new(AppState {
         connections: Arc::new(DashMap::new()),
-        redis_client,
+        presence_client,
         kafka_producer,
+        restrictions,
+        db_pool,
+        channel_subscriptions: ChannelSubscriptions::default(),
     });
     
     let app = Router::new()
@@ -159,6 +383,16 @@ async fn ws_handler(
         Err(_) => return StatusCode::UNAUTHORIZED.into_response(),
     };
     
+    // Tokens outlive a ban, so banned users are turned away here too
+    match state.restrictions.is_banned(claims.sub).await {
+        Ok(false) => {}
+        Ok(true) => return StatusCode::FORBIDDEN.into_response(),
+        Err(e) => {
+            error!("Failed to check restrictions for user {}: {}", claims.sub, e);
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+    }
+    
     ws.on_upgrade(move |socket| handle_socket(socket, claims.sub, query.device_id, state))
 }
 
@@ -175,6 +409,13 @@ async fn handle_socket(socket: WebSocket, user_id: Uuid, device_id: String, stat
         tx,
     };
     
+    // Channel posts reach a user through their subscriptions, so without
+    // them the connection would silently miss those
+    if let Err(e) = state.channel_subscriptions.load_user(&state.db_pool, user_id).await {
+        error!("Failed to load channel subscriptions for user {}: {}", user_id, e);
+        return;
+    }
+    
     // Store connection
     state
         .connections
@@ -184,15 +425,14 @@ async fn handle_socket(socket: WebSocket, user_id: Uuid, device_id: String, stat
     
     info!("User {} connected from device {}", user_id, device_id);
     
-    // Update presence in Redis
-    update_presence(&state.redis_client, user_id, "online", &device_id).await;
+    update_presence(&state.presence_client, user_id, &device_id, PresenceStatus::Online, None).await;
     
     // Spawn sender task
     let send_task = tokio::spawn(send_messages(sender, rx));
     
     // Spawn receiver task
     let connections = state.connections.clone();
-    let redis_client = state.redis_client.clone();
+    let presence_client = state.presence_client.clone();
     let kafka_producer = state.kafka_producer.clone();
     
     let receive_task = tokio::spawn(receive_messages(
@@ -200,7 +440,7 @@ async fn handle_socket(socket: WebSocket, user_id: Uuid, device_id: String, stat
         user_id,
         device_id,
         connections,
-        redis_client,
+        presence_client,
         kafka_producer,
     ));
     
@@ -214,12 +454,17 @@ async fn handle_socket(socket: WebSocket, user_id: Uuid, device_id: String, stat
     if let Some(mut connections) = state.connections.get_mut(&user_id) {
         connections.retain(|conn| conn.device_id != device_id);
         if connections.is_empty() {
+            drop(connections);
             state.connections.remove(&user_id);
         }
     }
     
+    if !state.connections.contains_key(&user_id) {
+        state.channel_subscriptions.forget_user(user_id);
+    }
+    
     // Update presence to offline
-    update_presence(&state.redis_client, user_id, "offline", &device_id).await;
+    update_presence(&state.presence_client, user_id, &device_id, PresenceStatus::Offline, None).await;
     
     info!("User {} disconnected from device {}", user_id, device_id);
 }
@@ -238,7 +483,7 @@ async fn receive_messages(
     user_id: Uuid,
     device_id: String,
     connections: Arc<DashMap<Uuid, Vec<Connection>>>,
-    redis_client: redis::Client,
+    presence_client: PresenceClient<Channel>,
     kafka_producer: rdkafka::producer::FutureProducer,
 ) {
     while let Some(Ok(message)) = receiver.next().await {
@@ -256,19 +501,32 @@ async fn receive_messages(
                                     }
                                 }
                             }
+                            
+                            report_heartbeat(&presence_client, user_id, &device_id, false).await;
+                        }
+                        WsMessage::Activity => {
+                            report_heartbeat(&presence_client, user_id, &device_id, true).await;
                         }
                         WsMessage::Message(msg) => {
                             // Forward to Kafka for processing
-                            if let Err(e) = forward_to_kafka(&kafka_producer, &msg, user_id).await {
+                            if let Err(e) = forward_to_kafka(&kafka_producer, &msg, user_id, &device_id).await {
                                 error!("Failed to forward message to Kafka: {}", e);
                             }
                         }
                         WsMessage::Presence(presence) => {
+                            // Going offline is tied to the connection; users
+                            // who want to look offline choose invisible
+                            if presence.status == PresenceStatus::Offline {
+                                warn!("Ignoring offline presence from connected user {}", user_id);
+                                continue;
+                            }
+                            
                             update_presence(
-                                &redis_client,
+                                &presence_client,
                                 user_id,
-                                &presence.status,
                                 &device_id,
+                                presence.status,
+                                presence.custom_status,
                             ).await;
                         }
                         WsMessage::Typing(typing) => {
@@ -277,7 +535,14 @@ async fn receive_messages(
                         }
                         WsMessage::ReadReceipt(receipt) => {
                             // Update read receipt in database via Kafka
-                            let receipt_data = serde_json::to_string(&receipt)
+                            let envelope = ReadReceiptEnvelope {
+                                user_id,
+                                device_id: device_id.clone(),
+                                conversation_id: receipt.conversation_id,
+                                message_id: receipt.message_id,
+                                read_at: receipt.read_at,
+                            };
+                            let receipt_data = serde_json::to_string(&envelope)
                                 .unwrap_or_default();
                             
                             let record = rdkafka::producer::FutureRecord::to("read-receipts")
@@ -288,6 +553,80 @@ async fn receive_messages(
                                 error!("Failed to send read receipt: {}", e);
                             }
                         }
+                        WsMessage::EditMessage(edit) => {
+                            let envelope = MessageOperationEnvelope {
+                                actor_id: user_id,
+                                conversation_id: edit.conversation_id,
+                                message_id: edit.message_id,
+                                operation: MessageOperation::Edit {
+                                    content: edit.content,
+                                    nonce: edit.nonce,
+                                },
+                                timestamp: edit.timestamp,
+                            };
+                            
+                            if let Err(e) = forward_operation_to_kafka(&kafka_producer, &envelope).await {
+                                error!("Failed to forward message edit to Kafka: {}", e);
+                            }
+                        }
+                        WsMessage::DeleteMessage(delete) => {
+                            let envelope = MessageOperationEnvelope {
+                                actor_id: user_id,
+                                conversation_id: delete.conversation_id,
+                                message_id: delete.message_id,
+                                operation: MessageOperation::Delete {
+                                    scope: delete.scope,
+                                },
+                                timestamp: delete.timestamp,
+                            };
+                            
+                            if let Err(e) = forward_operation_to_kafka(&kafka_producer, &envelope).await {
+                                error!("Failed to forward message delete to Kafka: {}", e);
+                            }
+                        }
+                        WsMessage::React(reaction) => {
+                            let envelope = MessageOperationEnvelope {
+                                actor_id: user_id,
+                                conversation_id: reaction.conversation_id,
+                                message_id: reaction.message_id,
+                                operation: MessageOperation::React {
+                                    emoji: reaction.emoji,
+                                    add: reaction.add,
+                                },
+                                timestamp: reaction.timestamp,
+                            };
+                            
+                            if let Err(e) = forward_operation_to_kafka(&kafka_producer, &envelope).await {
+                                error!("Failed to forward reaction to Kafka: {}", e);
+                            }
+                        }
+                        WsMessage::Pin(pin) => {
+                            let envelope = MessageOperationEnvelope {
+                                actor_id: user_id,
+                                conversation_id: pin.conversation_id,
+                                message_id: pin.message_id,
+                                operation: MessageOperation::Pin {
+                                    pinned: pin.pinned,
+                                },
+                                timestamp: pin.timestamp,
+                            };
+                            
+                            if let Err(e) = forward_operation_to_kafka(&kafka_producer, &envelope).await {
+                                error!("Failed to forward pin to Kafka: {}", e);
+                            }
+                        }
+                        WsMessage::MessageAck(_)
+                        | WsMessage::MessageEdited(_)
+                        | WsMessage::MessageDeleted(_)
+                        | WsMessage::ReactionsUpdated(_)
+                        | WsMessage::PinUpdated(_)
+                        | WsMessage::ThreadUpdated(_)
+                        | WsMessage::MessageRead(_)
+                        | WsMessage::ReadMarkerUpdated(_)
+                        | WsMessage::ConversationUpdated(_)
+                        | WsMessage::MessagesExpired(_) => {
+                            // Server-to-client frames only
+                        }
                     }
                 }
             }
@@ -303,15 +642,24 @@ async fn forward_to_kafka(
     producer: &rdkafka::producer::FutureProducer,
     message: &ClientMessage,
     sender_id: Uuid,
+    sender_device_id: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let envelope = MessageEnvelope {
         sender_id,
         conversation_id: message.conversation_id,
         message_id: message.message_id,
+        client_message_id: None,
+        sender_device_id: Some(sender_device_id.to_string()),
+        conversation_type: None,
+        recipients: Vec::new(),
         content: message.content.clone(),
         nonce: message.nonce.clone(),
         reply_to: message.reply_to,
         timestamp: message.timestamp,
+        mentions: message.mentions.clone(),
+        message_type: None,
+        expires_at: None,
+        signature: None,
     };
     
     let payload = serde_json::to_vec(&envelope)?;
@@ -325,6 +673,22 @@ async fn forward_to_kafka(
     Ok(())
 }
 
+async fn forward_operation_to_kafka(
+    producer: &rdkafka::producer::FutureProducer,
+    envelope: &MessageOperationEnvelope,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let payload = serde_json::to_vec(envelope)?;
+    
+    // Keyed by conversation so operations stay ordered with the messages they target
+    let record = rdkafka::producer::FutureRecord::to("message-operations")
+        .key(&envelope.conversation_id.to_string())
+        .payload(&payload);
+    
+    producer.send(record, Duration::from_secs(5)).await?;
+    
+    Ok(())
+}
+
 async fn broadcast_typing(
     connections: &DashMap<Uuid, Vec<Connection>>,
     typing: TypingIndicator,
@@ -346,31 +710,44 @@ async fn broadcast_typing(
 }
 
 async fn update_presence(
-    redis_client: &redis::Client,
+    presence_client: &PresenceClient<Channel>,
     user_id: Uuid,
-    status: &str,
     device_id: &str,
+    status: PresenceStatus,
+    custom_status: Option<CustomStatus>,
 ) {
-    let mut conn = match redis_client.get_async_connection().await {
-        Ok(conn) => conn,
-        Err(e) => {
-            error!("Failed to connect to Redis: {}", e);
-            return;
-        }
+    let request = UpdatePresenceRequest {
+        user_id: user_id.to_string(),
+        status: presence_proto::PresenceStatus::from(status) as i32,
+        device_id: device_id.to_string(),
+        ip_address: None,
+        user_agent: None,
+        custom_status: custom_status.map(Into::into),
     };
     
-    let key = format!("presence:{}", user_id);
-    let presence_data = serde_json::json!({
-        "status": status,
-        "device_id": device_id,
-        "last_active": chrono::Utc::now().timestamp(),
-    });
+    // Clients are cheap to clone and share the underlying channel
+    if let Err(e) = presence_client.clone().update_presence(request).await {
+        error!("Failed to update presence for user {}: {}", user_id, e);
+    }
+}
+
+/// Keeps the device's presence alive; `active` marks user interaction,
+/// without which the presence service moves the user to idle and then away.
+async fn report_heartbeat(
+    presence_client: &PresenceClient<Channel>,
+    user_id: Uuid,
+    device_id: &str,
+    active: bool,
+) {
+    let request = HeartbeatRequest {
+        user_id: user_id.to_string(),
+        device_id: device_id.to_string(),
+        active,
+    };
     
-    let _: Result<(), _> = conn.set_ex(
-        key,
-        presence_data.to_string(),
-        300, // 5 minute TTL
-    ).await;
+    if let Err(e) = presence_client.clone().heartbeat(request).await {
+        error!("Failed to report heartbeat for user {}: {}", user_id, e);
+    }
 }
 
 async fn cleanup_stale_connections(connections: &DashMap<Uuid, Vec<Connection>>) {
@@ -405,15 +782,36 @@ struct TokenClaims {
     session_id: Uuid,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct MessageEnvelope {
     sender_id: Uuid,
     conversation_id: Uuid,
     message_id: Uuid,
+    // Set on processed messages: the id the sending client chose, while
+    // `message_id` is the server-assigned timeuuid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_message_id: Option<Uuid>,
+    // The device that sent it; its ack goes there and nowhere else
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sender_device_id: Option<String>,
+    // Set on processed messages; channel posts are delivered to the
+    // channel's subscribers instead of `recipients`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    conversation_type: Option<ConversationType>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    recipients: Vec<Uuid>,
     content: Vec<u8>,
     nonce: Vec<u8>,
     reply_to: Option<Uuid>,
     timestamp: i64,
+    #[serde(default, skip_serializing_if = "MessageMentions::is_empty")]
+    mentions: MessageMentions,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message_type: Option<MessageType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<Vec<u8>>,
 }
 
 async fn start_kafka_consumer(state: Arc<AppState>) -> Result<(), Box<dyn std::error::Error>> {
@@ -428,7 +826,13 @@ async fn start_kafka_consumer(state: Arc<AppState>) -> Result<(), Box<dyn std::e
         .set("enable.auto.commit", "true")
         .create()?;
     
-    consumer.subscribe(&["processed-messages", "presence-updates"])?;
+    consumer.subscribe(&[
+        "processed-messages",
+        "message-updates",
+        "membership-events",
+        "presence-events",
+        "moderation-actions",
+    ])?;
     
     info!("Kafka consumer started");
     
@@ -437,15 +841,37 @@ async fn start_kafka_consumer(state: Arc<AppState>) -> Result<(), Box<dyn std::e
             "processed-messages" => {
                 if let Some(payload) = message.payload() {
                     if let Ok(envelope) = serde_json::from_slice::<MessageEnvelope>(payload) {
-                        // Broadcast to all participants in the conversation
-                        broadcast_message(&state.connections, envelope).await;
+                        broadcast_message(&state.connections, &state.channel_subscriptions, envelope).await;
+                    }
+                }
+            }
+            "message-updates" => {
+                if let Some(payload) = message.payload() {
+                    if let Ok(update) = serde_json::from_slice::<MessageUpdate>(payload) {
+                        deliver_message_update(&state.connections, update).await;
                     }
                 }
             }
-            "presence-updates" => {
+            "membership-events" => {
                 if let Some(payload) = message.payload() {
-                    // Handle presence updates
-                    handle_presence_update(&state.connections, payload).await;
+                    if let Ok(event) = serde_json::from_slice::<MembershipEvent>(payload) {
+                        track_channel_membership(&state.connections, &state.channel_subscriptions, &event);
+                        deliver_membership_event(&state.connections, event).await;
+                    }
+                }
+            }
+            "presence-events" => {
+                if let Some(payload) = message.payload() {
+                    if let Ok(event) = serde_json::from_slice::<PresenceEvent>(payload) {
+                        handle_presence_update(&state.connections, event).await;
+                    }
+                }
+            }
+            "moderation-actions" => {
+                if let Some(payload) = message.payload() {
+                    if let Ok(event) = serde_json::from_slice::<ModerationActionEvent>(payload) {
+                        handle_moderation_action(&state.connections, event).await;
+                    }
                 }
             }
             _ => {}
@@ -457,10 +883,13 @@ async fn start_kafka_consumer(state: Arc<AppState>) -> Result<(), Box<dyn std::e
 
 async fn broadcast_message(
     connections: &DashMap<Uuid, Vec<Connection>>,
+    channel_subscriptions: &ChannelSubscriptions,
     envelope: MessageEnvelope,
 ) {
-    // Fetch conversation participants (in production, from database)
-    // For demo, we'll broadcast to a hardcoded list
+    let recipients = match envelope.conversation_type {
+        Some(ConversationType::Channel) => channel_subscriptions.subscribers(envelope.conversation_id),
+        _ => envelope.recipients,
+    };
     
     let message = WsMessage::Message(ClientMessage {
         conversation_id: envelope.conversation_id,
@@ -469,6 +898,10 @@ async fn broadcast_message(
         nonce: envelope.nonce,
         reply_to: envelope.reply_to,
         timestamp: envelope.timestamp,
+        mentions: envelope.mentions,
+        message_type: envelope.message_type,
+        expires_at: envelope.expires_at,
+        signature: envelope.signature,
     });
     
     let message_json = match serde_json::to_string(&message) {
@@ -479,17 +912,267 @@ async fn broadcast_message(
         }
     };
     
-    // Find all connections for participants and send
-    // This is simplified - in production, we'd look up conversation participants
-    for conns in connections.iter() {
-        for conn in conns.value() {
-            if conn.user_id != envelope.sender_id { // Don't echo to sender
+    // The sending device gets an ack mapping its id to the server id
+    // instead; the sender's other devices get the message like everyone else
+    let ack = envelope.client_message_id.map(|client_message_id| {
+        WsMessage::MessageAck(MessageAck {
+            conversation_id: envelope.conversation_id,
+            client_message_id,
+            message_id: envelope.message_id,
+            timestamp: envelope.timestamp,
+        })
+    });
+    let ack_json = ack.and_then(|ack| serde_json::to_string(&ack).ok());
+    
+    for recipient in recipients {
+        let conns = match connections.get(&recipient) {
+            Some(conns) => conns,
+            None => continue,
+        };
+        
+        for conn in conns.iter() {
+            // System messages have no ack, so their actor gets the message itself
+            let is_sending_device = conn.user_id == envelope.sender_id
+                && envelope.sender_device_id.as_deref() == Some(conn.device_id.as_str());
+            
+            match &ack_json {
+                Some(ack_json) if is_sending_device => {
+                    let _ = conn.tx.send(Message::Text(ack_json.clone()));
+                }
+                _ => {
+                    let _ = conn.tx.send(Message::Text(message_json.clone()));
+                }
+            }
+        }
+    }
+}
+
+async fn deliver_message_update(
+    connections: &DashMap<Uuid, Vec<Connection>>,
+    update: MessageUpdate,
+) {
+    let message = match update.event {
+        MessageUpdateEvent::Edited {
+            conversation_id,
+            message_id,
+            editor_id,
+            content,
+            nonce,
+            edited_at,
+        } => WsMessage::MessageEdited(MessageEdited {
+            conversation_id,
+            message_id,
+            editor_id,
+            content,
+            nonce,
+            edited_at,
+        }),
+        MessageUpdateEvent::Deleted {
+            conversation_id,
+            message_id,
+            deleted_by,
+            scope,
+            deleted_at,
+        } => WsMessage::MessageDeleted(MessageDeleted {
+            conversation_id,
+            message_id,
+            deleted_by,
+            scope,
+            deleted_at,
+        }),
+        MessageUpdateEvent::ReactionsUpdated {
+            conversation_id,
+            message_id,
+            user_id,
+            emoji,
+            added,
+            counts,
+        } => WsMessage::ReactionsUpdated(ReactionsUpdated {
+            conversation_id,
+            message_id,
+            user_id,
+            emoji,
+            added,
+            counts,
+        }),
+        MessageUpdateEvent::PinUpdated {
+            conversation_id,
+            message_id,
+            pinned_by,
+            pinned,
+            updated_at,
+        } => WsMessage::PinUpdated(PinUpdated {
+            conversation_id,
+            message_id,
+            pinned_by,
+            pinned,
+            updated_at,
+        }),
+        MessageUpdateEvent::ThreadUpdated {
+            conversation_id,
+            root_id,
+            reply_id,
+            reply_count,
+        } => WsMessage::ThreadUpdated(ThreadUpdated {
+            conversation_id,
+            root_id,
+            reply_id,
+            reply_count,
+        }),
+        MessageUpdateEvent::MessageRead {
+            conversation_id,
+            message_id,
+            reader_id,
+            read_at,
+        } => WsMessage::MessageRead(MessageRead {
+            conversation_id,
+            message_id,
+            reader_id,
+            read_at,
+        }),
+        MessageUpdateEvent::ReadMarkerUpdated {
+            conversation_id,
+            last_read_message_id,
+            read_at,
+        } => WsMessage::ReadMarkerUpdated(ReadMarkerUpdated {
+            conversation_id,
+            last_read_message_id,
+            read_at,
+        }),
+        MessageUpdateEvent::Expired {
+            conversation_id,
+            message_ids,
+        } => WsMessage::MessagesExpired(MessagesExpired {
+            conversation_id,
+            message_ids,
+        }),
+    };
+    
+    let message_json = match serde_json::to_string(&message) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize message update: {}", e);
+            return;
+        }
+    };
+    
+    // Unlike new messages, updates carry their recipient list so that
+    // "delete for me" only reaches the deleting user's own devices
+    for recipient in update.recipients {
+        if let Some(conns) = connections.get(&recipient) {
+            for conn in conns.iter() {
                 let _ = conn.tx.send(Message::Text(message_json.clone()));
             }
         }
     }
 }
 
+/// Keeps the channel subscriptions of connected users in step with channel
+/// membership. Users who aren't connected here are loaded when they connect.
+fn track_channel_membership(
+    connections: &DashMap<Uuid, Vec<Connection>>,
+    channel_subscriptions: &ChannelSubscriptions,
+    event: &MembershipEvent,
+) {
+    if event.conversation_type != ConversationType::Channel {
+        return;
+    }
+    
+    let channel_id = event.conversation_id;
+    
+    match &event.change {
+        MembershipChange::Created { members, .. } => {
+            for &user_id in members.iter().filter(|user_id| connections.contains_key(user_id)) {
+                channel_subscriptions.subscribe(channel_id, user_id);
+            }
+        }
+        MembershipChange::MembersAdded { user_ids } => {
+            for &user_id in user_ids.iter().filter(|user_id| connections.contains_key(user_id)) {
+                channel_subscriptions.subscribe(channel_id, user_id);
+            }
+        }
+        MembershipChange::MemberRemoved { user_id } => {
+            channel_subscriptions.unsubscribe(channel_id, *user_id);
+        }
+        MembershipChange::Deleted => channel_subscriptions.forget_channel(channel_id),
+        _ => {}
+    }
+}
+
+async fn deliver_membership_event(
+    connections: &DashMap<Uuid, Vec<Connection>>,
+    event: MembershipEvent,
+) {
+    let message = WsMessage::ConversationUpdated(ConversationUpdated {
+        conversation_id: event.conversation_id,
+        actor_id: event.actor_id,
+        change: event.change,
+        timestamp: event.timestamp,
+    });
+    
+    let message_json = match serde_json::to_string(&message) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize membership event: {}", e);
+            return;
+        }
+    };
+    
+    for recipient in event.recipients {
+        if let Some(conns) = connections.get(&recipient) {
+            for conn in conns.iter() {
+                let _ = conn.tx.send(Message::Text(message_json.clone()));
+            }
+        }
+    }
+}
+
+/// Keeps a user's other devices in sync when one of them changes status.
+async fn handle_presence_update(
+    connections: &DashMap<Uuid, Vec<Connection>>,
+    event: PresenceEvent,
+) {
+    let message = WsMessage::Presence(PresenceUpdate {
+        status: event.status,
+        custom_status: event.custom_status,
+        last_active: event.timestamp,
+    });
+    
+    let message_json = match serde_json::to_string(&message) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize presence update: {}", e);
+            return;
+        }
+    };
+    
+    if let Some(conns) = connections.get(&event.user_id) {
+        for conn in conns.iter().filter(|conn| conn.device_id != event.device_id) {
+            let _ = conn.tx.send(Message::Text(message_json.clone()));
+        }
+    }
+}
+
+/// Drops every connection of a banned user. Closing the channel ends the
+/// connection's send task, which runs the usual disconnect cleanup.
+async fn handle_moderation_action(
+    connections: &DashMap<Uuid, Vec<Connection>>,
+    event: ModerationActionEvent,
+) {
+    let user_id = match (event.action, event.target) {
+        (ModerationAction::BanUser, ModerationTarget::User { user_id }) => user_id,
+        _ => return,
+    };
+    
+    if let Some((_, conns)) = connections.remove(&user_id) {
+        for conn in conns {
+            let _ = conn.tx.send(Message::Close(None));
+        }
+        
+        info!("Disconnected banned user {}", user_id);
+    }
+}
+
 async fn health_check() -> impl IntoResponse {
     (StatusCode::OK, "Gateway healthy")
 }
@@ -0,0 +1,558 @@
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+};
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{DateTime, Utc};
+use scylla::frame::value::Counter;
+use scylla::Session;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use shared::errors::AppError;
+
+use super::{
+    authenticate, get_hidden_messages, require_not_banned, verify_membership, ApiState,
+};
+
+const DEFAULT_INBOX_SIZE: usize = 20;
+const MAX_INBOX_SIZE: usize = 100;
+const MAX_PINNED_CONVERSATIONS: usize = 5;
+
+#[derive(Debug, Deserialize)]
+pub struct InboxQuery {
+    cursor: Option<String>,
+    limit: Option<usize>,
+    // Lists archived conversations instead of the main inbox
+    #[serde(default)]
+    archived: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MuteRequest {
+    until: DateTime<Utc>,
+}
+
+/// The last inbox position a page returned, as an opaque cursor.
+#[derive(Debug, Serialize, Deserialize)]
+struct InboxCursor {
+    last_message_id: Uuid,
+}
+
+impl InboxCursor {
+    fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        general_purpose::URL_SAFE_NO_PAD.encode(json)
+    }
+    
+    fn decode(cursor: &str) -> Result<Self, AppError> {
+        general_purpose::URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or(AppError::ValidationError("Invalid cursor".to_string()))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessagePreview {
+    message_id: Uuid,
+    sender_id: Uuid,
+    message_type: String,
+    content: Vec<u8>, // Client-encrypted; the SDK decrypts
+    nonce: Vec<u8>,
+    timestamp: DateTime<Utc>,
+    deleted: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InboxEntry {
+    conversation_id: Uuid,
+    conversation_type: String,
+    name: Option<String>,
+    avatar_url: Option<String>,
+    last_message_at: Option<DateTime<Utc>>,
+    // None if the message expired or the user deleted it for themselves
+    last_message: Option<MessagePreview>,
+    unread_count: i64,
+    mention_count: i64,
+    muted_until: Option<DateTime<Utc>>,
+    pinned: bool,
+    archived: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InboxPage {
+    conversations: Vec<InboxEntry>,
+    next_cursor: Option<String>,
+}
+
+struct InboxState {
+    last_message_id: Uuid,
+    last_message_at: Option<DateTime<Utc>>,
+    muted_until: Option<DateTime<Utc>>,
+    pinned: bool,
+    archived: bool,
+}
+
+/// The user's direct messages and groups, most recently active first, with
+/// pinned conversations ahead of the rest on the first page. Archived
+/// conversations are listed separately with `archived=true`. Channels aren't
+/// tracked per subscriber and are listed by the conversations service instead.
+pub async fn get_inbox(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Query(params): Query<InboxQuery>,
+) -> Result<Json<InboxPage>, AppError> {
+    let user_id = authenticate(&headers)?;
+    
+    let limit = params.limit.unwrap_or(DEFAULT_INBOX_SIZE).clamp(1, MAX_INBOX_SIZE);
+    let mut position = params.cursor.as_deref().map(InboxCursor::decode).transpose()?
+        .map(|cursor| cursor.last_message_id);
+    
+    let mut current = Vec::with_capacity(limit);
+    let mut more = false;
+    
+    if position.is_none() && !params.archived {
+        current.extend(load_pinned(&state.scylla_session, user_id).await?);
+    }
+    let pinned_count = current.len();
+    
+    // Stale index rows are skipped, so keep reading until the page is full
+    loop {
+        let remaining = (limit + pinned_count - current.len()) as i32;
+        let rows = read_inbox_rows(&state.scylla_session, user_id, position, remaining).await?;
+        let exhausted = rows.len() < remaining as usize;
+        
+        for (last_message_id, conversation_id) in rows {
+            position = Some(last_message_id);
+            
+            match load_inbox_state(&state.scylla_session, user_id, conversation_id).await? {
+                Some(inbox_state) if inbox_state.last_message_id == last_message_id => {
+                    // Pinned conversations were listed up front
+                    if inbox_state.archived == params.archived && !inbox_state.pinned {
+                        current.push((conversation_id, inbox_state));
+                    }
+                }
+                _ => remove_stale_row(&state.scylla_session, user_id, last_message_id, conversation_id).await?,
+            }
+        }
+        
+        if current.len() >= limit + pinned_count {
+            more = true;
+            break;
+        }
+        
+        if exhausted {
+            break;
+        }
+    }
+    
+    // Conversations the user has since left keep their inbox rows; drop them here
+    let conversation_ids: Vec<Uuid> = current.iter().map(|(id, _)| *id).collect();
+    let details = sqlx::query!(
+        r#"
+        SELECT c.id, c.conversation_type, c.name, c.avatar_url
+        FROM conversations c
+        JOIN group_members m ON m.group_id = c.id
+        WHERE m.user_id = $1 AND m.is_banned = false AND c.id = ANY($2)
+        "#,
+        user_id,
+        &conversation_ids
+    )
+    .fetch_all(&state.pg_pool)
+    .await?;
+    
+    let mut details: HashMap<Uuid, _> = details.into_iter().map(|row| (row.id, row)).collect();
+    
+    let mut conversations = Vec::with_capacity(current.len());
+    for (conversation_id, inbox_state) in current {
+        let conversation = match details.remove(&conversation_id) {
+            Some(conversation) => conversation,
+            None => continue,
+        };
+        
+        let (unread_count, mention_count) =
+            load_counters(&state.scylla_session, user_id, conversation_id).await?;
+        
+        let hidden = get_hidden_messages(&state.scylla_session, user_id, conversation_id).await?;
+        let last_message = if hidden.contains(&inbox_state.last_message_id) {
+            None
+        } else {
+            load_preview(&state.scylla_session, conversation_id, inbox_state.last_message_id).await?
+        };
+        
+        conversations.push(InboxEntry {
+            conversation_id,
+            conversation_type: conversation.conversation_type,
+            name: conversation.name,
+            avatar_url: conversation.avatar_url,
+            last_message_at: inbox_state.last_message_at,
+            last_message,
+            unread_count,
+            mention_count,
+            muted_until: inbox_state.muted_until,
+            pinned: inbox_state.pinned,
+            archived: inbox_state.archived,
+        });
+    }
+    
+    let next_cursor = if more {
+        position.map(|last_message_id| InboxCursor { last_message_id }.encode())
+    } else {
+        None
+    };
+    
+    Ok(Json(InboxPage {
+        conversations,
+        next_cursor,
+    }))
+}
+
+async fn read_inbox_rows(
+    session: &Session,
+    user_id: Uuid,
+    before: Option<Uuid>,
+    limit: i32,
+) -> Result<Vec<(Uuid, Uuid)>, AppError> {
+    let result = match before {
+        Some(last_message_id) => session.query(
+            r#"
+            SELECT last_message_id, conversation_id FROM messaging.user_inbox
+            WHERE user_id = ? AND last_message_id < ?
+            LIMIT ?
+            "#,
+            (user_id, last_message_id, limit),
+        ).await,
+        None => session.query(
+            r#"
+            SELECT last_message_id, conversation_id FROM messaging.user_inbox
+            WHERE user_id = ?
+            LIMIT ?
+            "#,
+            (user_id, limit),
+        ).await,
+    }
+    .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    result
+        .rows_typed::<(Uuid, Uuid)>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
+}
+
+async fn load_inbox_state(
+    session: &Session,
+    user_id: Uuid,
+    conversation_id: Uuid,
+) -> Result<Option<InboxState>, AppError> {
+    let row = session
+        .query(
+            r#"
+            SELECT last_message_uuid, last_message_at, muted_until, pinned, archived
+            FROM messaging.user_conversations
+            WHERE user_id = ? AND conversation_id = ?
+            "#,
+            (user_id, conversation_id),
+        )
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .maybe_first_row_typed::<InboxStateRow>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    Ok(row.and_then(InboxState::from_row))
+}
+
+type InboxStateRow = (Option<Uuid>, Option<DateTime<Utc>>, Option<DateTime<Utc>>, Option<bool>, Option<bool>);
+
+impl InboxState {
+    // Conversations without a message yet have nothing to show
+    fn from_row(row: InboxStateRow) -> Option<Self> {
+        let (last_message_id, last_message_at, muted_until, pinned, archived) = row;
+        
+        Some(InboxState {
+            last_message_id: last_message_id?,
+            last_message_at,
+            muted_until,
+            pinned: pinned.unwrap_or(false),
+            archived: archived.unwrap_or(false),
+        })
+    }
+}
+
+/// The user's pinned conversations, most recently active first.
+async fn load_pinned(
+    session: &Session,
+    user_id: Uuid,
+) -> Result<Vec<(Uuid, InboxState)>, AppError> {
+    let rows = session
+        .query(
+            r#"
+            SELECT conversation_id, last_message_uuid, last_message_at, muted_until, pinned, archived
+            FROM messaging.user_conversations
+            WHERE user_id = ? AND pinned = true
+            ALLOW FILTERING
+            "#,
+            (user_id,),
+        )
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .rows_typed::<(Uuid, Option<Uuid>, Option<DateTime<Utc>>, Option<DateTime<Utc>>, Option<bool>, Option<bool>)>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    let mut pinned: Vec<(Uuid, InboxState)> = rows
+        .into_iter()
+        .filter_map(|(conversation_id, last_message_id, last_message_at, muted_until, pinned, archived)| {
+            let state = InboxState::from_row((last_message_id, last_message_at, muted_until, pinned, archived))?;
+            Some((conversation_id, state))
+        })
+        .collect();
+    
+    pinned.sort_by(|(_, a), (_, b)| b.last_message_at.cmp(&a.last_message_at));
+    
+    Ok(pinned)
+}
+
+/// The processor has already moved this conversation to a newer row.
+async fn remove_stale_row(
+    session: &Session,
+    user_id: Uuid,
+    last_message_id: Uuid,
+    conversation_id: Uuid,
+) -> Result<(), AppError> {
+    session
+        .query(
+            r#"
+            DELETE FROM messaging.user_inbox
+            WHERE user_id = ? AND last_message_id = ? AND conversation_id = ?
+            "#,
+            (user_id, last_message_id, conversation_id),
+        )
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    Ok(())
+}
+
+async fn load_counters(
+    session: &Session,
+    user_id: Uuid,
+    conversation_id: Uuid,
+) -> Result<(i64, i64), AppError> {
+    let row = session
+        .query(
+            r#"
+            SELECT unread_count, mention_count FROM messaging.conversation_counters
+            WHERE user_id = ? AND conversation_id = ?
+            "#,
+            (user_id, conversation_id),
+        )
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .maybe_first_row_typed::<(Option<Counter>, Option<Counter>)>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    // A reset racing an increment can briefly dip below zero
+    let count = |counter: Option<Counter>| counter.map(|Counter(count)| count.max(0)).unwrap_or(0);
+    
+    Ok(row
+        .map(|(unread, mentions)| (count(unread), count(mentions)))
+        .unwrap_or((0, 0)))
+}
+
+async fn load_preview(
+    session: &Session,
+    conversation_id: Uuid,
+    message_id: Uuid,
+) -> Result<Option<MessagePreview>, AppError> {
+    let location = session
+        .query(
+            "SELECT bucket_id FROM messaging.message_locations WHERE message_id = ?",
+            (message_id,),
+        )
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .maybe_first_row_typed::<(i32,)>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    let bucket_id = match location {
+        Some((bucket_id,)) => bucket_id,
+        None => return Ok(None),
+    };
+    
+    let row = session
+        .query(
+            r#"
+            SELECT sender_id, message_type, content, nonce, timestamp, deleted
+            FROM messaging.messages_v2
+            WHERE conversation_id = ? AND bucket_id = ? AND message_id = ?
+            "#,
+            (conversation_id, bucket_id, message_id),
+        )
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .maybe_first_row_typed::<(Uuid, String, Vec<u8>, Vec<u8>, DateTime<Utc>, bool)>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    Ok(row.map(|(sender_id, message_type, content, nonce, timestamp, deleted)| MessagePreview {
+        message_id,
+        sender_id,
+        message_type,
+        content,
+        nonce,
+        timestamp,
+        deleted,
+    }))
+}
+
+/// Silences notifications for the conversation until the given time.
+/// Mentions still come through.
+pub async fn mute_conversation(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+    Json(payload): Json<MuteRequest>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    verify_membership(&state.pg_pool, &conversation_id, &user_id).await?;
+    
+    if payload.until <= Utc::now() {
+        return Err(AppError::ValidationError("Mute must end in the future".to_string()));
+    }
+    
+    update_preferences(
+        &state.scylla_session,
+        "UPDATE messaging.user_conversations SET muted_until = ? WHERE user_id = ? AND conversation_id = ?",
+        (payload.until, user_id, conversation_id),
+    )
+    .await?;
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn unmute_conversation(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    verify_membership(&state.pg_pool, &conversation_id, &user_id).await?;
+    
+    update_preferences(
+        &state.scylla_session,
+        "DELETE muted_until FROM messaging.user_conversations WHERE user_id = ? AND conversation_id = ?",
+        (user_id, conversation_id),
+    )
+    .await?;
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Moves the conversation out of the main inbox. It comes back on the next
+/// new message unless it is muted. Archiving also unpins.
+pub async fn archive_conversation(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    verify_membership(&state.pg_pool, &conversation_id, &user_id).await?;
+    
+    update_preferences(
+        &state.scylla_session,
+        "UPDATE messaging.user_conversations SET archived = true, pinned = false WHERE user_id = ? AND conversation_id = ?",
+        (user_id, conversation_id),
+    )
+    .await?;
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn unarchive_conversation(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    verify_membership(&state.pg_pool, &conversation_id, &user_id).await?;
+    
+    update_preferences(
+        &state.scylla_session,
+        "UPDATE messaging.user_conversations SET archived = false WHERE user_id = ? AND conversation_id = ?",
+        (user_id, conversation_id),
+    )
+    .await?;
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Keeps the conversation at the top of the inbox. Pinning an archived
+/// conversation brings it back.
+pub async fn pin_conversation(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    verify_membership(&state.pg_pool, &conversation_id, &user_id).await?;
+    
+    let pinned = load_pinned(&state.scylla_session, user_id).await?;
+    let already_pinned = pinned.iter().any(|(id, _)| *id == conversation_id);
+    
+    if !already_pinned && pinned.len() >= MAX_PINNED_CONVERSATIONS {
+        return Err(AppError::ValidationError(format!(
+            "Cannot pin more than {} conversations", MAX_PINNED_CONVERSATIONS
+        )));
+    }
+    
+    update_preferences(
+        &state.scylla_session,
+        "UPDATE messaging.user_conversations SET pinned = true, archived = false WHERE user_id = ? AND conversation_id = ?",
+        (user_id, conversation_id),
+    )
+    .await?;
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn unpin_conversation(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    verify_membership(&state.pg_pool, &conversation_id, &user_id).await?;
+    
+    update_preferences(
+        &state.scylla_session,
+        "UPDATE messaging.user_conversations SET pinned = false WHERE user_id = ? AND conversation_id = ?",
+        (user_id, conversation_id),
+    )
+    .await?;
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn update_preferences(
+    session: &Session,
+    query: &str,
+    values: impl scylla::frame::value::ValueList,
+) -> Result<(), AppError> {
+    session
+        .query(query, values)
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    Ok(())
+}
@@ -0,0 +1,742 @@
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::{delete, get, post, put},
+    Router,
+};
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{DateTime, Utc};
+use scylla::frame::value::Counter;
+use scylla::Session;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+
+use shared::errors::AppError;
+use shared::models::{MessageEdit, PinnedMessage, PresenceVisibility};
+use shared::restrictions::RestrictionCache;
+
+mod inbox;
+mod relationships;
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+
+pub struct ApiState {
+    pub scylla_session: Arc<Session>,
+    pub pg_pool: sqlx::PgPool,
+    pub system_message_key: String, // Base64 Ed25519 public key
+    pub restrictions: Arc<RestrictionCache>,
+}
+
+pub async fn serve(state: Arc<ApiState>) -> Result<(), Box<dyn std::error::Error>> {
+    let app = Router::new()
+        .route("/health", get(health_check))
+        .route("/messages/:message_id/edits", get(get_edit_history))
+        .route("/inbox", get(inbox::get_inbox))
+        .route("/inbox/:conversation_id/mute", put(inbox::mute_conversation))
+        .route("/inbox/:conversation_id/mute", delete(inbox::unmute_conversation))
+        .route("/inbox/:conversation_id/archive", put(inbox::archive_conversation))
+        .route("/inbox/:conversation_id/archive", delete(inbox::unarchive_conversation))
+        .route("/inbox/:conversation_id/pin", put(inbox::pin_conversation))
+        .route("/inbox/:conversation_id/pin", delete(inbox::unpin_conversation))
+        .route("/conversations/:conversation_id/messages", get(get_message_history))
+        .route("/conversations/:conversation_id/messages/:message_id/views", get(get_view_count))
+        .route("/conversations/:conversation_id/pins", get(list_pinned_messages))
+        .route("/conversations/:conversation_id/threads/:root_id", get(get_thread))
+        .route("/system-messages/signing-key", get(get_system_message_key))
+        .route("/settings/privacy", get(get_privacy_settings))
+        .route("/settings/privacy", put(update_privacy_settings))
+        .route("/relationships/friends", get(relationships::list_friends))
+        .route("/relationships/friends/:user_id", delete(relationships::remove_friend))
+        .route("/relationships/friend-requests", get(relationships::list_friend_requests))
+        .route("/relationships/friend-requests", post(relationships::send_friend_request))
+        .route("/relationships/friend-requests/:request_id", delete(relationships::cancel_friend_request))
+        .route("/relationships/friend-requests/:request_id/accept", post(relationships::accept_friend_request))
+        .route("/relationships/friend-requests/:request_id/decline", post(relationships::decline_friend_request))
+        .route("/relationships/contacts", get(relationships::list_contacts))
+        .route("/relationships/contacts/:user_id", put(relationships::add_contact))
+        .route("/relationships/contacts/:user_id", delete(relationships::remove_contact))
+        .route("/relationships/blocks", get(relationships::list_blocks))
+        .route("/relationships/blocks/:user_id", put(relationships::block_user))
+        .route("/relationships/blocks/:user_id", delete(relationships::unblock_user))
+        .with_state(state)
+        .layer(tower_http::trace::TraceLayer::new_for_http())
+        .layer(tower_http::cors::CorsLayer::permissive());
+    
+    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], 3002));
+    info!("Messaging API listening on {}", addr);
+    
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await?;
+    
+    Ok(())
+}
+
+async fn health_check() -> impl IntoResponse {
+    (StatusCode::OK, "Messaging service healthy")
+}
+
+#[derive(Debug, Serialize)]
+struct SigningKeyResponse {
+    public_key: String,
+}
+
+async fn get_system_message_key(State(state): State<Arc<ApiState>>) -> Json<SigningKeyResponse> {
+    Json(SigningKeyResponse {
+        public_key: state.system_message_key.clone(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct EditHistoryResponse {
+    message_id: Uuid,
+    revisions: Vec<MessageEdit>,
+}
+
+async fn get_edit_history(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(message_id): Path<Uuid>,
+) -> Result<Json<EditHistoryResponse>, AppError> {
+    let user_id = authenticate(&headers)?;
+    
+    // Edit history exposes earlier revisions, so it is for moderators only
+    verify_moderator_role(&state.pg_pool, &user_id).await?;
+    
+    let rows = state.scylla_session
+        .query(
+            r#"
+            SELECT edited_at, conversation_id, editor_id, previous_content, previous_nonce
+            FROM messaging.message_edits
+            WHERE message_id = ?
+            "#,
+            (message_id,),
+        )
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .rows_typed::<(DateTime<Utc>, Uuid, Uuid, Vec<u8>, Vec<u8>)>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    let mut revisions = Vec::new();
+    for row in rows {
+        let (edited_at, conversation_id, editor_id, previous_content, previous_nonce) =
+            row.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        
+        revisions.push(MessageEdit {
+            message_id,
+            conversation_id,
+            editor_id,
+            previous_content,
+            previous_nonce,
+            edited_at,
+        });
+    }
+    
+    Ok(Json(EditHistoryResponse {
+        message_id,
+        revisions,
+    }))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum PageDirection {
+    Before,
+    After,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    cursor: Option<String>,
+    direction: Option<PageDirection>,
+    limit: Option<usize>,
+}
+
+/// Position of the last message a page returned. Serialized into an opaque
+/// cursor so clients never depend on the bucket layout.
+#[derive(Debug, Serialize, Deserialize)]
+struct HistoryCursor {
+    bucket_id: i32,
+    message_id: Uuid,
+}
+
+impl HistoryCursor {
+    fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        general_purpose::URL_SAFE_NO_PAD.encode(json)
+    }
+    
+    fn decode(cursor: &str) -> Result<Self, AppError> {
+        general_purpose::URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or(AppError::ValidationError("Invalid cursor".to_string()))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct HistoryMessage {
+    message_id: Uuid,
+    sender_id: Uuid,
+    message_type: String,
+    content: Vec<u8>, // Client-encrypted; the SDK decrypts
+    nonce: Vec<u8>,
+    reply_to: Option<Uuid>,
+    timestamp: DateTime<Utc>,
+    edited: bool,
+    deleted: bool,
+    encryption_version: i32,
+    // Set on system messages; verify against GET /system-messages/signing-key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Serialize)]
+struct HistoryPage {
+    messages: Vec<HistoryMessage>,
+    next_cursor: Option<String>,
+}
+
+type HistoryRow = (Uuid, Uuid, String, Vec<u8>, Vec<u8>, Option<Uuid>, DateTime<Utc>, bool, bool, i32, Option<Vec<u8>>);
+
+async fn get_message_history(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+    Query(params): Query<HistoryQuery>,
+) -> Result<Json<HistoryPage>, AppError> {
+    let user_id = authenticate(&headers)?;
+    verify_membership(&state.pg_pool, &conversation_id, &user_id).await?;
+    
+    let direction = params.direction.unwrap_or(PageDirection::Before);
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let cursor = params.cursor.as_deref().map(HistoryCursor::decode).transpose()?;
+    
+    let hidden = get_hidden_messages(&state.scylla_session, user_id, conversation_id).await?;
+    
+    // Paging forward without a cursor starts from the oldest day; paging
+    // backward starts from the newest. Within a bucket the cursor's message
+    // is exclusive, later buckets are read from their edge.
+    let (mut bucket, mut after_message) = match cursor {
+        Some(cursor) => (Some(cursor.bucket_id), Some(cursor.message_id)),
+        None => (
+            next_bucket(&state.scylla_session, conversation_id, None, direction).await?,
+            None,
+        ),
+    };
+    
+    let mut messages = Vec::with_capacity(limit);
+    let mut last_position = None;
+    
+    while let Some(bucket_id) = bucket {
+        let remaining = (limit - messages.len()) as i32;
+        let rows = read_bucket(
+            &state.scylla_session,
+            conversation_id,
+            bucket_id,
+            after_message,
+            direction,
+            remaining,
+        )
+        .await?;
+        
+        let exhausted = rows.len() < remaining as usize;
+        
+        for (message_id, sender_id, message_type, content, nonce, reply_to, timestamp, edited, deleted, encryption_version, signature) in rows {
+            last_position = Some(HistoryCursor { bucket_id, message_id });
+            
+            if hidden.contains(&message_id) {
+                continue;
+            }
+            
+            messages.push(HistoryMessage {
+                message_id,
+                sender_id,
+                message_type,
+                content,
+                nonce,
+                reply_to,
+                timestamp,
+                edited,
+                deleted,
+                encryption_version,
+                signature,
+            });
+        }
+        
+        if messages.len() >= limit {
+            break;
+        }
+        
+        if !exhausted {
+            // Hidden messages were skipped; keep reading the same bucket
+            after_message = last_position.as_ref().map(|p| p.message_id);
+            continue;
+        }
+        
+        bucket = next_bucket(&state.scylla_session, conversation_id, Some(bucket_id), direction).await?;
+        after_message = None;
+    }
+    
+    // A short page means we ran out of buckets in this direction
+    let next_cursor = if messages.len() >= limit {
+        last_position.map(|p| p.encode())
+    } else {
+        None
+    };
+    
+    Ok(Json(HistoryPage {
+        messages,
+        next_cursor,
+    }))
+}
+
+async fn read_bucket(
+    session: &Session,
+    conversation_id: Uuid,
+    bucket_id: i32,
+    after_message: Option<Uuid>,
+    direction: PageDirection,
+    limit: i32,
+) -> Result<Vec<HistoryRow>, AppError> {
+    const COLUMNS: &str = "message_id, sender_id, message_type, content, nonce, \
+                           reply_to, timestamp, edited, deleted, encryption_version, signature";
+    
+    let (comparison, order) = match direction {
+        PageDirection::Before => ("<", "DESC"),
+        PageDirection::After => (">", "ASC"),
+    };
+    
+    let result = match after_message {
+        Some(message_id) => {
+            let query = format!(
+                "SELECT {} FROM messaging.messages_v2 \
+                 WHERE conversation_id = ? AND bucket_id = ? AND message_id {} ? \
+                 ORDER BY message_id {} LIMIT ?",
+                COLUMNS, comparison, order
+            );
+            session.query(query, (conversation_id, bucket_id, message_id, limit)).await
+        }
+        None => {
+            let query = format!(
+                "SELECT {} FROM messaging.messages_v2 \
+                 WHERE conversation_id = ? AND bucket_id = ? \
+                 ORDER BY message_id {} LIMIT ?",
+                COLUMNS, order
+            );
+            session.query(query, (conversation_id, bucket_id, limit)).await
+        }
+    }
+    .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    result
+        .rows_typed::<HistoryRow>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
+}
+
+/// Finds the closest day after (or before) `from` that actually has messages,
+/// so long gaps in a conversation cost one query instead of one per day.
+async fn next_bucket(
+    session: &Session,
+    conversation_id: Uuid,
+    from: Option<i32>,
+    direction: PageDirection,
+) -> Result<Option<i32>, AppError> {
+    let result = match (from, direction) {
+        (Some(bucket_id), PageDirection::Before) => session.query(
+            r#"
+            SELECT bucket_id FROM messaging.conversation_buckets
+            WHERE conversation_id = ? AND bucket_id < ?
+            ORDER BY bucket_id DESC LIMIT 1
+            "#,
+            (conversation_id, bucket_id),
+        ).await,
+        (Some(bucket_id), PageDirection::After) => session.query(
+            r#"
+            SELECT bucket_id FROM messaging.conversation_buckets
+            WHERE conversation_id = ? AND bucket_id > ?
+            ORDER BY bucket_id ASC LIMIT 1
+            "#,
+            (conversation_id, bucket_id),
+        ).await,
+        (None, PageDirection::Before) => session.query(
+            r#"
+            SELECT bucket_id FROM messaging.conversation_buckets
+            WHERE conversation_id = ?
+            ORDER BY bucket_id DESC LIMIT 1
+            "#,
+            (conversation_id,),
+        ).await,
+        (None, PageDirection::After) => session.query(
+            r#"
+            SELECT bucket_id FROM messaging.conversation_buckets
+            WHERE conversation_id = ?
+            ORDER BY bucket_id ASC LIMIT 1
+            "#,
+            (conversation_id,),
+        ).await,
+    }
+    .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    let row = result
+        .maybe_first_row_typed::<(i32,)>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    Ok(row.map(|(bucket_id,)| bucket_id))
+}
+
+async fn get_hidden_messages(
+    session: &Session,
+    user_id: Uuid,
+    conversation_id: Uuid,
+) -> Result<HashSet<Uuid>, AppError> {
+    let rows = session
+        .query(
+            r#"
+            SELECT message_id FROM messaging.hidden_messages
+            WHERE user_id = ? AND conversation_id = ?
+            "#,
+            (user_id, conversation_id),
+        )
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .rows_typed::<(Uuid,)>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    rows.map(|row| row.map(|(message_id,)| message_id))
+        .collect::<Result<HashSet<_>, _>>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
+}
+
+async fn list_pinned_messages(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<Uuid>,
+) -> Result<Json<Vec<PinnedMessage>>, AppError> {
+    let user_id = authenticate(&headers)?;
+    verify_membership(&state.pg_pool, &conversation_id, &user_id).await?;
+    
+    let rows = state.scylla_session
+        .query(
+            r#"
+            SELECT message_id, pinned_by, pinned_at
+            FROM messaging.pinned_messages
+            WHERE conversation_id = ?
+            "#,
+            (conversation_id,),
+        )
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .rows_typed::<(Uuid, Uuid, DateTime<Utc>)>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    let mut pins = Vec::new();
+    for row in rows {
+        let (message_id, pinned_by, pinned_at) =
+            row.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        
+        pins.push(PinnedMessage {
+            conversation_id,
+            message_id,
+            pinned_by,
+            pinned_at,
+        });
+    }
+    
+    // Most recently pinned first
+    pins.sort_by(|a, b| b.pinned_at.cmp(&a.pinned_at));
+    
+    Ok(Json(pins))
+}
+
+#[derive(Debug, Serialize)]
+struct ThreadMessage {
+    message_id: Uuid,
+    sender_id: Uuid,
+    content: Vec<u8>, // Client-encrypted
+    nonce: Vec<u8>,
+    timestamp: DateTime<Utc>,
+    edited: bool,
+    deleted: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ThreadResponse {
+    conversation_id: Uuid,
+    root_id: Uuid,
+    replies: Vec<ThreadMessage>,
+}
+
+async fn get_thread(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path((conversation_id, root_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<ThreadResponse>, AppError> {
+    let user_id = authenticate(&headers)?;
+    verify_membership(&state.pg_pool, &conversation_id, &user_id).await?;
+    
+    let replies = state.scylla_session
+        .query(
+            r#"
+            SELECT message_id, bucket_id
+            FROM messaging.message_threads
+            WHERE conversation_id = ? AND root_id = ?
+            "#,
+            (conversation_id, root_id),
+        )
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .rows_typed::<(Uuid, i32)>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    let mut messages = Vec::new();
+    for reply in replies {
+        let (message_id, bucket_id) = reply.map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        
+        let row = state.scylla_session
+            .query(
+                r#"
+                SELECT sender_id, content, nonce, timestamp, edited, deleted
+                FROM messaging.messages_v2
+                WHERE conversation_id = ? AND bucket_id = ? AND message_id = ?
+                "#,
+                (conversation_id, bucket_id, message_id),
+            )
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?
+            .maybe_first_row_typed::<(Uuid, Vec<u8>, Vec<u8>, DateTime<Utc>, bool, bool)>()
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        
+        if let Some((sender_id, content, nonce, timestamp, edited, deleted)) = row {
+            messages.push(ThreadMessage {
+                message_id,
+                sender_id,
+                content,
+                nonce,
+                timestamp,
+                edited,
+                deleted,
+            });
+        }
+    }
+    
+    Ok(Json(ThreadResponse {
+        conversation_id,
+        root_id,
+        replies: messages,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+struct ViewCountResponse {
+    message_id: Uuid,
+    views: i64,
+}
+
+async fn get_view_count(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path((conversation_id, message_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<ViewCountResponse>, AppError> {
+    let user_id = authenticate(&headers)?;
+    
+    verify_membership(&state.pg_pool, &conversation_id, &user_id).await?;
+    
+    let location = state.scylla_session
+        .query(
+            "SELECT conversation_id FROM messaging.message_locations WHERE message_id = ?",
+            (message_id,),
+        )
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .maybe_first_row_typed::<(Uuid,)>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+    
+    if location.map(|(id,)| id) != Some(conversation_id) {
+        return Err(AppError::NotFound("Message not found".to_string()));
+    }
+    
+    let views = state.scylla_session
+        .query(
+            "SELECT views FROM messaging.message_views WHERE message_id = ?",
+            (message_id,),
+        )
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .maybe_first_row_typed::<(Counter,)>()
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+        .map(|(views,)| views.0)
+        .unwrap_or(0);
+    
+    Ok(Json(ViewCountResponse { message_id, views }))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PrivacySettings {
+    send_read_receipts: bool,
+    // Enforced by the presence service
+    #[serde(default)]
+    last_seen_visibility: PresenceVisibility,
+    #[serde(default)]
+    online_visibility: PresenceVisibility,
+}
+
+async fn get_privacy_settings(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+) -> Result<Json<PrivacySettings>, AppError> {
+    let user_id = authenticate(&headers)?;
+    
+    let settings = sqlx::query!(
+        r#"
+        SELECT send_read_receipts, last_seen_visibility, online_visibility
+        FROM user_privacy_settings WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await?;
+    
+    let settings = match settings {
+        Some(s) => PrivacySettings {
+            send_read_receipts: s.send_read_receipts,
+            last_seen_visibility: s.last_seen_visibility.parse().unwrap_or_default(),
+            online_visibility: s.online_visibility.parse().unwrap_or_default(),
+        },
+        None => PrivacySettings {
+            send_read_receipts: true,
+            last_seen_visibility: PresenceVisibility::default(),
+            online_visibility: PresenceVisibility::default(),
+        },
+    };
+    
+    Ok(Json(settings))
+}
+
+async fn update_privacy_settings(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Json(payload): Json<PrivacySettings>,
+) -> Result<Json<PrivacySettings>, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    sqlx::query!(
+        r#"
+        INSERT INTO user_privacy_settings
+            (user_id, send_read_receipts, last_seen_visibility, online_visibility, updated_at)
+        VALUES ($1, $2, $3, $4, NOW())
+        ON CONFLICT (user_id) DO UPDATE
+        SET send_read_receipts = EXCLUDED.send_read_receipts,
+            last_seen_visibility = EXCLUDED.last_seen_visibility,
+            online_visibility = EXCLUDED.online_visibility,
+            updated_at = NOW()
+        "#,
+        user_id,
+        payload.send_read_receipts,
+        payload.last_seen_visibility.as_str(),
+        payload.online_visibility.as_str()
+    )
+    .execute(&state.pg_pool)
+    .await?;
+    
+    Ok(Json(payload))
+}
+
+async fn verify_membership(
+    db_pool: &sqlx::PgPool,
+    conversation_id: &Uuid,
+    user_id: &Uuid,
+) -> Result<(), AppError> {
+    let is_member = sqlx::query!(
+        r#"
+        SELECT 1 FROM group_members 
+        WHERE group_id = $1 AND user_id = $2 AND is_banned = false
+        "#,
+        conversation_id,
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await?;
+    
+    if is_member.is_none() {
+        return Err(AppError::Forbidden("Not a member of this conversation".to_string()));
+    }
+    
+    Ok(())
+}
+
+/// Banned users keep their tokens until they expire but can't change anything.
+async fn require_not_banned(restrictions: &RestrictionCache, user_id: Uuid) -> Result<(), AppError> {
+    if restrictions.is_banned(user_id).await? {
+        return Err(AppError::Forbidden("Account suspended".to_string()));
+    }
+    
+    Ok(())
+}
+
+/// For requests that reach other users, which muted users can't make either.
+async fn require_can_send(restrictions: &RestrictionCache, user_id: Uuid) -> Result<(), AppError> {
+    require_not_banned(restrictions, user_id).await?;
+    
+    if !restrictions.can_send(user_id).await? {
+        return Err(AppError::Forbidden("You are muted".to_string()));
+    }
+    
+    Ok(())
+}
+
+async fn verify_moderator_role(db_pool: &sqlx::PgPool, user_id: &Uuid) -> Result<(), AppError> {
+    let is_moderator = sqlx::query!(
+        r#"
+        SELECT 1 FROM users
+        WHERE id = $1 AND (is_admin = true OR is_moderator = true)
+        "#,
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await?;
+    
+    if is_moderator.is_none() {
+        return Err(AppError::Forbidden("Insufficient permissions".to_string()));
+    }
+    
+    Ok(())
+}
+
+fn authenticate(headers: &HeaderMap) -> Result<Uuid, AppError> {
+    use jsonwebtoken::{decode, DecodingKey, Validation};
+    
+    let token = headers
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .ok_or(AppError::Unauthorized("Missing token".to_string()))?;
+    
+    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    let token_data = decode::<TokenClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::default(),
+    )
+    .map_err(|_| AppError::Unauthorized("Invalid token".to_string()))?;
+    
+    Ok(token_data.claims.sub)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenClaims {
+    sub: Uuid,
+    exp: usize,
+    iat: usize,
+    device_id: String,
+    session_id: Uuid,
+}
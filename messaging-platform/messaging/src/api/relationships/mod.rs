@@ -0,0 +1,658 @@
+use axum::{
+    extract::{Json, Path, State},
+    http::{HeaderMap, StatusCode},
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+
+use shared::errors::AppError;
+use shared::models::{FriendRequestStatus, RelationshipType};
+
+use super::{authenticate, require_can_send, require_not_banned, ApiState};
+
+const MAX_FRIENDS: i64 = 5000;
+const MAX_CONTACTS: i64 = 5000;
+// How long a declined request keeps the sender from asking again
+const DECLINE_COOLDOWN_DAYS: i64 = 7;
+
+#[derive(Debug, Deserialize)]
+pub struct SendFriendRequest {
+    user_id: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FriendRequest {
+    id: Uuid,
+    sender_id: Uuid,
+    recipient_id: Uuid,
+    status: FriendRequestStatus,
+    created_at: DateTime<Utc>,
+    responded_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FriendRequestList {
+    incoming: Vec<FriendRequest>,
+    outgoing: Vec<FriendRequest>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelatedUser {
+    user_id: Uuid,
+    since: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelatedUserList {
+    users: Vec<RelatedUser>,
+}
+
+pub async fn list_friends(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+) -> Result<Json<RelatedUserList>, AppError> {
+    let user_id = authenticate(&headers)?;
+    list_related(&state.pg_pool, user_id, RelationshipType::Friend).await
+}
+
+/// Ends the friendship for both users.
+pub async fn remove_friend(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(friend_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    let removed = sqlx::query!(
+        r#"
+        DELETE FROM user_relationships
+        WHERE relationship_type = 'friend'
+          AND ((user_id = $1 AND related_user_id = $2)
+            OR (user_id = $2 AND related_user_id = $1))
+        "#,
+        user_id,
+        friend_id
+    )
+    .execute(&state.pg_pool)
+    .await?;
+    
+    if removed.rows_affected() == 0 {
+        return Err(AppError::NotFound("Not friends with this user".to_string()));
+    }
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Pending requests to and from the user.
+pub async fn list_friend_requests(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+) -> Result<Json<FriendRequestList>, AppError> {
+    let user_id = authenticate(&headers)?;
+    
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, sender_id, recipient_id, status, created_at, responded_at
+        FROM friend_requests
+        WHERE (sender_id = $1 OR recipient_id = $1) AND status = 'pending'
+        ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(&state.pg_pool)
+    .await?;
+    
+    let mut list = FriendRequestList {
+        incoming: Vec::new(),
+        outgoing: Vec::new(),
+    };
+    
+    for row in rows {
+        let request = FriendRequest {
+            id: row.id,
+            sender_id: row.sender_id,
+            recipient_id: row.recipient_id,
+            status: row.status.parse().map_err(AppError::DatabaseError)?,
+            created_at: row.created_at,
+            responded_at: row.responded_at,
+        };
+        
+        if request.recipient_id == user_id {
+            list.incoming.push(request);
+        } else {
+            list.outgoing.push(request);
+        }
+    }
+    
+    Ok(Json(list))
+}
+
+/// Asks another user to be friends. If they already asked, this accepts
+/// their request instead.
+pub async fn send_friend_request(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Json(payload): Json<SendFriendRequest>,
+) -> Result<(StatusCode, Json<FriendRequest>), AppError> {
+    let user_id = authenticate(&headers)?;
+    require_can_send(&state.restrictions, user_id).await?;
+    let recipient_id = payload.user_id;
+    
+    if recipient_id == user_id {
+        return Err(AppError::ValidationError("Cannot send a friend request to yourself".to_string()));
+    }
+    
+    verify_user_exists(&state.pg_pool, recipient_id).await?;
+    
+    // Same answer whichever side blocked, so blocks stay private
+    if is_blocked_between(&state.pg_pool, user_id, recipient_id).await? {
+        return Err(AppError::Forbidden("Cannot send a friend request to this user".to_string()));
+    }
+    
+    if has_relationship(&state.pg_pool, user_id, recipient_id, RelationshipType::Friend).await? {
+        return Err(AppError::Conflict("Already friends with this user".to_string()));
+    }
+    
+    if let Some(reverse) = load_pending(&state.pg_pool, recipient_id, user_id).await? {
+        let request = accept(&state.pg_pool, reverse).await?;
+        return Ok((StatusCode::OK, Json(request)));
+    }
+    
+    let recently_declined = sqlx::query!(
+        r#"
+        SELECT 1 AS "declined!" FROM friend_requests
+        WHERE sender_id = $1 AND recipient_id = $2
+          AND status = 'declined' AND responded_at > $3
+        LIMIT 1
+        "#,
+        user_id,
+        recipient_id,
+        Utc::now() - Duration::days(DECLINE_COOLDOWN_DAYS)
+    )
+    .fetch_optional(&state.pg_pool)
+    .await?;
+    
+    if recently_declined.is_some() {
+        return Err(AppError::Forbidden("Cannot send a friend request to this user yet".to_string()));
+    }
+    
+    ensure_capacity(&state.pg_pool, user_id, RelationshipType::Friend, MAX_FRIENDS).await?;
+    
+    let request_id = Uuid::new_v4();
+    
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO friend_requests (id, sender_id, recipient_id)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (sender_id, recipient_id) WHERE status = 'pending' DO NOTHING
+        RETURNING created_at
+        "#,
+        request_id,
+        user_id,
+        recipient_id
+    )
+    .fetch_optional(&state.pg_pool)
+    .await?;
+    
+    let created_at = match inserted {
+        Some(row) => row.created_at,
+        None => return Err(AppError::Conflict("Friend request already sent".to_string())),
+    };
+    
+    info!("User {} sent a friend request to {}", user_id, recipient_id);
+    
+    Ok((StatusCode::CREATED, Json(FriendRequest {
+        id: request_id,
+        sender_id: user_id,
+        recipient_id,
+        status: FriendRequestStatus::Pending,
+        created_at,
+        responded_at: None,
+    })))
+}
+
+pub async fn accept_friend_request(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(request_id): Path<Uuid>,
+) -> Result<Json<FriendRequest>, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    let request = load_request(&state.pg_pool, request_id).await?
+        .filter(|request| request.recipient_id == user_id)
+        .ok_or(AppError::NotFound("Friend request not found".to_string()))?;
+    
+    if request.status != FriendRequestStatus::Pending {
+        return Err(AppError::Conflict("Friend request is no longer pending".to_string()));
+    }
+    
+    Ok(Json(accept(&state.pg_pool, request).await?))
+}
+
+/// Turns the request down. The sender isn't told and can't ask again for a while.
+pub async fn decline_friend_request(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(request_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    let declined = sqlx::query!(
+        r#"
+        UPDATE friend_requests SET status = 'declined', responded_at = NOW()
+        WHERE id = $1 AND recipient_id = $2 AND status = 'pending'
+        "#,
+        request_id,
+        user_id
+    )
+    .execute(&state.pg_pool)
+    .await?;
+    
+    if declined.rows_affected() == 0 {
+        return Err(AppError::NotFound("Friend request not found".to_string()));
+    }
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Withdraws a request the user sent.
+pub async fn cancel_friend_request(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(request_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    let cancelled = sqlx::query!(
+        r#"
+        UPDATE friend_requests SET status = 'cancelled', responded_at = NOW()
+        WHERE id = $1 AND sender_id = $2 AND status = 'pending'
+        "#,
+        request_id,
+        user_id
+    )
+    .execute(&state.pg_pool)
+    .await?;
+    
+    if cancelled.rows_affected() == 0 {
+        return Err(AppError::NotFound("Friend request not found".to_string()));
+    }
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The user's own contact list. Contacts don't need the other user's
+/// consent; they only widen what the user shares with them.
+pub async fn list_contacts(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+) -> Result<Json<RelatedUserList>, AppError> {
+    let user_id = authenticate(&headers)?;
+    list_related(&state.pg_pool, user_id, RelationshipType::Contact).await
+}
+
+pub async fn add_contact(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(contact_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    if contact_id == user_id {
+        return Err(AppError::ValidationError("Cannot add yourself as a contact".to_string()));
+    }
+    
+    verify_user_exists(&state.pg_pool, contact_id).await?;
+    ensure_capacity(&state.pg_pool, user_id, RelationshipType::Contact, MAX_CONTACTS).await?;
+    
+    insert_relationship(&state.pg_pool, user_id, contact_id, RelationshipType::Contact).await?;
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn remove_contact(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(contact_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    if !delete_relationship(&state.pg_pool, user_id, contact_id, RelationshipType::Contact).await? {
+        return Err(AppError::NotFound("Not in your contacts".to_string()));
+    }
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_blocks(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+) -> Result<Json<RelatedUserList>, AppError> {
+    let user_id = authenticate(&headers)?;
+    list_related(&state.pg_pool, user_id, RelationshipType::Blocked).await
+}
+
+/// Blocks the user: ends any friendship and pending requests between the
+/// two, and stops direct messages, group adds and presence in both directions.
+pub async fn block_user(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(blocked_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    if blocked_id == user_id {
+        return Err(AppError::ValidationError("Cannot block yourself".to_string()));
+    }
+    
+    verify_user_exists(&state.pg_pool, blocked_id).await?;
+    
+    let mut tx = state.pg_pool.begin().await?;
+    
+    sqlx::query!(
+        r#"
+        INSERT INTO user_relationships (user_id, related_user_id, relationship_type)
+        VALUES ($1, $2, 'blocked')
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        blocked_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    
+    sqlx::query!(
+        r#"
+        DELETE FROM user_relationships
+        WHERE relationship_type = 'friend'
+          AND ((user_id = $1 AND related_user_id = $2)
+            OR (user_id = $2 AND related_user_id = $1))
+        "#,
+        user_id,
+        blocked_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    
+    sqlx::query!(
+        r#"
+        UPDATE friend_requests SET status = 'cancelled', responded_at = NOW()
+        WHERE status = 'pending'
+          AND ((sender_id = $1 AND recipient_id = $2)
+            OR (sender_id = $2 AND recipient_id = $1))
+        "#,
+        user_id,
+        blocked_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    
+    tx.commit().await?;
+    
+    info!("User {} blocked {}", user_id, blocked_id);
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Lifts the block. A friendship it ended is not restored.
+pub async fn unblock_user(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+    Path(blocked_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    require_not_banned(&state.restrictions, user_id).await?;
+    
+    if !delete_relationship(&state.pg_pool, user_id, blocked_id, RelationshipType::Blocked).await? {
+        return Err(AppError::NotFound("User is not blocked".to_string()));
+    }
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Marks the request accepted and makes the two users friends.
+async fn accept(pg_pool: &PgPool, request: FriendRequest) -> Result<FriendRequest, AppError> {
+    ensure_capacity(pg_pool, request.sender_id, RelationshipType::Friend, MAX_FRIENDS).await?;
+    ensure_capacity(pg_pool, request.recipient_id, RelationshipType::Friend, MAX_FRIENDS).await?;
+    
+    let mut tx = pg_pool.begin().await?;
+    
+    let accepted = sqlx::query!(
+        r#"
+        UPDATE friend_requests SET status = 'accepted', responded_at = NOW()
+        WHERE id = $1 AND status = 'pending'
+        RETURNING responded_at
+        "#,
+        request.id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    
+    let responded_at = match accepted {
+        Some(row) => row.responded_at,
+        None => return Err(AppError::Conflict("Friend request is no longer pending".to_string())),
+    };
+    
+    sqlx::query!(
+        r#"
+        INSERT INTO user_relationships (user_id, related_user_id, relationship_type)
+        VALUES ($1, $2, 'friend'), ($2, $1, 'friend')
+        ON CONFLICT DO NOTHING
+        "#,
+        request.sender_id,
+        request.recipient_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    
+    tx.commit().await?;
+    
+    info!("Users {} and {} are now friends", request.sender_id, request.recipient_id);
+    
+    Ok(FriendRequest {
+        status: FriendRequestStatus::Accepted,
+        responded_at,
+        ..request
+    })
+}
+
+async fn list_related(
+    pg_pool: &PgPool,
+    user_id: Uuid,
+    relationship: RelationshipType,
+) -> Result<Json<RelatedUserList>, AppError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT related_user_id, created_at FROM user_relationships
+        WHERE user_id = $1 AND relationship_type = $2
+        ORDER BY created_at DESC
+        "#,
+        user_id,
+        relationship.as_str()
+    )
+    .fetch_all(pg_pool)
+    .await?;
+    
+    Ok(Json(RelatedUserList {
+        users: rows
+            .into_iter()
+            .map(|row| RelatedUser {
+                user_id: row.related_user_id,
+                since: row.created_at,
+            })
+            .collect(),
+    }))
+}
+
+async fn load_request(pg_pool: &PgPool, request_id: Uuid) -> Result<Option<FriendRequest>, AppError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, sender_id, recipient_id, status, created_at, responded_at
+        FROM friend_requests WHERE id = $1
+        "#,
+        request_id
+    )
+    .fetch_optional(pg_pool)
+    .await?;
+    
+    row.map(|row| {
+        Ok(FriendRequest {
+            id: row.id,
+            sender_id: row.sender_id,
+            recipient_id: row.recipient_id,
+            status: row.status.parse().map_err(AppError::DatabaseError)?,
+            created_at: row.created_at,
+            responded_at: row.responded_at,
+        })
+    })
+    .transpose()
+}
+
+async fn load_pending(
+    pg_pool: &PgPool,
+    sender_id: Uuid,
+    recipient_id: Uuid,
+) -> Result<Option<FriendRequest>, AppError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, created_at FROM friend_requests
+        WHERE sender_id = $1 AND recipient_id = $2 AND status = 'pending'
+        "#,
+        sender_id,
+        recipient_id
+    )
+    .fetch_optional(pg_pool)
+    .await?;
+    
+    Ok(row.map(|row| FriendRequest {
+        id: row.id,
+        sender_id,
+        recipient_id,
+        status: FriendRequestStatus::Pending,
+        created_at: row.created_at,
+        responded_at: None,
+    }))
+}
+
+async fn has_relationship(
+    pg_pool: &PgPool,
+    user_id: Uuid,
+    related_user_id: Uuid,
+    relationship: RelationshipType,
+) -> Result<bool, AppError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT 1 AS "exists!" FROM user_relationships
+        WHERE user_id = $1 AND related_user_id = $2 AND relationship_type = $3
+        "#,
+        user_id,
+        related_user_id,
+        relationship.as_str()
+    )
+    .fetch_optional(pg_pool)
+    .await?;
+    
+    Ok(row.is_some())
+}
+
+/// Whether either user has blocked the other.
+async fn is_blocked_between(pg_pool: &PgPool, user_id: Uuid, other_id: Uuid) -> Result<bool, AppError> {
+    Ok(has_relationship(pg_pool, user_id, other_id, RelationshipType::Blocked).await?
+        || has_relationship(pg_pool, other_id, user_id, RelationshipType::Blocked).await?)
+}
+
+async fn insert_relationship(
+    pg_pool: &PgPool,
+    user_id: Uuid,
+    related_user_id: Uuid,
+    relationship: RelationshipType,
+) -> Result<(), AppError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO user_relationships (user_id, related_user_id, relationship_type)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        related_user_id,
+        relationship.as_str()
+    )
+    .execute(pg_pool)
+    .await?;
+    
+    Ok(())
+}
+
+/// Returns whether there was anything to delete.
+async fn delete_relationship(
+    pg_pool: &PgPool,
+    user_id: Uuid,
+    related_user_id: Uuid,
+    relationship: RelationshipType,
+) -> Result<bool, AppError> {
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM user_relationships
+        WHERE user_id = $1 AND related_user_id = $2 AND relationship_type = $3
+        "#,
+        user_id,
+        related_user_id,
+        relationship.as_str()
+    )
+    .execute(pg_pool)
+    .await?;
+    
+    Ok(deleted.rows_affected() > 0)
+}
+
+async fn ensure_capacity(
+    pg_pool: &PgPool,
+    user_id: Uuid,
+    relationship: RelationshipType,
+    limit: i64,
+) -> Result<(), AppError> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM user_relationships
+        WHERE user_id = $1 AND relationship_type = $2
+        "#,
+        user_id,
+        relationship.as_str()
+    )
+    .fetch_one(pg_pool)
+    .await?;
+    
+    if count >= limit {
+        return Err(AppError::ValidationError(format!(
+            "Cannot have more than {} {}s", limit, relationship.as_str()
+        )));
+    }
+    
+    Ok(())
+}
+
+async fn verify_user_exists(pg_pool: &PgPool, user_id: Uuid) -> Result<(), AppError> {
+    let user = sqlx::query!(
+        r#"SELECT 1 AS "exists!" FROM users WHERE id = $1 AND is_active = true"#,
+        user_id
+    )
+    .fetch_optional(pg_pool)
+    .await?;
+    
+    if user.is_none() {
+        return Err(AppError::NotFound("User not found".to_string()));
+    }
+    
+    Ok(())
+}
@@ -0,0 +1,850 @@
+//! Copies messages, delivery status and conversation pointers from the
+//! legacy bigint-keyed tables into the timeuuid-keyed ones, and re-keys
+//! everything else that refers to a message by id.
+//!
+//! The legacy key only kept the low 64 bits of the client UUID, so the
+//! original id can't be recovered. Each row gets a timeuuid built from its
+//! stored timestamp, with the legacy bits as clock sequence and node. That
+//! makes the rewrite deterministic: running the tool twice writes the same
+//! rows, so it can be restarted after a failure.
+//!
+//! Messages themselves were keyed by the bigint, but the tables that grew up
+//! around them (locations, reactions, pins, threads, edits and hidden
+//! messages) used the full client UUID. Their rows are moved to the new id:
+//! written under it, then deleted under the old one. Counters can't be copied
+//! idempotently, so they are recounted from the rows they count.
+//!
+//! Usage: migrate-message-ids [--dry-run]
+
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use scylla::frame::value::Counter;
+use scylla::{Session, SessionBuilder};
+use std::collections::{HashMap, HashSet};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use shared::utils::{day_bucket, timeuuid_at};
+
+type LegacyKey = (Uuid, i64);
+
+type LegacyMessageRow = (
+    Uuid,
+    i64,
+    Option<Uuid>,
+    Uuid,
+    Option<String>,
+    Option<Vec<u8>>,
+    Option<Vec<u8>>,
+    Option<i64>,
+    Option<Uuid>,
+    DateTime<Utc>,
+    Option<bool>,
+    Option<bool>,
+    Option<i32>,
+);
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt::init();
+    
+    dotenv::dotenv().ok();
+    
+    let dry_run = std::env::args().any(|arg| arg == "--dry-run");
+    
+    let scylla_nodes = std::env::var("SCYLLA_NODES")
+        .unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    
+    let session: Session = SessionBuilder::new()
+        .known_node(&scylla_nodes)
+        .build()
+        .await?;
+    
+    let id_map = build_id_map(&session).await?;
+    info!("Mapped {} legacy message ids", id_map.by_legacy_id.len());
+    
+    if dry_run {
+        info!("Dry run: no rows written");
+        return Ok(());
+    }
+    
+    let messages = copy_messages(&session, &id_map).await?;
+    info!("Copied {} messages", messages);
+    
+    let statuses = copy_delivery_status(&session, &id_map).await?;
+    info!("Copied {} delivery status rows", statuses);
+    
+    let pointers = update_conversation_pointers(&session, &id_map).await?;
+    info!("Updated {} user conversation pointers", pointers);
+    
+    let locations = rekey_message_locations(&session, &id_map).await?;
+    info!("Re-keyed {} message locations", locations);
+    
+    let reactions = rekey_reactions(&session, &id_map).await?;
+    info!("Re-keyed {} reactions", reactions);
+    
+    let pins = rekey_pins(&session, &id_map).await?;
+    info!("Re-keyed {} pinned messages", pins);
+    
+    let replies = rekey_threads(&session, &id_map).await?;
+    info!("Re-keyed {} thread replies", replies);
+    
+    let edits = rekey_edits(&session, &id_map).await?;
+    info!("Re-keyed {} message revisions", edits);
+    
+    let hidden = rekey_hidden_messages(&session, &id_map).await?;
+    info!("Re-keyed {} hidden messages", hidden);
+    
+    Ok(())
+}
+
+/// Where each legacy message went, by either of the ids it used to go by.
+struct IdMap {
+    by_legacy_id: HashMap<LegacyKey, Uuid>,
+    // Only messages written after message_uuid was added have one
+    by_client_id: HashMap<Uuid, Uuid>,
+}
+
+impl IdMap {
+    fn legacy(&self, conversation_id: Uuid, legacy_id: i64) -> Option<Uuid> {
+        self.by_legacy_id.get(&(conversation_id, legacy_id)).copied()
+    }
+    
+    fn client(&self, client_id: Uuid) -> Option<Uuid> {
+        self.by_client_id.get(&client_id).copied()
+    }
+    
+    /// The new id for a side-table key, or the key itself if it is already
+    /// new (or unknown).
+    fn rekey(&self, message_id: Uuid) -> Uuid {
+        self.client(message_id).unwrap_or(message_id)
+    }
+}
+
+/// Deterministic replacement id for a legacy row.
+fn migrated_id(legacy_id: i64, timestamp: DateTime<Utc>) -> Uuid {
+    let bits = legacy_id as u64;
+    let counter = (bits >> 48) as u16;
+    
+    let mut node_id = [0u8; 6];
+    node_id.copy_from_slice(&bits.to_be_bytes()[2..]);
+    node_id[0] |= 0x01; // Not a MAC address
+    
+    timeuuid_at(timestamp, counter, &node_id)
+}
+
+async fn build_id_map(session: &Session) -> Result<IdMap, Box<dyn std::error::Error>> {
+    let mut rows = session
+        .query_iter(
+            "SELECT conversation_id, message_id, message_uuid, timestamp FROM messaging.messages",
+            &[],
+        )
+        .await?
+        .into_typed::<(Uuid, i64, Option<Uuid>, DateTime<Utc>)>();
+    
+    let mut id_map = IdMap {
+        by_legacy_id: HashMap::new(),
+        by_client_id: HashMap::new(),
+    };
+    while let Some(row) = rows.next().await {
+        let (conversation_id, legacy_id, client_id, timestamp) = row?;
+        let message_id = migrated_id(legacy_id, timestamp);
+        
+        id_map.by_legacy_id.insert((conversation_id, legacy_id), message_id);
+        if let Some(client_id) = client_id {
+            id_map.by_client_id.insert(client_id, message_id);
+        }
+    }
+    
+    Ok(id_map)
+}
+
+async fn copy_messages(
+    session: &Session,
+    id_map: &IdMap,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let insert = session
+        .prepare(
+            r#"
+            INSERT INTO messaging.messages_v2
+            (conversation_id, bucket_id, message_id, client_message_id, sender_id,
+             message_type, content, nonce, reply_to, timestamp,
+             edited, deleted, encryption_version)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .await?;
+    
+    let insert_location = session
+        .prepare(
+            r#"
+            INSERT INTO messaging.message_locations
+            (message_id, conversation_id, bucket_id)
+            VALUES (?, ?, ?)
+            "#,
+        )
+        .await?;
+    
+    // History pages walk this index, so a message outside it is never listed
+    let insert_bucket = session
+        .prepare(
+            r#"
+            INSERT INTO messaging.conversation_buckets
+            (conversation_id, bucket_id)
+            VALUES (?, ?)
+            "#,
+        )
+        .await?;
+    
+    let mut rows = session
+        .query_iter(
+            r#"
+            SELECT conversation_id, message_id, message_uuid, sender_id,
+                   message_type, content, nonce, reply_to, reply_to_uuid, timestamp,
+                   edited, deleted, encryption_version
+            FROM messaging.messages
+            "#,
+            &[],
+        )
+        .await?
+        .into_typed::<LegacyMessageRow>();
+    
+    let mut copied = 0;
+    while let Some(row) = rows.next().await {
+        let (
+            conversation_id,
+            legacy_id,
+            client_message_id,
+            sender_id,
+            message_type,
+            content,
+            nonce,
+            legacy_reply_to,
+            reply_to_uuid,
+            timestamp,
+            edited,
+            deleted,
+            encryption_version,
+        ) = row?;
+        
+        let message_id = match id_map.legacy(conversation_id, legacy_id) {
+            Some(message_id) => message_id,
+            None => {
+                // Written after the id map was built; a second run picks it up
+                warn!("Skipping message {} in conversation {}: not in the id map",
+                      legacy_id, conversation_id);
+                continue;
+            }
+        };
+        let bucket_id = day_bucket(timestamp);
+        
+        // The full parent id is exact; the bigint only resolves within this
+        // conversation and is all that older rows have
+        let reply_to = reply_to_uuid
+            .and_then(|parent| id_map.client(parent))
+            .or_else(|| legacy_reply_to.and_then(|parent| id_map.legacy(conversation_id, parent)));
+        if reply_to.is_none() && (reply_to_uuid.is_some() || legacy_reply_to.is_some()) {
+            warn!("Dropping reply_to of message {}: parent not found", message_id);
+        }
+        
+        session
+            .execute(
+                &insert,
+                (
+                    conversation_id,
+                    bucket_id,
+                    message_id,
+                    client_message_id,
+                    sender_id,
+                    message_type.unwrap_or_else(|| "text".to_string()),
+                    content.unwrap_or_default(),
+                    nonce.unwrap_or_default(),
+                    reply_to,
+                    timestamp,
+                    edited.unwrap_or(false),
+                    deleted.unwrap_or(false),
+                    encryption_version.unwrap_or(1),
+                ),
+            )
+            .await?;
+        
+        session
+            .execute(&insert_location, (message_id, conversation_id, bucket_id))
+            .await?;
+        
+        session
+            .execute(&insert_bucket, (conversation_id, bucket_id))
+            .await?;
+        
+        copied += 1;
+        if copied % 10_000 == 0 {
+            info!("Copied {} messages", copied);
+        }
+    }
+    
+    Ok(copied)
+}
+
+async fn copy_delivery_status(
+    session: &Session,
+    id_map: &IdMap,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let insert = session
+        .prepare(
+            r#"
+            INSERT INTO messaging.delivery_status_v2
+            (message_id, user_id, conversation_id, delivered, read, delivered_at, read_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .await?;
+    
+    let mut rows = session
+        .query_iter(
+            r#"
+            SELECT message_id, user_id, conversation_id, delivered, read, delivered_at, read_at
+            FROM messaging.delivery_status
+            "#,
+            &[],
+        )
+        .await?
+        .into_typed::<(i64, Uuid, Uuid, Option<bool>, Option<bool>, Option<DateTime<Utc>>, Option<DateTime<Utc>>)>();
+    
+    let mut copied = 0;
+    while let Some(row) = rows.next().await {
+        let (legacy_id, user_id, conversation_id, delivered, read, delivered_at, read_at) = row?;
+        
+        let message_id = match id_map.legacy(conversation_id, legacy_id) {
+            Some(message_id) => message_id,
+            None => {
+                warn!("Skipping delivery status for unknown message {}", legacy_id);
+                continue;
+            }
+        };
+        
+        session
+            .execute(
+                &insert,
+                (
+                    message_id,
+                    user_id,
+                    conversation_id,
+                    delivered.unwrap_or(false),
+                    read.unwrap_or(false),
+                    delivered_at,
+                    read_at,
+                ),
+            )
+            .await?;
+        
+        copied += 1;
+    }
+    
+    Ok(copied)
+}
+
+async fn update_conversation_pointers(
+    session: &Session,
+    id_map: &IdMap,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let update = session
+        .prepare(
+            r#"
+            UPDATE messaging.user_conversations
+            SET last_message_uuid = ?
+            WHERE user_id = ? AND conversation_id = ?
+            "#,
+        )
+        .await?;
+    
+    let mut rows = session
+        .query_iter(
+            "SELECT user_id, conversation_id, last_message_id FROM messaging.user_conversations",
+            &[],
+        )
+        .await?
+        .into_typed::<(Uuid, Uuid, Option<i64>)>();
+    
+    let mut updated = 0;
+    while let Some(row) = rows.next().await {
+        let (user_id, conversation_id, last_message_id) = row?;
+        
+        let message_id = match last_message_id.and_then(|id| id_map.legacy(conversation_id, id)) {
+            Some(message_id) => message_id,
+            None => continue,
+        };
+        
+        session.execute(&update, (message_id, user_id, conversation_id)).await?;
+        updated += 1;
+    }
+    
+    Ok(updated)
+}
+
+/// Moves each location to the new id and points its thread at the new root.
+/// Runs after `copy_messages`, which writes locations without a thread root.
+async fn rekey_message_locations(
+    session: &Session,
+    id_map: &IdMap,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let insert = session
+        .prepare(
+            r#"
+            INSERT INTO messaging.message_locations
+            (message_id, conversation_id, bucket_id, thread_root)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .await?;
+    
+    let delete = session
+        .prepare("DELETE FROM messaging.message_locations WHERE message_id = ?")
+        .await?;
+    
+    let mut rows = session
+        .query_iter(
+            "SELECT message_id, conversation_id, bucket_id, thread_root FROM messaging.message_locations",
+            &[],
+        )
+        .await?
+        .into_typed::<(Uuid, Option<Uuid>, Option<i32>, Option<Uuid>)>();
+    
+    let mut moved = 0;
+    while let Some(row) = rows.next().await {
+        let (old_id, conversation_id, bucket_id, thread_root) = row?;
+        
+        let message_id = id_map.rekey(old_id);
+        let new_root = thread_root.map(|root| id_map.rekey(root));
+        if message_id == old_id && new_root == thread_root {
+            continue;
+        }
+        
+        session
+            .execute(&insert, (message_id, conversation_id, bucket_id, new_root))
+            .await?;
+        
+        if message_id != old_id {
+            session.execute(&delete, (old_id,)).await?;
+        }
+        
+        moved += 1;
+    }
+    
+    Ok(moved)
+}
+
+async fn rekey_reactions(
+    session: &Session,
+    id_map: &IdMap,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let insert = session
+        .prepare(
+            r#"
+            INSERT INTO messaging.message_reactions
+            (conversation_id, message_id, emoji, user_id, reacted_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .await?;
+    
+    let delete = session
+        .prepare(
+            r#"
+            DELETE FROM messaging.message_reactions
+            WHERE conversation_id = ? AND message_id = ? AND emoji = ? AND user_id = ?
+            "#,
+        )
+        .await?;
+    
+    let mut rows = session
+        .query_iter(
+            "SELECT conversation_id, message_id, emoji, user_id, reacted_at FROM messaging.message_reactions",
+            &[],
+        )
+        .await?
+        .into_typed::<(Uuid, Uuid, String, Uuid, Option<DateTime<Utc>>)>();
+    
+    let mut recount = HashSet::new();
+    let mut moved = 0;
+    while let Some(row) = rows.next().await {
+        let (conversation_id, old_id, emoji, user_id, reacted_at) = row?;
+        
+        let message_id = match id_map.client(old_id) {
+            Some(message_id) => message_id,
+            None => continue,
+        };
+        
+        session
+            .execute(&insert, (conversation_id, message_id, &emoji, user_id, reacted_at))
+            .await?;
+        session
+            .execute(&delete, (conversation_id, old_id, &emoji, user_id))
+            .await?;
+        
+        recount.insert((conversation_id, old_id, message_id));
+        moved += 1;
+    }
+    
+    // Counts can outlive their reactions (every user took theirs back), and a
+    // restarted run has already moved the reactions of some messages
+    let mut counted = session
+        .query_iter(
+            "SELECT DISTINCT conversation_id, message_id FROM messaging.message_reaction_counts",
+            &[],
+        )
+        .await?
+        .into_typed::<(Uuid, Uuid)>();
+    
+    while let Some(row) = counted.next().await {
+        let (conversation_id, old_id) = row?;
+        if let Some(message_id) = id_map.client(old_id) {
+            recount.insert((conversation_id, old_id, message_id));
+        }
+    }
+    
+    for (conversation_id, old_id, message_id) in recount {
+        recount_reactions(session, conversation_id, message_id).await?;
+        
+        session
+            .query(
+                r#"
+                DELETE FROM messaging.message_reaction_counts
+                WHERE conversation_id = ? AND message_id = ?
+                "#,
+                (conversation_id, old_id),
+            )
+            .await?;
+    }
+    
+    Ok(moved)
+}
+
+/// Brings a message's reaction counters in line with its reaction rows.
+async fn recount_reactions(
+    session: &Session,
+    conversation_id: Uuid,
+    message_id: Uuid,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let reactions = session
+        .query(
+            r#"
+            SELECT emoji FROM messaging.message_reactions
+            WHERE conversation_id = ? AND message_id = ?
+            "#,
+            (conversation_id, message_id),
+        )
+        .await?
+        .rows_typed::<(String,)>()?;
+    
+    let mut adjustments: HashMap<String, i64> = HashMap::new();
+    for row in reactions {
+        let (emoji,) = row?;
+        *adjustments.entry(emoji).or_default() += 1;
+    }
+    
+    let counts = session
+        .query(
+            r#"
+            SELECT emoji, count FROM messaging.message_reaction_counts
+            WHERE conversation_id = ? AND message_id = ?
+            "#,
+            (conversation_id, message_id),
+        )
+        .await?
+        .rows_typed::<(String, Counter)>()?;
+    
+    for row in counts {
+        let (emoji, Counter(count)) = row?;
+        *adjustments.entry(emoji).or_default() -= count;
+    }
+    
+    for (emoji, delta) in adjustments.into_iter().filter(|(_, delta)| *delta != 0) {
+        session
+            .query(
+                r#"
+                UPDATE messaging.message_reaction_counts
+                SET count = count + ?
+                WHERE conversation_id = ? AND message_id = ? AND emoji = ?
+                "#,
+                (Counter(delta), conversation_id, message_id, emoji),
+            )
+            .await?;
+    }
+    
+    Ok(())
+}
+
+async fn rekey_pins(
+    session: &Session,
+    id_map: &IdMap,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let insert = session
+        .prepare(
+            r#"
+            INSERT INTO messaging.pinned_messages
+            (conversation_id, message_id, pinned_by, pinned_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .await?;
+    
+    let delete = session
+        .prepare("DELETE FROM messaging.pinned_messages WHERE conversation_id = ? AND message_id = ?")
+        .await?;
+    
+    let mut rows = session
+        .query_iter(
+            "SELECT conversation_id, message_id, pinned_by, pinned_at FROM messaging.pinned_messages",
+            &[],
+        )
+        .await?
+        .into_typed::<(Uuid, Uuid, Option<Uuid>, Option<DateTime<Utc>>)>();
+    
+    let mut moved = 0;
+    while let Some(row) = rows.next().await {
+        let (conversation_id, old_id, pinned_by, pinned_at) = row?;
+        
+        let message_id = match id_map.client(old_id) {
+            Some(message_id) => message_id,
+            None => continue,
+        };
+        
+        session
+            .execute(&insert, (conversation_id, message_id, pinned_by, pinned_at))
+            .await?;
+        session.execute(&delete, (conversation_id, old_id)).await?;
+        
+        moved += 1;
+    }
+    
+    Ok(moved)
+}
+
+/// Moves replies under their root's new id, and each reply to its own new
+/// id. Either may already be new: a reply sent after the switch can still
+/// hang off an old root.
+async fn rekey_threads(
+    session: &Session,
+    id_map: &IdMap,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let insert = session
+        .prepare(
+            r#"
+            INSERT INTO messaging.message_threads
+            (conversation_id, root_id, sent_at, message_id, sender_id, bucket_id)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .await?;
+    
+    let delete = session
+        .prepare(
+            r#"
+            DELETE FROM messaging.message_threads
+            WHERE conversation_id = ? AND root_id = ? AND sent_at = ? AND message_id = ?
+            "#,
+        )
+        .await?;
+    
+    let mut rows = session
+        .query_iter(
+            "SELECT conversation_id, root_id, sent_at, message_id, sender_id FROM messaging.message_threads",
+            &[],
+        )
+        .await?
+        .into_typed::<(Uuid, Uuid, DateTime<Utc>, Uuid, Option<Uuid>)>();
+    
+    let mut recount = HashSet::new();
+    let mut moved = 0;
+    while let Some(row) = rows.next().await {
+        let (conversation_id, old_root, sent_at, old_id, sender_id) = row?;
+        
+        let root_id = id_map.rekey(old_root);
+        let message_id = id_map.rekey(old_id);
+        if root_id == old_root && message_id == old_id {
+            continue;
+        }
+        
+        // Replies live in the bucket of the day they were sent
+        session
+            .execute(
+                &insert,
+                (conversation_id, root_id, sent_at, message_id, sender_id, day_bucket(sent_at)),
+            )
+            .await?;
+        session
+            .execute(&delete, (conversation_id, old_root, sent_at, old_id))
+            .await?;
+        
+        if root_id != old_root {
+            recount.insert((conversation_id, old_root, root_id));
+        }
+        moved += 1;
+    }
+    
+    let mut counted = session
+        .query_iter(
+            "SELECT conversation_id, root_id FROM messaging.thread_reply_counts",
+            &[],
+        )
+        .await?
+        .into_typed::<(Uuid, Uuid)>();
+    
+    while let Some(row) = counted.next().await {
+        let (conversation_id, old_root) = row?;
+        if let Some(root_id) = id_map.client(old_root) {
+            recount.insert((conversation_id, old_root, root_id));
+        }
+    }
+    
+    for (conversation_id, old_root, root_id) in recount {
+        let (replies,) = session
+            .query(
+                r#"
+                SELECT COUNT(*) FROM messaging.message_threads
+                WHERE conversation_id = ? AND root_id = ?
+                "#,
+                (conversation_id, root_id),
+            )
+            .await?
+            .first_row_typed::<(i64,)>()?;
+        
+        let current = session
+            .query(
+                r#"
+                SELECT reply_count FROM messaging.thread_reply_counts
+                WHERE conversation_id = ? AND root_id = ?
+                "#,
+                (conversation_id, root_id),
+            )
+            .await?
+            .maybe_first_row_typed::<(Counter,)>()?
+            .map(|(Counter(count),)| count)
+            .unwrap_or(0);
+        
+        if replies != current {
+            session
+                .query(
+                    r#"
+                    UPDATE messaging.thread_reply_counts
+                    SET reply_count = reply_count + ?
+                    WHERE conversation_id = ? AND root_id = ?
+                    "#,
+                    (Counter(replies - current), conversation_id, root_id),
+                )
+                .await?;
+        }
+        
+        session
+            .query(
+                "DELETE FROM messaging.thread_reply_counts WHERE conversation_id = ? AND root_id = ?",
+                (conversation_id, old_root),
+            )
+            .await?;
+    }
+    
+    Ok(moved)
+}
+
+async fn rekey_edits(
+    session: &Session,
+    id_map: &IdMap,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let insert = session
+        .prepare(
+            r#"
+            INSERT INTO messaging.message_edits
+            (message_id, edited_at, conversation_id, editor_id, previous_content, previous_nonce)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .await?;
+    
+    let delete = session
+        .prepare("DELETE FROM messaging.message_edits WHERE message_id = ? AND edited_at = ?")
+        .await?;
+    
+    let mut rows = session
+        .query_iter(
+            r#"
+            SELECT message_id, edited_at, conversation_id, editor_id, previous_content, previous_nonce
+            FROM messaging.message_edits
+            "#,
+            &[],
+        )
+        .await?
+        .into_typed::<(Uuid, DateTime<Utc>, Option<Uuid>, Option<Uuid>, Option<Vec<u8>>, Option<Vec<u8>>)>();
+    
+    let mut moved = 0;
+    while let Some(row) = rows.next().await {
+        let (old_id, edited_at, conversation_id, editor_id, previous_content, previous_nonce) = row?;
+        
+        let message_id = match id_map.client(old_id) {
+            Some(message_id) => message_id,
+            None => continue,
+        };
+        
+        session
+            .execute(
+                &insert,
+                (message_id, edited_at, conversation_id, editor_id, previous_content, previous_nonce),
+            )
+            .await?;
+        session.execute(&delete, (old_id, edited_at)).await?;
+        
+        moved += 1;
+    }
+    
+    Ok(moved)
+}
+
+async fn rekey_hidden_messages(
+    session: &Session,
+    id_map: &IdMap,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let insert = session
+        .prepare(
+            r#"
+            INSERT INTO messaging.hidden_messages
+            (user_id, conversation_id, message_id, hidden_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .await?;
+    
+    let delete = session
+        .prepare(
+            r#"
+            DELETE FROM messaging.hidden_messages
+            WHERE user_id = ? AND conversation_id = ? AND message_id = ?
+            "#,
+        )
+        .await?;
+    
+    let mut rows = session
+        .query_iter(
+            "SELECT user_id, conversation_id, message_id, hidden_at FROM messaging.hidden_messages",
+            &[],
+        )
+        .await?
+        .into_typed::<(Uuid, Uuid, Uuid, Option<DateTime<Utc>>)>();
+    
+    let mut moved = 0;
+    while let Some(row) = rows.next().await {
+        let (user_id, conversation_id, old_id, hidden_at) = row?;
+        
+        let message_id = match id_map.client(old_id) {
+            Some(message_id) => message_id,
+            None => continue,
+        };
+        
+        session
+            .execute(&insert, (user_id, conversation_id, message_id, hidden_at))
+            .await?;
+        session.execute(&delete, (user_id, conversation_id, old_id)).await?;
+        
+        moved += 1;
+    }
+    
+    Ok(moved)
+}
@@ -0,0 +1,188 @@
+use chrono::{DateTime, Utc};
+use rdkafka::producer::FutureProducer;
+use scylla::Session;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use shared::types::{MessageUpdate, MessageUpdateEvent};
+
+const SWEEP_INTERVAL_SECS: u64 = 60;
+// Index rows are kept a day past expiry, so a restart can catch up on a day
+const STARTUP_LOOKBACK_MINUTES: i64 = 24 * 60;
+const INDEX_GRACE_SECONDS: u32 = 86400;
+
+/// Records when a disappearing message expires so the sweeper can announce it.
+pub async fn schedule_expiry(
+    session: &Session,
+    conversation_id: Uuid,
+    message_id: Uuid,
+    expires_at: DateTime<Utc>,
+    ttl_seconds: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    session
+        .query(
+            r#"
+            INSERT INTO messaging.message_expiries
+            (expiry_minute, conversation_id, message_id)
+            VALUES (?, ?, ?)
+            USING TTL ?
+            "#,
+            (
+                expiry_minute(expires_at),
+                conversation_id,
+                message_id,
+                (ttl_seconds + INDEX_GRACE_SECONDS) as i32,
+            ),
+        )
+        .await?;
+    
+    Ok(())
+}
+
+fn expiry_minute(at: DateTime<Utc>) -> i64 {
+    at.timestamp() / 60
+}
+
+/// Scylla drops expired messages on its own; the sweeper tells the
+/// conversation's members which ones are gone and deletes what can't carry a
+/// TTL. Every processor runs one, and sweeping a minute twice just repeats
+/// the event, which clients ignore.
+pub struct ExpirySweeper {
+    scylla_session: Arc<Session>,
+    kafka_producer: FutureProducer,
+    pg_pool: sqlx::PgPool,
+    next_minute: i64,
+}
+
+impl ExpirySweeper {
+    pub fn new(scylla_session: Arc<Session>, kafka_producer: FutureProducer, pg_pool: sqlx::PgPool) -> Self {
+        Self {
+            scylla_session,
+            kafka_producer,
+            pg_pool,
+            next_minute: expiry_minute(Utc::now()) - STARTUP_LOOKBACK_MINUTES,
+        }
+    }
+    
+    pub async fn run(mut self) {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(SWEEP_INTERVAL_SECS));
+        
+        info!("Expiry sweeper started");
+        
+        loop {
+            interval.tick().await;
+            
+            if let Err(e) = self.sweep().await {
+                error!("Expiry sweep failed: {}", e);
+            }
+        }
+    }
+    
+    /// Sweeps every fully elapsed minute since the last sweep. On failure the
+    /// cursor stays put and the minute is retried next time.
+    async fn sweep(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let current_minute = expiry_minute(Utc::now());
+        
+        while self.next_minute < current_minute {
+            self.sweep_minute(self.next_minute).await?;
+            self.next_minute += 1;
+        }
+        
+        Ok(())
+    }
+    
+    async fn sweep_minute(&self, minute: i64) -> Result<(), Box<dyn std::error::Error>> {
+        let rows = self.scylla_session
+            .query(
+                r#"
+                SELECT conversation_id, message_id FROM messaging.message_expiries
+                WHERE expiry_minute = ?
+                "#,
+                (minute,),
+            )
+            .await?
+            .rows_typed::<(Uuid, Uuid)>()?;
+        
+        let mut expired: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+        for row in rows {
+            let (conversation_id, message_id) = row?;
+            expired.entry(conversation_id).or_default().push(message_id);
+        }
+        
+        if expired.is_empty() {
+            return Ok(());
+        }
+        
+        for (conversation_id, message_ids) in expired {
+            for &message_id in &message_ids {
+                self.delete_message_extras(conversation_id, message_id).await?;
+            }
+            
+            let recipients = self.get_participants(conversation_id).await?;
+            let count = message_ids.len();
+            
+            self.publish_message_update(MessageUpdate {
+                recipients,
+                event: MessageUpdateEvent::Expired {
+                    conversation_id,
+                    message_ids,
+                },
+            }).await?;
+            
+            info!("Expired {} messages in conversation {}", count, conversation_id);
+        }
+        
+        self.scylla_session
+            .query(
+                "DELETE FROM messaging.message_expiries WHERE expiry_minute = ?",
+                (minute,),
+            )
+            .await?;
+        
+        Ok(())
+    }
+    
+    /// Reaction counters can't carry a TTL, so they are deleted here. Pins
+    /// and reactions carry the message's TTL, but go too in case they were
+    /// written without it.
+    async fn delete_message_extras(&self, conversation_id: Uuid, message_id: Uuid) -> Result<(), Box<dyn std::error::Error>> {
+        for query in [
+            "DELETE FROM messaging.message_reaction_counts WHERE conversation_id = ? AND message_id = ?",
+            "DELETE FROM messaging.message_reactions WHERE conversation_id = ? AND message_id = ?",
+            "DELETE FROM messaging.pinned_messages WHERE conversation_id = ? AND message_id = ?",
+        ] {
+            self.scylla_session.query(query, (conversation_id, message_id)).await?;
+        }
+        
+        Ok(())
+    }
+    
+    async fn get_participants(&self, conversation_id: Uuid) -> Result<Vec<Uuid>, Box<dyn std::error::Error>> {
+        let members = sqlx::query!(
+            "SELECT user_id FROM group_members WHERE group_id = $1 AND is_banned = false",
+            conversation_id
+        )
+        .fetch_all(&self.pg_pool)
+        .await?;
+        
+        Ok(members.into_iter().map(|m| m.user_id).collect())
+    }
+    
+    async fn publish_message_update(&self, update: MessageUpdate) -> Result<(), Box<dyn std::error::Error>> {
+        let key = update.event.conversation_id().to_string();
+        let payload = serde_json::to_vec(&update)?;
+        
+        let record = rdkafka::producer::FutureRecord::to("message-updates")
+            .key(&key)
+            .payload(&payload);
+        
+        self.kafka_producer
+            .send(record, std::time::Duration::from_secs(5))
+            .await
+            .map_err(|(e, _)| e)?;
+        
+        Ok(())
+    }
+}
@@ -1,23 +1,69 @@
+use base64::{engine::general_purpose, Engine as _};
 use chrono::{DateTime, Utc};
 use rdkafka::consumer::{Consumer, StreamConsumer};
-use scylla::{IntoTypedRows, Session, SessionBuilder};
+use rdkafka::producer::FutureProducer;
+use rdkafka::Message as KafkaMessage;
+use scylla::frame::value::Counter;
+use scylla::{IntoTypedRows, QueryResult, Session, SessionBuilder};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
-use shared::models::{Message, Conversation, GroupMember};
+use shared::models::{
+    Message, Conversation, ConversationType, DeleteScope, GroupMember, GroupRole, MessageMentions, MessageType,
+    ModerationAction, ModerationTarget, Permission,
+};
+use shared::crypto::SystemMessageSigner;
+use shared::restrictions::{RestrictionCache, DEFAULT_CACHE_TTL};
+use shared::permissions::{
+    parse_permissions, ConversationSettings, MemberPermissions, MAX_SLOW_MODE_SECONDS,
+};
+use shared::utils::{day_bucket, timeuuid_datetime};
+use shared::types::{
+    MembershipChange, MembershipEvent, MessageOperation, MessageOperationEnvelope, MessageUpdate,
+    MessageUpdateEvent, ModerationActionEvent, NotificationEvent, NotificationReason,
+    ReadReceiptEnvelope, SystemEvent,
+};
+
+mod api;
+mod expiry;
+
+const MAX_REACTION_LENGTH: usize = 16;
+const MAX_MARKER_RETRIES: usize = 5;
+const MAX_INBOX_RETRIES: usize = 5;
+const SLOW_MODE_PRUNE_THRESHOLD: usize = 100_000;
+const CLIENT_MESSAGE_ID_TTL_SECONDS: i32 = 7 * 86400;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ProcessedMessage {
     message_id: Uuid,
+    // Absent for system messages, which no client sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_message_id: Option<Uuid>,
+    // The device that sent it, which gets the ack instead of the message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sender_device_id: Option<String>,
     conversation_id: Uuid,
+    conversation_type: ConversationType,
+    // Who the gateways deliver it to. Empty for channels: each gateway
+    // tracks which of its connected users subscribe to which channels
+    recipients: Vec<Uuid>,
     sender_id: Uuid,
+    message_type: MessageType,
     content: Vec<u8>,
     nonce: Vec<u8>,
     reply_to: Option<Uuid>,
     timestamp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<i64>,
+    #[serde(skip_serializing_if = "MessageMentions::is_empty")]
+    mentions: MessageMentions,
+    // Only system messages are signed by the server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<Vec<u8>>,
     delivered_to: Vec<Uuid>,
     read_by: Vec<Uuid>,
 }
@@ -25,7 +71,38 @@ struct ProcessedMessage {
 struct MessageProcessor {
     scylla_session: Arc<Session>,
     kafka_consumer: StreamConsumer,
+    kafka_producer: FutureProducer,
     pg_pool: sqlx::PgPool,
+    node_id: [u8; 6],
+    // Last send per (conversation, user) for slow mode. Kafka keys messages by
+    // conversation, so each conversation is only ever seen by one processor.
+    last_sent: RwLock<HashMap<(Uuid, Uuid), DateTime<Utc>>>,
+    system_signer: SystemMessageSigner,
+    restrictions: Arc<RestrictionCache>,
+}
+
+// A user's own settings for a conversation, kept in user_conversations
+#[derive(Default)]
+struct InboxPreferences {
+    muted_until: Option<DateTime<Utc>>,
+    archived: bool,
+}
+
+impl InboxPreferences {
+    fn is_muted(&self, now: DateTime<Utc>) -> bool {
+        self.muted_until.map_or(false, |until| until > now)
+    }
+}
+
+struct StoredMessage {
+    bucket_id: i32,
+    sender_id: Uuid,
+    content: Vec<u8>,
+    nonce: Vec<u8>,
+    deleted: bool,
+    // Seconds a disappearing message has left, 0 for one that never expires.
+    // Every later write about the message uses it so nothing outlives it.
+    ttl_seconds: i32,
 }
 
 impl MessageProcessor {
@@ -49,67 +126,217 @@ impl MessageProcessor {
             .set("enable.auto.commit", "true")
             .create()?;
         
+        let producer: FutureProducer = rdkafka::config::ClientConfig::new()
+            .set("bootstrap.servers", &kafka_brokers)
+            .set("message.timeout.ms", "5000")
+            .create()?;
+        
         let database_url = std::env::var("DATABASE_URL")
             .expect("DATABASE_URL must be set");
         
         let pg_pool = sqlx::PgPool::connect(&database_url).await?;
         
+        shared::migrations::MIGRATOR.run(&pg_pool).await?;
+        
+        let signing_key = std::env::var("SYSTEM_MESSAGE_SIGNING_KEY")
+            .expect("SYSTEM_MESSAGE_SIGNING_KEY must be set");
+        let system_signer = SystemMessageSigner::from_base64(&signing_key)?;
+        
+        let restrictions = Arc::new(RestrictionCache::new(pg_pool.clone(), DEFAULT_CACHE_TTL));
+        let restrictions_clone = restrictions.clone();
+        tokio::spawn(async move {
+            if let Err(e) = restrictions_clone.run(kafka_brokers, "message-processor").await {
+                error!("Restriction cache consumer error: {}", e);
+            }
+        });
+        
         Ok(Self {
             scylla_session: Arc::new(session),
-            kafka_consumer,
+            kafka_consumer: consumer,
+            kafka_producer: producer,
             pg_pool,
+            node_id: generate_node_id(),
+            last_sent: RwLock::new(HashMap::new()),
+            system_signer,
+            restrictions,
         })
     }
     
     async fn process_messages(&self) -> Result<(), Box<dyn std::error::Error>> {
-        self.kafka_consumer.subscribe(&["messages"])?;
+        self.kafka_consumer.subscribe(&[
+            "messages",
+            "message-operations",
+            "read-receipts",
+            "membership-events",
+            "moderation-actions",
+        ])?;
         
         info!("Message processor started");
         
-        while let Some(message) = self.kafka_consumer.recv().await {
-            if let Some(payload) = message.payload() {
-                if let Ok(envelope) = serde_json::from_slice::<MessageEnvelope>(payload) {
-                    self.process_message(envelope).await?;
+        while let Ok(message) = self.kafka_consumer.recv().await {
+            let payload = match message.payload() {
+                Some(payload) => payload,
+                None => continue,
+            };
+            
+            match message.topic() {
+                "messages" => {
+                    if let Ok(envelope) = serde_json::from_slice::<MessageEnvelope>(payload) {
+                        if let Err(e) = self.process_message(envelope).await {
+                            error!("Failed to process message: {}", e);
+                        }
+                    }
+                }
+                "message-operations" => {
+                    if let Ok(operation) = serde_json::from_slice::<MessageOperationEnvelope>(payload) {
+                        if let Err(e) = self.process_operation(operation).await {
+                            error!("Failed to process message operation: {}", e);
+                        }
+                    }
+                }
+                "read-receipts" => {
+                    if let Ok(receipt) = serde_json::from_slice::<ReadReceiptEnvelope>(payload) {
+                        if let Err(e) = self.handle_read_receipt(receipt).await {
+                            error!("Failed to process read receipt: {}", e);
+                        }
+                    }
                 }
+                "membership-events" => {
+                    if let Ok(event) = serde_json::from_slice::<MembershipEvent>(payload) {
+                        if let Err(e) = self.handle_membership_event(event).await {
+                            error!("Failed to process membership event: {}", e);
+                        }
+                    }
+                }
+                "moderation-actions" => {
+                    if let Ok(event) = serde_json::from_slice::<ModerationActionEvent>(payload) {
+                        if let Err(e) = self.handle_moderation_action(event).await {
+                            error!("Failed to apply moderation action: {}", e);
+                        }
+                    }
+                }
+                _ => {}
             }
         }
         
         Ok(())
     }
     
-    async fn process_message(&self, envelope: MessageEnvelope) -> Result<(), Box<dyn std::error::Error>> {
+    async fn process_message(&self, mut envelope: MessageEnvelope) -> Result<(), Box<dyn std::error::Error>> {
+        // Muted users keep reading but anything they send is dropped
+        if !self.restrictions.can_send(envelope.sender_id).await? {
+            warn!("Dropped message from restricted user {}", envelope.sender_id);
+            return Ok(());
+        }
+        
         // Validate conversation exists and user is member
-        let is_member = sqlx::query!(
-            r#"
-            SELECT 1 FROM group_members 
-            WHERE group_id = $1 AND user_id = $2 AND is_banned = false
-            "#,
-            envelope.conversation_id,
-            envelope.sender_id
-        )
-        .fetch_optional(&self.pg_pool)
-        .await?;
+        let member = match self.get_member_permissions(envelope.conversation_id, envelope.sender_id).await? {
+            Some(member) => member,
+            None => {
+                warn!("User {} is not a member of conversation {}", 
+                      envelope.sender_id, envelope.conversation_id);
+                return Ok(());
+            }
+        };
+        
+        let conversation_type = match self.get_conversation_type(envelope.conversation_id).await? {
+            Some(conversation_type) => conversation_type,
+            None => {
+                warn!("Conversation {} has an unknown type", envelope.conversation_id);
+                return Ok(());
+            }
+        };
+        let is_channel = conversation_type == ConversationType::Channel;
         
-        if is_member.is_none() {
-            warn!("User {} is not a member of conversation {}", 
+        // Only channel admins post; subscribers just read
+        if is_channel && !member.role.is_admin() {
+            warn!("User {} cannot post in channel {}", 
                   envelope.sender_id, envelope.conversation_id);
             return Ok(());
         }
         
-        // Calculate time bucket (e.g., day-based)
-        let timestamp = DateTime::from_timestamp(envelope.timestamp, 0)
-            .unwrap_or_else(Utc::now);
-        let bucket_id = (timestamp.date().num_days_from_ce() / 1) as i32; // Daily bucket
+        // A block on either side closes the direct message
+        if conversation_type == ConversationType::DirectMessage
+            && self.is_blocked_in(envelope.conversation_id, envelope.sender_id).await?
+        {
+            warn!("User {} is blocked in direct message {}", 
+                  envelope.sender_id, envelope.conversation_id);
+            return Ok(());
+        }
         
-        // Store message in ScyllaDB
-        let message_id = envelope.message_id.as_u128() as i64;
+        let settings = self.get_conversation_settings(envelope.conversation_id).await?;
+        
+        if !member.allows(&Permission::SendMessages, &settings) {
+            warn!("User {} may not send in conversation {}", 
+                  envelope.sender_id, envelope.conversation_id);
+            return Ok(());
+        }
+        
+        if envelope.mentions.everyone && !member.has(&Permission::MentionEveryone) {
+            warn!("User {} may not mention everyone in conversation {}", 
+                  envelope.sender_id, envelope.conversation_id);
+            return Ok(());
+        }
+        
+        if envelope.mentions.user_ids.len() > MessageMentions::MAX_USERS {
+            warn!("User {} mentioned too many users in conversation {}", 
+                  envelope.sender_id, envelope.conversation_id);
+            return Ok(());
+        }
+        
+        // Replies to a reply belong to the same thread as their parent. The
+        // parent must be a stored message of this conversation; anything
+        // else, a non-timeuuid included, would fail the insert below
+        let thread_root = match envelope.reply_to {
+            Some(parent_id) => match self.resolve_thread_root(envelope.conversation_id, parent_id).await? {
+                Some(root_id) => Some(root_id),
+                None => {
+                    warn!("User {} replied to unknown message {} in conversation {}", 
+                          envelope.sender_id, parent_id, envelope.conversation_id);
+                    return Ok(());
+                }
+            },
+            None => None,
+        };
+        
+        if let Some(next_allowed) = self
+            .check_slow_mode(envelope.conversation_id, envelope.sender_id, &member, &settings)
+            .await
+        {
+            warn!("User {} is in slow mode in conversation {} until {}", 
+                  envelope.sender_id, envelope.conversation_id, next_allowed);
+            return Ok(());
+        }
+        
+        // Server-assigned timeuuid: orders messages by arrival and keeps the
+        // full 128 bits. The client's id is kept only to correlate the ack.
+        let message_id = match self
+            .claim_client_message_id(envelope.sender_id, envelope.message_id, Uuid::now_v1(&self.node_id))
+            .await?
+        {
+            Some(message_id) => message_id,
+            None => {
+                info!("Skipping duplicate message {} from user {}", 
+                      envelope.message_id, envelope.sender_id);
+                return Ok(());
+            }
+        };
+        let timestamp = timeuuid_datetime(&message_id).unwrap_or_else(Utc::now);
+        let bucket_id = day_bucket(timestamp); // Daily bucket
+        
+        // Disappearing messages carry a TTL; 0 keeps them forever
+        let ttl_seconds = self.get_message_ttl(envelope.conversation_id).await?;
+        let expires_at = (ttl_seconds > 0)
+            .then(|| timestamp + chrono::Duration::seconds(ttl_seconds as i64));
         
+        // Store message in ScyllaDB
         let query = r#"
-        INSERT INTO messaging.messages 
-        (conversation_id, bucket_id, message_id, sender_id, 
+        INSERT INTO messaging.messages_v2 
+        (conversation_id, bucket_id, message_id, client_message_id, sender_id, 
          message_type, content, nonce, reply_to, timestamp, 
          edited, deleted, encryption_version)
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, false, false, 1)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, false, false, 1)
+        USING TTL ?
         "#;
         
         self.scylla_session
@@ -117,15 +344,64 @@ impl MessageProcessor {
                 envelope.conversation_id,
                 bucket_id,
                 message_id,
+                envelope.message_id,
                 envelope.sender_id,
                 "text", // message_type
-                envelope.content,
-                envelope.nonce,
-                envelope.reply_to.map(|id| id.as_u128() as i64),
+                &envelope.content,
+                &envelope.nonce,
+                envelope.reply_to,
                 timestamp,
+                ttl_seconds as i32,
             ))
             .await?;
         
+        // Record that this day has messages so history reads can skip empty days
+        self.scylla_session
+            .query(
+                r#"
+                INSERT INTO messaging.conversation_buckets
+                (conversation_id, bucket_id)
+                VALUES (?, ?)
+                "#,
+                (envelope.conversation_id, bucket_id),
+            )
+            .await?;
+        
+        // Index the message by its full id so edits and deletes can find its bucket
+        self.scylla_session
+            .query(
+                r#"
+                INSERT INTO messaging.message_locations
+                (message_id, conversation_id, bucket_id, thread_root)
+                VALUES (?, ?, ?, ?)
+                USING TTL ?
+                "#,
+                (message_id, envelope.conversation_id, bucket_id, thread_root, ttl_seconds as i32),
+            )
+            .await?;
+        
+        if let Some(expires_at) = expires_at {
+            expiry::schedule_expiry(
+                &self.scylla_session,
+                envelope.conversation_id,
+                message_id,
+                expires_at,
+                ttl_seconds,
+            ).await?;
+        }
+        
+        if let Some(root_id) = thread_root {
+            self.record_thread_reply(
+                envelope.conversation_id,
+                root_id,
+                message_id,
+                envelope.sender_id,
+                bucket_id,
+                timestamp,
+                ttl_seconds,
+            ).await?;
+        }
+        
         // Update conversation last message timestamp
         sqlx::query!(
             r#"
@@ -139,60 +415,283 @@ impl MessageProcessor {
         .execute(&self.pg_pool)
         .await?;
         
-        // Update user_conversations for all participants
-        let participants = self.get_conversation_participants(envelope.conversation_id).await?;
-        
-        for participant_id in participants {
-            if participant_id != envelope.sender_id {
-                // Update unread count
-                let update_query = r#"
-                UPDATE messaging.user_conversations 
-                SET unread_count = unread_count + 1,
-                    last_message_id = ?,
-                    last_message_at = ?
-                WHERE user_id = ? AND conversation_id = ?
-                "#;
+        let mut recipients = Vec::new();
+        
+        // Channels can have far more subscribers than is worth writing per post;
+        // their unread state comes from the subscriber's read marker instead
+        if !is_channel {
+            // Update user_conversations for all participants
+            let participants = self.get_conversation_participants(envelope.conversation_id).await?;
+            
+            // Mentions only count for other current members
+            let sender_id = envelope.sender_id;
+            envelope.mentions.user_ids.retain(|id| *id != sender_id && participants.contains(id));
+            envelope.mentions.user_ids.sort();
+            envelope.mentions.user_ids.dedup();
+            
+            for &participant_id in &participants {
+                let preferences = self
+                    .touch_inbox(participant_id, envelope.conversation_id, message_id, timestamp, true)
+                    .await?;
                 
-                self.scylla_session
-                    .query(update_query, (
-                        participant_id,
-                        envelope.conversation_id,
+                if participant_id != envelope.sender_id {
+                    let reason = if envelope.mentions.user_ids.contains(&participant_id) {
+                        NotificationReason::Mentioned
+                    } else if envelope.mentions.everyone {
+                        NotificationReason::MentionedEveryone
+                    } else {
+                        NotificationReason::NewMessage
+                    };
+                    let mentioned = if reason.is_priority() { 1 } else { 0 };
+                    
+                    self.scylla_session
+                        .query(
+                            r#"
+                            UPDATE messaging.conversation_counters
+                            SET unread_count = unread_count + 1,
+                                mention_count = mention_count + ?
+                            WHERE user_id = ? AND conversation_id = ?
+                            "#,
+                            (Counter(mentioned), participant_id, envelope.conversation_id),
+                        )
+                        .await?;
+                    
+                    // Muting silences pushes, but mentions still get through
+                    if preferences.is_muted(timestamp) && !reason.is_priority() {
+                        continue;
+                    }
+                    
+                    self.publish_notification(NotificationEvent {
+                        user_id: participant_id,
+                        conversation_id: envelope.conversation_id,
                         message_id,
-                        timestamp,
-                    ))
-                    .await?;
+                        sender_id: envelope.sender_id,
+                        reason,
+                        priority: reason.is_priority(),
+                        timestamp: timestamp.timestamp(),
+                    }).await?;
+                }
             }
+            
+            // Create delivery status records
+            self.create_delivery_status(
+                message_id,
+                envelope.conversation_id,
+                &participants,
+                envelope.sender_id,
+                ttl_seconds,
+            ).await?;
+            
+            recipients = participants;
+        } else {
+            // Subscribers aren't tracked per post, so there is no one to
+            // notify individually; @everyone still reaches the whole channel
+            envelope.mentions.user_ids.clear();
         }
         
-        // Create delivery status records
-        self.create_delivery_status(
-            envelope.message_id,
-            envelope.conversation_id,
-            &participants,
-            envelope.sender_id,
-        ).await?;
-        
         // Publish processed message for WebSocket distribution
         let processed_msg = ProcessedMessage {
-            message_id: envelope.message_id,
+            message_id,
+            client_message_id: Some(envelope.message_id),
+            sender_device_id: envelope.sender_device_id,
             conversation_id: envelope.conversation_id,
+            conversation_type,
+            recipients,
             sender_id: envelope.sender_id,
+            message_type: MessageType::Text,
             content: envelope.content,
             nonce: envelope.nonce,
             reply_to: envelope.reply_to,
-            timestamp: envelope.timestamp,
+            timestamp: timestamp.timestamp(),
+            expires_at: expires_at.map(|at| at.timestamp()),
+            mentions: envelope.mentions,
+            signature: None,
             delivered_to: vec![envelope.sender_id], // Sender sees it as delivered immediately
             read_by: vec![],
         };
         
         self.publish_processed_message(processed_msg).await?;
         
+        self.complete_client_message_id(envelope.sender_id, envelope.message_id).await?;
+        
         info!("Processed message {} from user {}", 
-              envelope.message_id, envelope.sender_id);
+              message_id, envelope.sender_id);
+        
+        Ok(())
+    }
+    
+    /// Maps the sender's client id to `message_id` unless it already maps to
+    /// another id, and returns the id to store the message under. A claim
+    /// that was never marked completed belongs to an attempt that failed
+    /// part way; the redelivery reruns it under the same id, so the stored
+    /// rows are overwritten rather than duplicated, though counters bumped
+    /// before the failure are bumped again. None when the message was fully
+    /// processed and this is a duplicate.
+    async fn claim_client_message_id(
+        &self,
+        sender_id: Uuid,
+        client_message_id: Uuid,
+        message_id: Uuid,
+    ) -> Result<Option<Uuid>, Box<dyn std::error::Error>> {
+        let result = self.scylla_session
+            .query(
+                r#"
+                INSERT INTO messaging.client_message_ids
+                (sender_id, client_message_id, message_id)
+                VALUES (?, ?, ?)
+                IF NOT EXISTS
+                USING TTL ?
+                "#,
+                (sender_id, client_message_id, message_id, CLIENT_MESSAGE_ID_TTL_SECONDS),
+            )
+            .await?;
+        
+        if lwt_applied(&result) {
+            return Ok(Some(message_id));
+        }
+        
+        let claimed = self.scylla_session
+            .query(
+                r#"
+                SELECT message_id, completed FROM messaging.client_message_ids
+                WHERE sender_id = ? AND client_message_id = ?
+                "#,
+                (sender_id, client_message_id),
+            )
+            .await?
+            .maybe_first_row_typed::<(Uuid, Option<bool>)>()?;
+        
+        // A claim that expired in between is treated as a duplicate
+        Ok(match claimed {
+            Some((claimed_id, completed)) if !completed.unwrap_or(false) => Some(claimed_id),
+            _ => None,
+        })
+    }
+    
+    /// Marks the sender's client id as fully processed, once the message has
+    /// been published for delivery.
+    async fn complete_client_message_id(
+        &self,
+        sender_id: Uuid,
+        client_message_id: Uuid,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.scylla_session
+            .query(
+                r#"
+                UPDATE messaging.client_message_ids
+                USING TTL ?
+                SET completed = true
+                WHERE sender_id = ? AND client_message_id = ?
+                "#,
+                (CLIENT_MESSAGE_ID_TTL_SECONDS, sender_id, client_message_id),
+            )
+            .await?;
         
         Ok(())
     }
     
+    /// Moves the conversation to `message_id` in the user's inbox and returns
+    /// the user's preferences for it. The compare-and-set keeps a message
+    /// arriving out of order from moving the conversation back, and only the
+    /// writer that wins a transition touches the inbox index. With `unarchive`
+    /// an archived conversation comes back unless it is muted.
+    async fn touch_inbox(
+        &self,
+        user_id: Uuid,
+        conversation_id: Uuid,
+        message_id: Uuid,
+        timestamp: DateTime<Utc>,
+        unarchive: bool,
+    ) -> Result<InboxPreferences, Box<dyn std::error::Error>> {
+        let new_position = timeuuid_datetime(&message_id);
+        
+        for _ in 0..MAX_INBOX_RETRIES {
+            let row = self.scylla_session
+                .query(
+                    r#"
+                    SELECT last_message_uuid, muted_until, archived FROM messaging.user_conversations
+                    WHERE user_id = ? AND conversation_id = ?
+                    "#,
+                    (user_id, conversation_id),
+                )
+                .await?
+                .maybe_first_row_typed::<(Option<Uuid>, Option<DateTime<Utc>>, Option<bool>)>()?;
+            
+            let (current, preferences) = match row {
+                Some((current, muted_until, archived)) => (current, InboxPreferences {
+                    muted_until,
+                    archived: archived.unwrap_or(false),
+                }),
+                None => (None, InboxPreferences::default()),
+            };
+            
+            if let Some(current_id) = current {
+                if timeuuid_datetime(&current_id) >= new_position {
+                    return Ok(preferences);
+                }
+            }
+            
+            // A missing row or column compares equal to null
+            let result = self.scylla_session
+                .query(
+                    r#"
+                    UPDATE messaging.user_conversations
+                    SET last_message_uuid = ?, last_message_at = ?
+                    WHERE user_id = ? AND conversation_id = ?
+                    IF last_message_uuid = ?
+                    "#,
+                    (message_id, timestamp, user_id, conversation_id, current),
+                )
+                .await?;
+            
+            if !lwt_applied(&result) {
+                continue;
+            }
+            
+            if let Some(previous_id) = current {
+                self.scylla_session
+                    .query(
+                        r#"
+                        DELETE FROM messaging.user_inbox
+                        WHERE user_id = ? AND last_message_id = ? AND conversation_id = ?
+                        "#,
+                        (user_id, previous_id, conversation_id),
+                    )
+                    .await?;
+            }
+            
+            self.scylla_session
+                .query(
+                    r#"
+                    INSERT INTO messaging.user_inbox
+                    (user_id, last_message_id, conversation_id)
+                    VALUES (?, ?, ?)
+                    "#,
+                    (user_id, message_id, conversation_id),
+                )
+                .await?;
+            
+            if unarchive && preferences.archived && !preferences.is_muted(timestamp) {
+                self.scylla_session
+                    .query(
+                        r#"
+                        UPDATE messaging.user_conversations
+                        SET archived = false
+                        WHERE user_id = ? AND conversation_id = ?
+                        "#,
+                        (user_id, conversation_id),
+                    )
+                    .await?;
+            }
+            
+            return Ok(preferences);
+        }
+        
+        warn!("Gave up updating inbox of user {} for conversation {}",
+              user_id, conversation_id);
+        Ok(InboxPreferences::default())
+    }
+    
     async fn get_conversation_participants(&self, conversation_id: Uuid) -> Result<Vec<Uuid>, Box<dyn std::error::Error>> {
         let members = sqlx::query!(
             r#"
@@ -207,29 +706,55 @@ impl MessageProcessor {
         Ok(members.into_iter().map(|r| r.user_id).collect())
     }
     
+    /// Whether the user and any other member of the conversation have
+    /// blocked one another.
+    async fn is_blocked_in(&self, conversation_id: Uuid, user_id: Uuid) -> Result<bool, Box<dyn std::error::Error>> {
+        let blocked = sqlx::query!(
+            r#"
+            SELECT 1 AS "blocked!"
+            FROM group_members m
+            JOIN user_relationships r
+              ON r.relationship_type = 'blocked'
+             AND ((r.user_id = $2 AND r.related_user_id = m.user_id)
+               OR (r.user_id = m.user_id AND r.related_user_id = $2))
+            WHERE m.group_id = $1 AND m.user_id <> $2
+            LIMIT 1
+            "#,
+            conversation_id,
+            user_id
+        )
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        
+        Ok(blocked.is_some())
+    }
+    
     async fn create_delivery_status(
         &self,
         message_id: Uuid,
         conversation_id: Uuid,
         participants: &[Uuid],
         sender_id: Uuid,
+        ttl_seconds: u32,
     ) -> Result<(), Box<dyn std::error::Error>> {
         for &user_id in participants {
             let delivered = user_id == sender_id; // Sender sees it as delivered immediately
             
             let query = r#"
-            INSERT INTO messaging.delivery_status 
+            INSERT INTO messaging.delivery_status_v2 
             (message_id, conversation_id, user_id, delivered, read, delivered_at)
             VALUES (?, ?, ?, ?, false, ?)
+            USING TTL ?
             "#;
             
             self.scylla_session
                 .query(query, (
-                    message_id.as_u128() as i64,
+                    message_id,
                     conversation_id,
                     user_id,
                     delivered,
                     if delivered { Some(Utc::now()) } else { None },
+                    ttl_seconds as i32,
                 ))
                 .await?;
         }
@@ -237,88 +762,1209 @@ impl MessageProcessor {
         Ok(())
     }
     
-    async fn publish_processed_message(&self, msg: ProcessedMessage) -> Result<(), Box<dyn std::error::Error>> {
-        use rdkafka::producer::FutureProducer;
+    async fn publish_notification(&self, event: NotificationEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let payload = serde_json::to_vec(&event)?;
         
-        let producer: FutureProducer = rdkafka::config::ClientConfig::new()
-            .set("bootstrap.servers", "localhost:9092")
-            .set("message.timeout.ms", "5000")
-            .create()?;
+        let record = rdkafka::producer::FutureRecord::to("notifications")
+            .key(&event.user_id.to_string())
+            .payload(&payload);
+        
+        self.kafka_producer
+            .send(record, std::time::Duration::from_secs(5))
+            .await
+            .map_err(|(e, _)| e)?;
         
+        Ok(())
+    }
+    
+    async fn publish_processed_message(&self, msg: ProcessedMessage) -> Result<(), Box<dyn std::error::Error>> {
         let payload = serde_json::to_vec(&msg)?;
         
         let record = rdkafka::producer::FutureRecord::to("processed-messages")
             .key(&msg.conversation_id.to_string())
             .payload(&payload);
         
-        producer.send(record, std::time::Duration::from_secs(5)).await?;
+        self.kafka_producer
+            .send(record, std::time::Duration::from_secs(5))
+            .await
+            .map_err(|(e, _)| e)?;
         
         Ok(())
     }
     
-    async fn handle_read_receipt(&self, user_id: Uuid, message_id: Uuid) -> Result<(), Box<dyn std::error::Error>> {
-        let query = r#"
-        UPDATE messaging.delivery_status 
-        SET read = true, read_at = ?
-        WHERE message_id = ? AND user_id = ?
-        "#;
+    async fn process_operation(&self, op: MessageOperationEnvelope) -> Result<(), Box<dyn std::error::Error>> {
+        let member = match self.get_member_permissions(op.conversation_id, op.actor_id).await? {
+            Some(member) => member,
+            None => {
+                warn!("User {} is not a member of conversation {}",
+                      op.actor_id, op.conversation_id);
+                return Ok(());
+            }
+        };
+        
+        let stored = match self.load_message(op.conversation_id, op.message_id).await? {
+            Some(stored) if !stored.deleted => stored,
+            _ => {
+                warn!("Message {} not found in conversation {}",
+                      op.message_id, op.conversation_id);
+                return Ok(());
+            }
+        };
+        
+        // Restricted users may still delete; anything else would let a
+        // muted user keep posting through edits and reactions
+        let is_delete = matches!(op.operation, MessageOperation::Delete { .. });
+        if !is_delete && !self.restrictions.can_send(op.actor_id).await? {
+            warn!("Dropped operation from restricted user {}", op.actor_id);
+            return Ok(());
+        }
+        
+        match op.operation {
+            MessageOperation::Edit { content, nonce } => {
+                // Only the author may change what a message says
+                if stored.sender_id != op.actor_id {
+                    warn!("User {} attempted to edit message {} they did not send",
+                          op.actor_id, op.message_id);
+                    return Ok(());
+                }
+                
+                self.edit_message(&op, &stored, content, nonce).await
+            }
+            MessageOperation::Delete { scope: DeleteScope::ForMe } => {
+                self.hide_message_for_user(&op).await
+            }
+            MessageOperation::Delete { scope: DeleteScope::ForEveryone } => {
+                if stored.sender_id != op.actor_id && !member.has(&Permission::DeleteMessages) {
+                    warn!("User {} attempted to delete message {} for everyone without permission",
+                          op.actor_id, op.message_id);
+                    return Ok(());
+                }
+                
+                self.delete_message_for_everyone(&op, &stored).await
+            }
+            MessageOperation::React { emoji, add } => {
+                if emoji.is_empty() || emoji.chars().count() > MAX_REACTION_LENGTH {
+                    warn!("Rejected invalid reaction from user {}", op.actor_id);
+                    return Ok(());
+                }
+                
+                self.update_reaction(&op, &stored, emoji, add).await
+            }
+            MessageOperation::Pin { pinned } => {
+                if !member.has(&Permission::PinMessages) {
+                    warn!("User {} lacks permission to pin messages in conversation {}",
+                          op.actor_id, op.conversation_id);
+                    return Ok(());
+                }
+                
+                self.update_pin(&op, &stored, pinned).await
+            }
+        }
+    }
+    
+    async fn update_reaction(
+        &self,
+        op: &MessageOperationEnvelope,
+        stored: &StoredMessage,
+        emoji: String,
+        add: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // The lightweight transaction makes repeated reacts/unreacts idempotent,
+        // so the counter only moves when the user's reaction actually changes.
+        // Counters can't expire; the expiry sweeper deletes them.
+        let result = if add {
+            self.scylla_session
+                .query(
+                    r#"
+                    INSERT INTO messaging.message_reactions
+                    (conversation_id, message_id, emoji, user_id, reacted_at)
+                    VALUES (?, ?, ?, ?, ?)
+                    IF NOT EXISTS
+                    USING TTL ?
+                    "#,
+                    (op.conversation_id, op.message_id, &emoji, op.actor_id, Utc::now(), stored.ttl_seconds),
+                )
+                .await?
+        } else {
+            self.scylla_session
+                .query(
+                    r#"
+                    DELETE FROM messaging.message_reactions
+                    WHERE conversation_id = ? AND message_id = ? AND emoji = ? AND user_id = ?
+                    IF EXISTS
+                    "#,
+                    (op.conversation_id, op.message_id, &emoji, op.actor_id),
+                )
+                .await?
+        };
+        
+        if !lwt_applied(&result) {
+            return Ok(());
+        }
         
+        let delta: i64 = if add { 1 } else { -1 };
         self.scylla_session
-            .query(query, (
-                Utc::now(),
-                message_id.as_u128() as i64,
-                user_id,
-            ))
+            .query(
+                r#"
+                UPDATE messaging.message_reaction_counts
+                SET count = count + ?
+                WHERE conversation_id = ? AND message_id = ? AND emoji = ?
+                "#,
+                (Counter(delta), op.conversation_id, op.message_id, &emoji),
+            )
             .await?;
         
-        // Publish read receipt update
-        let receipt_update = ReadReceiptUpdate {
-            user_id,
-            message_id,
-            read_at: Utc::now().timestamp(),
-        };
+        let counts = self.get_reaction_counts(op.conversation_id, op.message_id).await?;
+        let recipients = self.get_conversation_participants(op.conversation_id).await?;
         
-        let producer: rdkafka::producer::FutureProducer = rdkafka::config::ClientConfig::new()
-            .set("bootstrap.servers", "localhost:9092")
-            .create()?;
+        self.publish_message_update(MessageUpdate {
+            recipients,
+            event: MessageUpdateEvent::ReactionsUpdated {
+                conversation_id: op.conversation_id,
+                message_id: op.message_id,
+                user_id: op.actor_id,
+                emoji,
+                added: add,
+                counts,
+            },
+        }).await?;
+        
+        Ok(())
+    }
+    
+    async fn get_reaction_counts(
+        &self,
+        conversation_id: Uuid,
+        message_id: Uuid,
+    ) -> Result<HashMap<String, i64>, Box<dyn std::error::Error>> {
+        let rows = self.scylla_session
+            .query(
+                r#"
+                SELECT emoji, count FROM messaging.message_reaction_counts
+                WHERE conversation_id = ? AND message_id = ?
+                "#,
+                (conversation_id, message_id),
+            )
+            .await?
+            .rows_typed::<(String, Counter)>()?;
         
-        let payload = serde_json::to_vec(&receipt_update)?;
+        let mut counts = HashMap::new();
+        for row in rows {
+            let (emoji, Counter(count)) = row?;
+            if count > 0 {
+                counts.insert(emoji, count);
+            }
+        }
         
-        let record = rdkafka::producer::FutureRecord::to("read-receipts")
-            .key(&user_id.to_string())
-            .payload(&payload);
+        Ok(counts)
+    }
+    
+    async fn update_pin(
+        &self,
+        op: &MessageOperationEnvelope,
+        stored: &StoredMessage,
+        pinned: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let now = Utc::now();
+        
+        if pinned {
+            self.scylla_session
+                .query(
+                    r#"
+                    INSERT INTO messaging.pinned_messages
+                    (conversation_id, message_id, pinned_by, pinned_at)
+                    VALUES (?, ?, ?, ?)
+                    USING TTL ?
+                    "#,
+                    (op.conversation_id, op.message_id, op.actor_id, now, stored.ttl_seconds),
+                )
+                .await?;
+        } else {
+            self.scylla_session
+                .query(
+                    r#"
+                    DELETE FROM messaging.pinned_messages
+                    WHERE conversation_id = ? AND message_id = ?
+                    "#,
+                    (op.conversation_id, op.message_id),
+                )
+                .await?;
+        }
+        
+        let recipients = self.get_conversation_participants(op.conversation_id).await?;
+        
+        self.publish_message_update(MessageUpdate {
+            recipients,
+            event: MessageUpdateEvent::PinUpdated {
+                conversation_id: op.conversation_id,
+                message_id: op.message_id,
+                pinned_by: op.actor_id,
+                pinned,
+                updated_at: now.timestamp(),
+            },
+        }).await?;
         
-        producer.send(record, std::time::Duration::from_secs(5)).await?;
+        info!("Message {} {} by user {}", op.message_id,
+              if pinned { "pinned" } else { "unpinned" }, op.actor_id);
         
         Ok(())
     }
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct MessageEnvelope {
-    sender_id: Uuid,
-    conversation_id: Uuid,
-    message_id: Uuid,
-    content: Vec<u8>,
-    nonce: Vec<u8>,
-    reply_to: Option<Uuid>,
-    timestamp: i64,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct ReadReceiptUpdate {
-    user_id: Uuid,
-    message_id: Uuid,
-    read_at: i64,
-}
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt::init();
     
-    dotenv::dotenv().ok();
+    /// The thread a reply to `parent_id` joins, or None when the parent
+    /// isn't a stored message of `conversation_id`.
+    async fn resolve_thread_root(
+        &self,
+        conversation_id: Uuid,
+        parent_id: Uuid,
+    ) -> Result<Option<Uuid>, Box<dyn std::error::Error>> {
+        let parent = self.scylla_session
+            .query(
+                "SELECT conversation_id, thread_root FROM messaging.message_locations WHERE message_id = ?",
+                (parent_id,),
+            )
+            .await?
+            .maybe_first_row_typed::<(Option<Uuid>, Option<Uuid>)>()?;
+        
+        Ok(match parent {
+            Some((Some(parent_conversation), root)) if parent_conversation == conversation_id => {
+                Some(root.unwrap_or(parent_id))
+            }
+            _ => None,
+        })
+    }
+    
+    async fn record_thread_reply(
+        &self,
+        conversation_id: Uuid,
+        root_id: Uuid,
+        reply_id: Uuid,
+        sender_id: Uuid,
+        bucket_id: i32,
+        sent_at: DateTime<Utc>,
+        ttl_seconds: u32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Counters can't expire, so only the reply's own entry carries the TTL
+        self.scylla_session
+            .query(
+                r#"
+                INSERT INTO messaging.message_threads
+                (conversation_id, root_id, sent_at, message_id, sender_id, bucket_id)
+                VALUES (?, ?, ?, ?, ?, ?)
+                USING TTL ?
+                "#,
+                (conversation_id, root_id, sent_at, reply_id, sender_id, bucket_id, ttl_seconds as i32),
+            )
+            .await?;
+        
+        self.scylla_session
+            .query(
+                r#"
+                UPDATE messaging.thread_reply_counts
+                SET reply_count = reply_count + 1
+                WHERE conversation_id = ? AND root_id = ?
+                "#,
+                (conversation_id, root_id),
+            )
+            .await?;
+        
+        let reply_count = self.scylla_session
+            .query(
+                r#"
+                SELECT reply_count FROM messaging.thread_reply_counts
+                WHERE conversation_id = ? AND root_id = ?
+                "#,
+                (conversation_id, root_id),
+            )
+            .await?
+            .maybe_first_row_typed::<(Counter,)>()?
+            .map(|(Counter(count),)| count)
+            .unwrap_or(0);
+        
+        let recipients = self.get_conversation_participants(conversation_id).await?;
+        
+        self.publish_message_update(MessageUpdate {
+            recipients,
+            event: MessageUpdateEvent::ThreadUpdated {
+                conversation_id,
+                root_id,
+                reply_id,
+                reply_count,
+            },
+        }).await?;
+        
+        Ok(())
+    }
+    
+    async fn edit_message(
+        &self,
+        op: &MessageOperationEnvelope,
+        stored: &StoredMessage,
+        content: Vec<u8>,
+        nonce: Vec<u8>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let now = Utc::now();
+        
+        self.record_revision(op, stored, now).await?;
+        
+        self.scylla_session
+            .query(
+                r#"
+                UPDATE messaging.messages_v2
+                USING TTL ?
+                SET content = ?, nonce = ?, edited = true
+                WHERE conversation_id = ? AND bucket_id = ? AND message_id = ?
+                "#,
+                (
+                    stored.ttl_seconds,
+                    &content,
+                    &nonce,
+                    op.conversation_id,
+                    stored.bucket_id,
+                    op.message_id,
+                ),
+            )
+            .await?;
+        
+        let recipients = self.get_conversation_participants(op.conversation_id).await?;
+        
+        self.publish_message_update(MessageUpdate {
+            recipients,
+            event: MessageUpdateEvent::Edited {
+                conversation_id: op.conversation_id,
+                message_id: op.message_id,
+                editor_id: op.actor_id,
+                content,
+                nonce,
+                edited_at: now.timestamp(),
+            },
+        }).await?;
+        
+        info!("Message {} edited by user {}", op.message_id, op.actor_id);
+        
+        Ok(())
+    }
+    
+    async fn delete_message_for_everyone(
+        &self,
+        op: &MessageOperationEnvelope,
+        stored: &StoredMessage,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let now = Utc::now();
+        
+        // Keep the final ciphertext in the history so moderators can still review it
+        self.record_revision(op, stored, now).await?;
+        
+        self.scylla_session
+            .query(
+                r#"
+                UPDATE messaging.messages_v2
+                USING TTL ?
+                SET content = ?, nonce = ?, deleted = true
+                WHERE conversation_id = ? AND bucket_id = ? AND message_id = ?
+                "#,
+                (
+                    stored.ttl_seconds,
+                    Vec::<u8>::new(),
+                    Vec::<u8>::new(),
+                    op.conversation_id,
+                    stored.bucket_id,
+                    op.message_id,
+                ),
+            )
+            .await?;
+        
+        let recipients = self.get_conversation_participants(op.conversation_id).await?;
+        
+        self.publish_message_update(MessageUpdate {
+            recipients,
+            event: MessageUpdateEvent::Deleted {
+                conversation_id: op.conversation_id,
+                message_id: op.message_id,
+                deleted_by: op.actor_id,
+                scope: DeleteScope::ForEveryone,
+                deleted_at: now.timestamp(),
+            },
+        }).await?;
+        
+        info!("Message {} deleted for everyone by user {}", op.message_id, op.actor_id);
+        
+        Ok(())
+    }
+    
+    /// Moderators are usually not members of the conversation, so their
+    /// deletions skip the membership and permission checks in
+    /// `process_operation`. Other actions are applied by the moderation
+    /// service itself.
+    async fn handle_moderation_action(&self, event: ModerationActionEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let (conversation_id, message_id) = match (event.action, event.target) {
+            (ModerationAction::DeleteMessage, ModerationTarget::Message { conversation_id, message_id }) => {
+                (conversation_id, message_id)
+            }
+            _ => return Ok(()),
+        };
+        
+        let stored = match self.load_message(conversation_id, message_id).await? {
+            Some(stored) if !stored.deleted => stored,
+            _ => {
+                warn!("Message {} not found in conversation {}", message_id, conversation_id);
+                return Ok(());
+            }
+        };
+        
+        let op = MessageOperationEnvelope {
+            actor_id: event.moderator_id,
+            conversation_id,
+            message_id,
+            operation: MessageOperation::Delete { scope: DeleteScope::ForEveryone },
+            timestamp: event.timestamp,
+        };
+        
+        self.delete_message_for_everyone(&op, &stored).await
+    }
+    
+    async fn hide_message_for_user(&self, op: &MessageOperationEnvelope) -> Result<(), Box<dyn std::error::Error>> {
+        let now = Utc::now();
+        
+        self.scylla_session
+            .query(
+                r#"
+                INSERT INTO messaging.hidden_messages
+                (user_id, conversation_id, message_id, hidden_at)
+                VALUES (?, ?, ?, ?)
+                "#,
+                (op.actor_id, op.conversation_id, op.message_id, now),
+            )
+            .await?;
+        
+        // Only the user's own devices need to drop the message
+        self.publish_message_update(MessageUpdate {
+            recipients: vec![op.actor_id],
+            event: MessageUpdateEvent::Deleted {
+                conversation_id: op.conversation_id,
+                message_id: op.message_id,
+                deleted_by: op.actor_id,
+                scope: DeleteScope::ForMe,
+                deleted_at: now.timestamp(),
+            },
+        }).await?;
+        
+        Ok(())
+    }
+    
+    async fn record_revision(
+        &self,
+        op: &MessageOperationEnvelope,
+        stored: &StoredMessage,
+        edited_at: DateTime<Utc>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.scylla_session
+            .query(
+                r#"
+                INSERT INTO messaging.message_edits
+                (message_id, edited_at, conversation_id, editor_id, previous_content, previous_nonce)
+                VALUES (?, ?, ?, ?, ?, ?)
+                USING TTL ?
+                "#,
+                (
+                    op.message_id,
+                    edited_at,
+                    op.conversation_id,
+                    op.actor_id,
+                    &stored.content,
+                    &stored.nonce,
+                    stored.ttl_seconds,
+                ),
+            )
+            .await?;
+        
+        Ok(())
+    }
+    
+    async fn load_message(
+        &self,
+        conversation_id: Uuid,
+        message_id: Uuid,
+    ) -> Result<Option<StoredMessage>, Box<dyn std::error::Error>> {
+        let location = self.scylla_session
+            .query(
+                "SELECT bucket_id FROM messaging.message_locations WHERE message_id = ?",
+                (message_id,),
+            )
+            .await?
+            .maybe_first_row_typed::<(i32,)>()?;
+        
+        let bucket_id = match location {
+            Some((bucket_id,)) => bucket_id,
+            None => return Ok(None),
+        };
+        
+        let row = self.scylla_session
+            .query(
+                r#"
+                SELECT sender_id, content, nonce, deleted, TTL(content)
+                FROM messaging.messages_v2
+                WHERE conversation_id = ? AND bucket_id = ? AND message_id = ?
+                "#,
+                (conversation_id, bucket_id, message_id),
+            )
+            .await?
+            .maybe_first_row_typed::<(Uuid, Vec<u8>, Vec<u8>, bool, Option<i32>)>()?;
+        
+        Ok(row.map(|(sender_id, content, nonce, deleted, ttl)| StoredMessage {
+            bucket_id,
+            sender_id,
+            content,
+            nonce,
+            deleted,
+            // TTL() is null for cells written without one
+            ttl_seconds: ttl.unwrap_or(0),
+        }))
+    }
+    
+    async fn get_member_role(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<GroupRole>, Box<dyn std::error::Error>> {
+        let member = sqlx::query!(
+            r#"
+            SELECT role FROM group_members 
+            WHERE group_id = $1 AND user_id = $2 AND is_banned = false
+            "#,
+            conversation_id,
+            user_id
+        )
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        
+        Ok(member.and_then(|m| m.role.parse().ok()))
+    }
+    
+    async fn get_member_permissions(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<MemberPermissions>, Box<dyn std::error::Error>> {
+        let member = sqlx::query!(
+            r#"
+            SELECT role, granted_permissions, denied_permissions FROM group_members 
+            WHERE group_id = $1 AND user_id = $2 AND is_banned = false
+            "#,
+            conversation_id,
+            user_id
+        )
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        
+        Ok(member.and_then(|m| {
+            let role = m.role.parse().ok()?;
+            Some(MemberPermissions::new(
+                role,
+                parse_permissions(&m.granted_permissions),
+                parse_permissions(&m.denied_permissions),
+            ))
+        }))
+    }
+    
+    async fn get_conversation_settings(
+        &self,
+        conversation_id: Uuid,
+    ) -> Result<ConversationSettings, Box<dyn std::error::Error>> {
+        let conversation = sqlx::query!(
+            "SELECT only_admins_can_send, slow_mode_seconds FROM conversations WHERE id = $1",
+            conversation_id
+        )
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        
+        Ok(conversation
+            .map(|c| ConversationSettings {
+                only_admins_can_send: c.only_admins_can_send,
+                slow_mode_seconds: c.slow_mode_seconds as u32,
+            })
+            .unwrap_or_default())
+    }
+    
+    /// Returns when the user may next send if slow mode holds them back;
+    /// otherwise records this send and returns `None`.
+    async fn check_slow_mode(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        member: &MemberPermissions,
+        settings: &ConversationSettings,
+    ) -> Option<DateTime<Utc>> {
+        member.slow_mode(settings)?;
+        
+        let now = Utc::now();
+        let key = (conversation_id, user_id);
+        let mut last_sent = self.last_sent.write().await;
+        
+        if let Some(next_allowed) = member.next_send_allowed_at(settings, last_sent.get(&key).copied(), now) {
+            return Some(next_allowed);
+        }
+        
+        if last_sent.len() >= SLOW_MODE_PRUNE_THRESHOLD {
+            let cutoff = now - chrono::Duration::seconds(MAX_SLOW_MODE_SECONDS as i64);
+            last_sent.retain(|_, sent_at| *sent_at > cutoff);
+        }
+        
+        last_sent.insert(key, now);
+        None
+    }
+    
+    async fn get_message_ttl(&self, conversation_id: Uuid) -> Result<u32, Box<dyn std::error::Error>> {
+        let conversation = sqlx::query!(
+            "SELECT message_ttl_seconds FROM conversations WHERE id = $1",
+            conversation_id
+        )
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        
+        Ok(conversation.map(|c| c.message_ttl_seconds as u32).unwrap_or(0))
+    }
+    
+    async fn handle_membership_event(&self, event: MembershipEvent) -> Result<(), Box<dyn std::error::Error>> {
+        if let MembershipChange::Deleted = event.change {
+            return self.delete_conversation_data(event.conversation_id, &event.recipients).await;
+        }
+        
+        // Who subscribes to or moderates a channel is private, so a channel's
+        // timeline only records changes to the channel itself
+        let about_member = matches!(
+            event.change,
+            MembershipChange::MembersAdded { .. }
+                | MembershipChange::MemberRemoved { .. }
+                | MembershipChange::RoleChanged { .. }
+                | MembershipChange::PermissionsChanged { .. }
+        );
+        if event.conversation_type == ConversationType::Channel && about_member {
+            return Ok(());
+        }
+        
+        match SystemEvent::from_membership_change(event.actor_id, event.change) {
+            Some(system_event) => {
+                self.post_system_message(
+                    event.conversation_id,
+                    event.conversation_type,
+                    event.actor_id,
+                    system_event,
+                ).await
+            }
+            None => Ok(()),
+        }
+    }
+    
+    /// Drops a deleted conversation from its former members' inboxes and
+    /// deletes its history. Reactions, edits and threads are keyed by message
+    /// and left to expire unreachable; without a location nothing finds them.
+    async fn delete_conversation_data(
+        &self,
+        conversation_id: Uuid,
+        members: &[Uuid],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for &user_id in members {
+            let last_message = self.scylla_session
+                .query(
+                    r#"
+                    SELECT last_message_uuid FROM messaging.user_conversations
+                    WHERE user_id = ? AND conversation_id = ?
+                    "#,
+                    (user_id, conversation_id),
+                )
+                .await?
+                .maybe_first_row_typed::<(Option<Uuid>,)>()?
+                .and_then(|(last_message,)| last_message);
+            
+            if let Some(last_message_id) = last_message {
+                self.scylla_session
+                    .query(
+                        r#"
+                        DELETE FROM messaging.user_inbox
+                        WHERE user_id = ? AND last_message_id = ? AND conversation_id = ?
+                        "#,
+                        (user_id, last_message_id, conversation_id),
+                    )
+                    .await?;
+            }
+            
+            for table in ["user_conversations", "conversation_counters", "read_markers"] {
+                self.scylla_session
+                    .query(
+                        format!(
+                            "DELETE FROM messaging.{} WHERE user_id = ? AND conversation_id = ?",
+                            table
+                        ),
+                        (user_id, conversation_id),
+                    )
+                    .await?;
+            }
+        }
+        
+        let buckets = self.scylla_session
+            .query(
+                "SELECT bucket_id FROM messaging.conversation_buckets WHERE conversation_id = ?",
+                (conversation_id,),
+            )
+            .await?
+            .rows_typed::<(i32,)>()?
+            .collect::<Result<Vec<_>, _>>()?;
+        
+        for (bucket_id,) in buckets {
+            let message_ids = self.scylla_session
+                .query(
+                    r#"
+                    SELECT message_id FROM messaging.messages_v2
+                    WHERE conversation_id = ? AND bucket_id = ?
+                    "#,
+                    (conversation_id, bucket_id),
+                )
+                .await?
+                .rows_typed::<(Uuid,)>()?
+                .collect::<Result<Vec<_>, _>>()?;
+            
+            for (message_id,) in message_ids {
+                self.scylla_session
+                    .query(
+                        "DELETE FROM messaging.message_locations WHERE message_id = ?",
+                        (message_id,),
+                    )
+                    .await?;
+            }
+            
+            self.scylla_session
+                .query(
+                    "DELETE FROM messaging.messages_v2 WHERE conversation_id = ? AND bucket_id = ?",
+                    (conversation_id, bucket_id),
+                )
+                .await?;
+        }
+        
+        for table in ["conversation_buckets", "pinned_messages"] {
+            self.scylla_session
+                .query(
+                    format!("DELETE FROM messaging.{} WHERE conversation_id = ?", table),
+                    (conversation_id,),
+                )
+                .await?;
+        }
+        
+        info!("Deleted conversation {} for {} members", conversation_id, members.len());
+        
+        Ok(())
+    }
+    
+    /// Stores a system message in the conversation's history and delivers it
+    /// like any other. System messages are plaintext JSON, signed so clients
+    /// can trust them as an audit trail, and never expire.
+    async fn post_system_message(
+        &self,
+        conversation_id: Uuid,
+        conversation_type: ConversationType,
+        actor_id: Uuid,
+        system_event: SystemEvent,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let message_id = Uuid::now_v1(&self.node_id);
+        let timestamp = timeuuid_datetime(&message_id).unwrap_or_else(Utc::now);
+        let bucket_id = day_bucket(timestamp);
+        let content = serde_json::to_vec(&system_event)?;
+        let signature = self.system_signer.sign(&conversation_id, &message_id, &content);
+        
+        self.scylla_session
+            .query(
+                r#"
+                INSERT INTO messaging.messages_v2 
+                (conversation_id, bucket_id, message_id, sender_id, 
+                 message_type, content, nonce, timestamp, 
+                 edited, deleted, encryption_version, signature)
+                VALUES (?, ?, ?, ?, 'system', ?, ?, ?, false, false, 0, ?)
+                "#,
+                (
+                    conversation_id,
+                    bucket_id,
+                    message_id,
+                    actor_id,
+                    &content,
+                    Vec::<u8>::new(),
+                    timestamp,
+                    &signature,
+                ),
+            )
+            .await?;
+        
+        self.scylla_session
+            .query(
+                r#"
+                INSERT INTO messaging.conversation_buckets
+                (conversation_id, bucket_id)
+                VALUES (?, ?)
+                "#,
+                (conversation_id, bucket_id),
+            )
+            .await?;
+        
+        self.scylla_session
+            .query(
+                r#"
+                INSERT INTO messaging.message_locations
+                (message_id, conversation_id, bucket_id)
+                VALUES (?, ?, ?)
+                "#,
+                (message_id, conversation_id, bucket_id),
+            )
+            .await?;
+        
+        let recipients = if conversation_type != ConversationType::Channel {
+            self.get_conversation_participants(conversation_id).await?
+        } else {
+            Vec::new()
+        };
+        
+        // Surfaces the conversation in members' inboxes without counting as unread
+        for &participant_id in &recipients {
+            self.touch_inbox(participant_id, conversation_id, message_id, timestamp, false).await?;
+        }
+        
+        self.publish_processed_message(ProcessedMessage {
+            message_id,
+            client_message_id: None,
+            sender_device_id: None,
+            conversation_id,
+            conversation_type,
+            recipients,
+            sender_id: actor_id,
+            message_type: MessageType::System,
+            content,
+            nonce: vec![],
+            reply_to: None,
+            timestamp: timestamp.timestamp(),
+            expires_at: None,
+            mentions: MessageMentions::default(),
+            signature: Some(signature),
+            delivered_to: vec![],
+            read_by: vec![],
+        }).await?;
+        
+        info!("Posted system message {} in conversation {}", message_id, conversation_id);
+        
+        Ok(())
+    }
+    
+    async fn get_conversation_type(
+        &self,
+        conversation_id: Uuid,
+    ) -> Result<Option<ConversationType>, Box<dyn std::error::Error>> {
+        let conversation = sqlx::query!(
+            "SELECT conversation_type FROM conversations WHERE id = $1",
+            conversation_id
+        )
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        
+        Ok(conversation.and_then(|c| c.conversation_type.parse().ok()))
+    }
+    
+    async fn publish_message_update(&self, update: MessageUpdate) -> Result<(), Box<dyn std::error::Error>> {
+        let key = update.event.conversation_id().to_string();
+        let payload = serde_json::to_vec(&update)?;
+        
+        let record = rdkafka::producer::FutureRecord::to("message-updates")
+            .key(&key)
+            .payload(&payload);
+        
+        self.kafka_producer
+            .send(record, std::time::Duration::from_secs(5))
+            .await
+            .map_err(|(e, _)| e)?;
+        
+        Ok(())
+    }
+    
+    async fn handle_read_receipt(&self, receipt: ReadReceiptEnvelope) -> Result<(), Box<dyn std::error::Error>> {
+        let user_id = receipt.user_id;
+        let conversation_id = receipt.conversation_id;
+        let message_id = receipt.message_id;
+        
+        if self.get_member_role(conversation_id, user_id).await?.is_none() {
+            warn!("User {} is not a member of conversation {}", user_id, conversation_id);
+            return Ok(());
+        }
+        
+        let stored = match self.load_message(conversation_id, message_id).await? {
+            Some(stored) => stored,
+            None => {
+                warn!("Read receipt for unknown message {}", message_id);
+                return Ok(());
+            }
+        };
+        
+        let is_channel = self.get_conversation_type(conversation_id).await?
+            == Some(ConversationType::Channel);
+        let read_at = Utc::now();
+        
+        if is_channel {
+            // Channel posts have no per-subscriber delivery rows; reading one counts a view
+            self.record_view(message_id, user_id).await?;
+        } else {
+            let query = r#"
+            UPDATE messaging.delivery_status_v2 
+            SET read = true, read_at = ?
+            WHERE message_id = ? AND user_id = ?
+            "#;
+            
+            self.scylla_session
+                .query(query, (
+                    read_at,
+                    message_id,
+                    user_id,
+                ))
+                .await?;
+        }
+        
+        // Receipts for older messages arrive out of order from other devices;
+        // they still mark the message read but never move the marker back
+        if !self.advance_read_marker(user_id, conversation_id, message_id, read_at).await? {
+            return Ok(());
+        }
+        
+        if !is_channel {
+            self.reset_unread_counts(user_id, conversation_id).await?;
+        }
+        
+        // Keep the reader's other devices in sync
+        self.publish_message_update(MessageUpdate {
+            recipients: vec![user_id],
+            event: MessageUpdateEvent::ReadMarkerUpdated {
+                conversation_id,
+                last_read_message_id: message_id,
+                read_at: read_at.timestamp(),
+            },
+        }).await?;
+        
+        // Channel admins only ever see view counts, never who read a post
+        if !is_channel && stored.sender_id != user_id && self.sends_read_receipts(user_id).await? {
+            self.publish_message_update(MessageUpdate {
+                recipients: vec![stored.sender_id],
+                event: MessageUpdateEvent::MessageRead {
+                    conversation_id,
+                    message_id,
+                    reader_id: user_id,
+                    read_at: read_at.timestamp(),
+                },
+            }).await?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Counts `user_id` as a viewer of a channel post, at most once per user.
+    async fn record_view(&self, message_id: Uuid, user_id: Uuid) -> Result<(), Box<dyn std::error::Error>> {
+        let result = self.scylla_session
+            .query(
+                r#"
+                INSERT INTO messaging.message_viewers (message_id, user_id)
+                VALUES (?, ?)
+                IF NOT EXISTS
+                "#,
+                (message_id, user_id),
+            )
+            .await?;
+        
+        if lwt_applied(&result) {
+            self.scylla_session
+                .query(
+                    "UPDATE messaging.message_views SET views = views + 1 WHERE message_id = ?",
+                    (message_id,),
+                )
+                .await?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Moves the user's "read up to" marker forward to `message_id`. Returns
+    /// false if the marker already points at this message or a later one.
+    async fn advance_read_marker(
+        &self,
+        user_id: Uuid,
+        conversation_id: Uuid,
+        message_id: Uuid,
+        read_at: DateTime<Utc>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let new_position = timeuuid_datetime(&message_id);
+        
+        // Compare-and-set so two devices reading at once can't regress the marker
+        for _ in 0..MAX_MARKER_RETRIES {
+            let current = self.scylla_session
+                .query(
+                    r#"
+                    SELECT last_read_message_id FROM messaging.read_markers
+                    WHERE user_id = ? AND conversation_id = ?
+                    "#,
+                    (user_id, conversation_id),
+                )
+                .await?
+                .maybe_first_row_typed::<(Uuid,)>()?;
+            
+            let result = match current {
+                None => {
+                    self.scylla_session
+                        .query(
+                            r#"
+                            INSERT INTO messaging.read_markers
+                            (user_id, conversation_id, last_read_message_id, read_at)
+                            VALUES (?, ?, ?, ?)
+                            IF NOT EXISTS
+                            "#,
+                            (user_id, conversation_id, message_id, read_at),
+                        )
+                        .await?
+                }
+                Some((current_id,)) => {
+                    if timeuuid_datetime(&current_id) >= new_position {
+                        return Ok(false);
+                    }
+                    
+                    self.scylla_session
+                        .query(
+                            r#"
+                            UPDATE messaging.read_markers
+                            SET last_read_message_id = ?, read_at = ?
+                            WHERE user_id = ? AND conversation_id = ?
+                            IF last_read_message_id = ?
+                            "#,
+                            (message_id, read_at, user_id, conversation_id, current_id),
+                        )
+                        .await?
+                }
+            };
+            
+            if lwt_applied(&result) {
+                return Ok(true);
+            }
+        }
+        
+        warn!("Gave up advancing read marker for user {} in conversation {}",
+              user_id, conversation_id);
+        Ok(false)
+    }
+    
+    /// Counters can't be set, only adjusted, so subtract whatever is there.
+    /// A message arriving in between survives as a count of one.
+    async fn reset_unread_counts(&self, user_id: Uuid, conversation_id: Uuid) -> Result<(), Box<dyn std::error::Error>> {
+        let row = self.scylla_session
+            .query(
+                r#"
+                SELECT unread_count, mention_count FROM messaging.conversation_counters
+                WHERE user_id = ? AND conversation_id = ?
+                "#,
+                (user_id, conversation_id),
+            )
+            .await?
+            .maybe_first_row_typed::<(Option<Counter>, Option<Counter>)>()?;
+        
+        let (unread, mentions) = match row {
+            Some((unread, mentions)) => (
+                unread.map(|Counter(count)| count).unwrap_or(0),
+                mentions.map(|Counter(count)| count).unwrap_or(0),
+            ),
+            None => return Ok(()),
+        };
+        
+        if unread != 0 || mentions != 0 {
+            self.scylla_session
+                .query(
+                    r#"
+                    UPDATE messaging.conversation_counters
+                    SET unread_count = unread_count - ?,
+                        mention_count = mention_count - ?
+                    WHERE user_id = ? AND conversation_id = ?
+                    "#,
+                    (Counter(unread), Counter(mentions), user_id, conversation_id),
+                )
+                .await?;
+        }
+        
+        Ok(())
+    }
+    
+    async fn sends_read_receipts(&self, user_id: Uuid) -> Result<bool, Box<dyn std::error::Error>> {
+        let settings = sqlx::query!(
+            "SELECT send_read_receipts FROM user_privacy_settings WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        
+        // Receipts are on unless the user turned them off
+        Ok(settings.map(|s| s.send_read_receipts).unwrap_or(true))
+    }
+}
+
+/// Random node id for this processor's timeuuids, with the multicast bit set
+/// as RFC 4122 requires for ids not derived from a MAC address.
+fn generate_node_id() -> [u8; 6] {
+    let mut node_id = [0u8; 6];
+    node_id.copy_from_slice(&Uuid::new_v4().as_bytes()[..6]);
+    node_id[0] |= 0x01;
+    node_id
+}
+
+fn lwt_applied(result: &QueryResult) -> bool {
+    result
+        .first_row()
+        .ok()
+        .and_then(|row| row.columns.first().cloned().flatten())
+        .and_then(|value| value.as_boolean())
+        .unwrap_or(false)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MessageEnvelope {
+    sender_id: Uuid,
+    conversation_id: Uuid,
+    message_id: Uuid,
+    content: Vec<u8>,
+    nonce: Vec<u8>,
+    reply_to: Option<Uuid>,
+    timestamp: i64,
+    #[serde(default)]
+    mentions: MessageMentions,
+    // Absent for messages the scheduler sends
+    #[serde(default)]
+    sender_device_id: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt::init();
+    
+    dotenv::dotenv().ok();
+    
+    let processor = MessageProcessor::new().await?;
+    
+    // Tell clients when disappearing messages expire
+    let sweeper = expiry::ExpirySweeper::new(
+        processor.scylla_session.clone(),
+        processor.kafka_producer.clone(),
+        processor.pg_pool.clone(),
+    );
+    tokio::spawn(sweeper.run());
+    
+    // Serve the read API alongside the Kafka consumer
+    let api_state = Arc::new(api::ApiState {
+        scylla_session: processor.scylla_session.clone(),
+        pg_pool: processor.pg_pool.clone(),
+        system_message_key: general_purpose::STANDARD
+            .encode(processor.system_signer.verifying_key().as_bytes()),
+        restrictions: processor.restrictions.clone(),
+    });
+    tokio::spawn(async move {
+        if let Err(e) = api::serve(api_state).await {
+            error!("Messaging API error: {}", e);
+        }
+    });
     
-    let processor = MessageProcessor::new().await?;
     processor.process_messages().await?;
     
     Ok(())
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS user_privacy_settings (
+    user_id UUID PRIMARY KEY,
+    send_read_receipts BOOLEAN NOT NULL DEFAULT TRUE,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
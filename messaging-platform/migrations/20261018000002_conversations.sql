@@ -0,0 +1,31 @@
+-- conversations and group_members predate this repository's migrations, so
+-- the tables are only created on fresh databases and the columns added
+-- since are added to existing ones separately.
+CREATE TABLE IF NOT EXISTS conversations (
+    id UUID PRIMARY KEY,
+    conversation_type TEXT NOT NULL,
+    name TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_message_at TIMESTAMPTZ
+);
+
+-- Null for conversations created before it was recorded
+ALTER TABLE conversations ADD COLUMN IF NOT EXISTS created_by UUID;
+ALTER TABLE conversations ADD COLUMN IF NOT EXISTS avatar_url TEXT;
+ALTER TABLE conversations ADD COLUMN IF NOT EXISTS is_encrypted BOOLEAN NOT NULL DEFAULT TRUE;
+-- "<lower user id>:<higher user id>" for direct messages so each pair has one
+ALTER TABLE conversations ADD COLUMN IF NOT EXISTS dm_key TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_conversations_dm_key ON conversations(dm_key);
+
+CREATE TABLE IF NOT EXISTS group_members (
+    group_id UUID NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL,
+    role TEXT NOT NULL DEFAULT 'member',
+    joined_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (group_id, user_id)
+);
+
+ALTER TABLE group_members ADD COLUMN IF NOT EXISTS is_banned BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX IF NOT EXISTS idx_group_members_user ON group_members(user_id);
@@ -0,0 +1,29 @@
+CREATE TABLE IF NOT EXISTS group_invites (
+    id UUID PRIMARY KEY,
+    group_id UUID NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    code TEXT NOT NULL UNIQUE,
+    created_by UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ,
+    max_uses INTEGER,
+    use_count INTEGER NOT NULL DEFAULT 0,
+    requires_approval BOOLEAN NOT NULL DEFAULT FALSE,
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_group_invites_group ON group_invites(group_id);
+
+CREATE TABLE IF NOT EXISTS join_requests (
+    id UUID PRIMARY KEY,
+    group_id UUID NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL,
+    invite_id UUID NOT NULL REFERENCES group_invites(id) ON DELETE CASCADE,
+    status TEXT NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    decided_by UUID,
+    decided_at TIMESTAMPTZ
+);
+
+-- At most one open request per user and group
+CREATE UNIQUE INDEX IF NOT EXISTS idx_join_requests_pending
+    ON join_requests(group_id, user_id) WHERE status = 'pending';
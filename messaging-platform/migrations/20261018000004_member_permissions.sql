@@ -0,0 +1,10 @@
+ALTER TABLE group_members
+    ADD COLUMN IF NOT EXISTS granted_permissions TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS denied_permissions TEXT[] NOT NULL DEFAULT '{}';
+
+ALTER TABLE conversations
+    ADD COLUMN IF NOT EXISTS only_admins_can_send BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN IF NOT EXISTS slow_mode_seconds INTEGER NOT NULL DEFAULT 0;
+
+-- Channels were already admin-only in the processor; make the setting say so
+UPDATE conversations SET only_admins_can_send = TRUE WHERE conversation_type = 'channel';
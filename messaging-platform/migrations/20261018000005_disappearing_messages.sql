@@ -0,0 +1,3 @@
+-- Lifetime of new messages in seconds; 0 keeps them forever
+ALTER TABLE conversations
+    ADD COLUMN IF NOT EXISTS message_ttl_seconds INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS scheduled_messages (
+    id UUID PRIMARY KEY,
+    sender_id UUID NOT NULL,
+    conversation_id UUID NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    content BYTEA NOT NULL, -- Client-encrypted
+    nonce BYTEA NOT NULL,
+    reply_to UUID,
+    deliver_at TIMESTAMPTZ NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    sent_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_scheduled_messages_sender
+    ON scheduled_messages(sender_id, deliver_at) WHERE status = 'pending';
+
+-- The dispatcher only ever scans messages that are still due to go out
+CREATE INDEX IF NOT EXISTS idx_scheduled_messages_due
+    ON scheduled_messages(deliver_at) WHERE status = 'pending';
@@ -0,0 +1,7 @@
+-- Who can see a user's last-seen time and online status:
+-- 'everyone', 'contacts' or 'nobody'
+ALTER TABLE user_privacy_settings
+    ADD COLUMN IF NOT EXISTS last_seen_visibility TEXT NOT NULL DEFAULT 'everyone'
+        CHECK (last_seen_visibility IN ('everyone', 'contacts', 'nobody')),
+    ADD COLUMN IF NOT EXISTS online_visibility TEXT NOT NULL DEFAULT 'everyone'
+        CHECK (online_visibility IN ('everyone', 'contacts', 'nobody'));
@@ -0,0 +1,41 @@
+-- Raw presence changes as written by the presence service. Rows are kept for
+-- PRESENCE_HISTORY_RETENTION_DAYS once they have been rolled up.
+CREATE TABLE IF NOT EXISTS user_presence_history (
+    id BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL,
+    device_id TEXT NOT NULL,
+    status TEXT NOT NULL,
+    ip_address TEXT,
+    user_agent TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_presence_history_created_at
+    ON user_presence_history (created_at);
+
+-- Time a user was connected on any device, one row per continuous span,
+-- split at midnight UTC
+CREATE TABLE IF NOT EXISTS user_presence_sessions (
+    user_id UUID NOT NULL,
+    day DATE NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL,
+    ended_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, started_at)
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_presence_sessions_day
+    ON user_presence_sessions (day);
+
+-- Devices still connected at the end of the last rolled-up day
+CREATE TABLE IF NOT EXISTS presence_open_sessions (
+    user_id UUID NOT NULL,
+    device_id TEXT NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, device_id)
+);
+
+-- History before this instant has been rolled up
+CREATE TABLE IF NOT EXISTS presence_rollup_state (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    rolled_up_until TIMESTAMPTZ NOT NULL
+);
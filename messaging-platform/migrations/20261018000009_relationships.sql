@@ -0,0 +1,32 @@
+-- Friendships are stored once for each side; contacts and blocks only for
+-- the user who made them (user_id blocked related_user_id)
+CREATE TABLE IF NOT EXISTS user_relationships (
+    user_id UUID NOT NULL,
+    related_user_id UUID NOT NULL,
+    relationship_type TEXT NOT NULL
+        CHECK (relationship_type IN ('friend', 'contact', 'blocked')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, related_user_id, relationship_type),
+    CHECK (user_id <> related_user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_relationships_related
+    ON user_relationships(related_user_id, relationship_type);
+
+CREATE TABLE IF NOT EXISTS friend_requests (
+    id UUID PRIMARY KEY,
+    sender_id UUID NOT NULL,
+    recipient_id UUID NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'accepted', 'declined', 'cancelled')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    responded_at TIMESTAMPTZ,
+    CHECK (sender_id <> recipient_id)
+);
+
+-- At most one open request from each user to another
+CREATE UNIQUE INDEX IF NOT EXISTS idx_friend_requests_pending
+    ON friend_requests(sender_id, recipient_id) WHERE status = 'pending';
+
+CREATE INDEX IF NOT EXISTS idx_friend_requests_recipient
+    ON friend_requests(recipient_id) WHERE status = 'pending';
@@ -0,0 +1,15 @@
+-- Bans and mutes. A restriction is active until expires_at (forever when
+-- NULL); lifting one early moves expires_at to the time it was lifted.
+-- created_by is the moderator's id, or 'system' for automated actions
+CREATE TABLE IF NOT EXISTS user_restrictions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL,
+    restriction_type TEXT NOT NULL CHECK (restriction_type IN ('ban', 'mute')),
+    reason TEXT,
+    expires_at TIMESTAMPTZ,
+    created_by TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_restrictions_user
+    ON user_restrictions(user_id, restriction_type);
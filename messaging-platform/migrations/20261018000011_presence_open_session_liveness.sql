@@ -0,0 +1,8 @@
+-- When a device carried over from the last rolled-up day was last heard
+-- from, so the rollup can close sessions that stopped heartbeating without
+-- ever going offline
+ALTER TABLE presence_open_sessions ADD COLUMN IF NOT EXISTS last_seen_at TIMESTAMPTZ;
+
+UPDATE presence_open_sessions SET last_seen_at = started_at WHERE last_seen_at IS NULL;
+
+ALTER TABLE presence_open_sessions ALTER COLUMN last_seen_at SET NOT NULL;
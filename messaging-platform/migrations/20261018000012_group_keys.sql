@@ -0,0 +1,19 @@
+-- Members' X25519 public keys, which group keys are wrapped to
+ALTER TABLE users ADD COLUMN IF NOT EXISTS dh_public_key TEXT;
+
+-- Each rotation of an encrypted group's key, wrapped once per member. A
+-- member unwraps it with the shared secret between their X25519 key and the
+-- ephemeral key stored alongside it.
+CREATE TABLE IF NOT EXISTS group_keys (
+    group_id UUID NOT NULL,
+    key_version BIGINT NOT NULL,
+    user_id UUID NOT NULL,
+    ephemeral_public_key BYTEA NOT NULL,
+    wrapped_key BYTEA NOT NULL,
+    nonce BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (group_id, key_version, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_group_keys_member
+    ON group_keys(user_id, group_id, key_version DESC);
@@ -0,0 +1,5 @@
+-- Mentions travel in cleartext next to the encrypted content, so scheduled
+-- messages keep them until they go out
+ALTER TABLE scheduled_messages
+    ADD COLUMN IF NOT EXISTS mention_user_ids UUID[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS mention_everyone BOOLEAN NOT NULL DEFAULT false;
@@ -13,7 +13,11 @@ use tracing::{error, info, warn};
 use uuid::Uuid;
 use validator::Validate;
 
-use shared::models::{Report, ReportReason, ReportStatus, ReportTargetType};
+use shared::models::{
+    ConversationType, ModerationAction, ModerationTarget, Report, ReportReason, ReportStatus,
+    ReportTargetType, RestrictionType,
+};
+use shared::types::{MembershipChange, MembershipEvent, ModerationActionEvent};
 use shared::errors::AppError;
 
 struct AppState {
@@ -71,20 +75,12 @@ pub struct UpdateReportRequest {
 #[derive(Debug, Deserialize)]
 pub struct ModerationActionRequest {
     pub action: ModerationAction,
+    pub target: ModerationTarget,
+    // Bans and mutes without one are permanent
     pub duration_seconds: Option<i64>,
     pub reason: String,
 }
 
-#[derive(Debug, Deserialize)]
-pub enum ModerationAction {
-    BanUser,
-    UnbanUser,
-    MuteUser,
-    DeleteMessage,
-    DeleteGroup,
-    RemoveMember,
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt::init();
@@ -112,7 +108,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .create()?;
     
     // Run migrations
-    sqlx::migrate!("./migrations").run(&db_pool).await?;
+    shared::migrations::MIGRATOR.run(&db_pool).await?;
     
     let state = Arc::new(AppState {
         db_pool,
@@ -153,7 +149,7 @@ async fn create_report(
     headers: HeaderMap,
     Json(payload): Json<CreateReportRequest>,
 ) -> Result<Json<ReportResponse>, AppError> {
-    // Extract user ID from JWT (simplified)
+    // Extract user ID from JWT
     let user_id = extract_user_id_from_headers(&headers)?;
     
     payload.validate()?;
@@ -367,45 +363,69 @@ async fn take_moderation_action(
     // Verify user is a moderator/admin
     verify_moderator_role(&state.db_pool, &moderator_id).await?;
     
-    match payload.action {
-        ModerationAction::BanUser => {
-            // Extract target user ID from request (would need additional field)
-            // ban_user(&state, target_user_id, payload.duration_seconds, &payload.reason).await?;
+    let expires_at = match payload.duration_seconds {
+        Some(seconds) if seconds <= 0 => {
+            return Err(AppError::ValidationError("Duration must be positive".to_string()));
+        }
+        Some(seconds) => Some(Utc::now() + chrono::Duration::seconds(seconds)),
+        None => None,
+    };
+    
+    match (payload.action, &payload.target) {
+        (ModerationAction::BanUser, ModerationTarget::User { user_id }) => {
+            restrict_user(
+                &state.db_pool,
+                *user_id,
+                RestrictionType::Ban,
+                expires_at,
+                &payload.reason,
+                moderator_id,
+            ).await?;
+        }
+        (ModerationAction::MuteUser, ModerationTarget::User { user_id }) => {
+            restrict_user(
+                &state.db_pool,
+                *user_id,
+                RestrictionType::Mute,
+                expires_at,
+                &payload.reason,
+                moderator_id,
+            ).await?;
         }
-        ModerationAction::UnbanUser => {
-            // unban_user(&state, target_user_id).await?;
+        (ModerationAction::UnbanUser, ModerationTarget::User { user_id }) => {
+            lift_restriction(&state.db_pool, *user_id, RestrictionType::Ban).await?;
         }
-        ModerationAction::MuteUser => {
-            // mute_user(&state, target_user_id, payload.duration_seconds).await?;
+        (ModerationAction::UnmuteUser, ModerationTarget::User { user_id }) => {
+            lift_restriction(&state.db_pool, *user_id, RestrictionType::Mute).await?;
         }
-        ModerationAction::DeleteMessage => {
-            // delete_message(&state, message_id).await?;
+        (ModerationAction::DeleteMessage, ModerationTarget::Message { .. }) => {
+            // Messages live in Scylla, which only the message processor
+            // writes; it deletes the message when it sees the event below
         }
-        ModerationAction::DeleteGroup => {
-            // delete_group(&state, group_id).await?;
+        (ModerationAction::DeleteGroup, ModerationTarget::Group { group_id }) => {
+            delete_group(&state, moderator_id, *group_id).await?;
         }
-        ModerationAction::RemoveMember => {
-            // remove_member(&state, group_id, user_id).await?;
+        (ModerationAction::RemoveMember, ModerationTarget::Member { group_id, user_id }) => {
+            remove_member(&state, moderator_id, *group_id, *user_id).await?;
+        }
+        (action, _) => {
+            return Err(AppError::ValidationError(format!(
+                "{:?} cannot be taken against this target",
+                action
+            )));
         }
     }
     
-    // Publish moderation action event
-    let action_event = ModerationActionEvent {
+    publish_action_event(&state.kafka_producer, ModerationActionEvent {
         moderator_id,
-        action: format!("{:?}", payload.action),
+        action: payload.action,
+        target: payload.target,
         reason: payload.reason,
+        expires_at: expires_at.map(|at| at.timestamp()),
         timestamp: Utc::now().timestamp(),
-    };
-    
-    let payload = serde_json::to_vec(&action_event)
-        .map_err(|e| AppError::SerializationError(e.to_string()))?;
+    }).await?;
     
-    let record = rdkafka::producer::FutureRecord::to("moderation-actions")
-        .key(&moderator_id.to_string())
-        .payload(&payload);
-    
-    state.kafka_producer.send(record, Duration::from_secs(5)).await
-        .map_err(|e| AppError::ExternalServiceError(e.to_string()))?;
+    info!("Moderator {} took action {:?}", moderator_id, payload.action);
     
     Ok(StatusCode::OK)
 }
@@ -540,6 +560,193 @@ async fn verify_moderator_role(db_pool: &PgPool, user_id: &Uuid) -> Result<(), A
     Ok(())
 }
 
+async fn restrict_user(
+    db_pool: &PgPool,
+    user_id: Uuid,
+    restriction_type: RestrictionType,
+    expires_at: Option<DateTime<Utc>>,
+    reason: &str,
+    moderator_id: Uuid,
+) -> Result<(), AppError> {
+    let exists = sqlx::query!("SELECT 1 FROM users WHERE id = $1", user_id)
+        .fetch_optional(db_pool)
+        .await?;
+    
+    if exists.is_none() {
+        return Err(AppError::NotFound("User not found".to_string()));
+    }
+    
+    sqlx::query!(
+        r#"
+        INSERT INTO user_restrictions
+        (user_id, restriction_type, reason, expires_at, created_by)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        user_id,
+        restriction_type.as_str(),
+        reason,
+        expires_at,
+        moderator_id.to_string()
+    )
+    .execute(db_pool)
+    .await?;
+    
+    Ok(())
+}
+
+/// Ends every active restriction of the given type. Rows are kept, with
+/// `expires_at` moved to now, so the user's history stays reviewable.
+async fn lift_restriction(
+    db_pool: &PgPool,
+    user_id: Uuid,
+    restriction_type: RestrictionType,
+) -> Result<(), AppError> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_restrictions SET expires_at = NOW()
+        WHERE user_id = $1 AND restriction_type = $2
+        AND (expires_at IS NULL OR expires_at > NOW())
+        "#,
+        user_id,
+        restriction_type.as_str()
+    )
+    .execute(db_pool)
+    .await?;
+    
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound("User has no active restriction of that type".to_string()));
+    }
+    
+    Ok(())
+}
+
+async fn delete_group(state: &AppState, moderator_id: Uuid, group_id: Uuid) -> Result<(), AppError> {
+    let mut tx = state.db_pool.begin().await?;
+    
+    // Locking the row keeps anyone from joining between reading the members
+    // and deleting them
+    let conversation = sqlx::query!(
+        r#"
+        SELECT conversation_type, is_encrypted FROM conversations
+        WHERE id = $1 AND conversation_type <> 'direct_message'
+        FOR UPDATE
+        "#,
+        group_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound("Group not found".to_string()))?;
+    
+    let conversation_type: ConversationType = conversation
+        .conversation_type
+        .parse()
+        .map_err(AppError::DatabaseError)?;
+    
+    let rows = sqlx::query!(
+        "SELECT user_id FROM group_members WHERE group_id = $1",
+        group_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    
+    // Members, invites and settings go with it through ON DELETE CASCADE
+    sqlx::query!("DELETE FROM conversations WHERE id = $1", group_id)
+        .execute(&mut *tx)
+        .await?;
+    
+    tx.commit().await?;
+    
+    // Everyone who had the conversation, subscribers and banned members
+    // included, has something to clean up
+    publish_membership_event(&state.kafka_producer, &MembershipEvent {
+        conversation_id: group_id,
+        conversation_type,
+        is_encrypted: conversation.is_encrypted,
+        actor_id: moderator_id,
+        change: MembershipChange::Deleted,
+        recipients: rows.into_iter().map(|r| r.user_id).collect(),
+        timestamp: Utc::now().timestamp(),
+    }).await
+}
+
+async fn remove_member(
+    state: &AppState,
+    moderator_id: Uuid,
+    group_id: Uuid,
+    user_id: Uuid,
+) -> Result<(), AppError> {
+    let conversation = sqlx::query!(
+        "SELECT conversation_type, is_encrypted FROM conversations WHERE id = $1",
+        group_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await?
+    .ok_or(AppError::NotFound("Group not found".to_string()))?;
+    
+    let conversation_type: ConversationType = conversation
+        .conversation_type
+        .parse()
+        .map_err(AppError::DatabaseError)?;
+    
+    let result = sqlx::query!(
+        "DELETE FROM group_members WHERE group_id = $1 AND user_id = $2",
+        group_id,
+        user_id
+    )
+    .execute(&state.db_pool)
+    .await?;
+    
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound("Member not found".to_string()));
+    }
+    
+    // Same audience the conversations service uses: channel subscribers
+    // never hear about each other
+    let rows = sqlx::query!(
+        r#"
+        SELECT user_id FROM group_members
+        WHERE group_id = $1 AND is_banned = false
+        AND ($2 = false OR role IN ('owner', 'admin'))
+        "#,
+        group_id,
+        conversation_type == ConversationType::Channel
+    )
+    .fetch_all(&state.db_pool)
+    .await?;
+    
+    let mut recipients: Vec<Uuid> = rows.into_iter().map(|r| r.user_id).collect();
+    recipients.push(user_id);
+    
+    // Published like any other removal so the timeline records it and
+    // encrypted groups rotate their key
+    publish_membership_event(&state.kafka_producer, &MembershipEvent {
+        conversation_id: group_id,
+        conversation_type,
+        is_encrypted: conversation.is_encrypted,
+        actor_id: moderator_id,
+        change: MembershipChange::MemberRemoved { user_id },
+        recipients,
+        timestamp: Utc::now().timestamp(),
+    }).await
+}
+
+async fn publish_membership_event(
+    producer: &rdkafka::producer::FutureProducer,
+    event: &MembershipEvent,
+) -> Result<(), AppError> {
+    let payload = serde_json::to_vec(event)
+        .map_err(|e| AppError::SerializationError(e.to_string()))?;
+    
+    let record = rdkafka::producer::FutureRecord::to("membership-events")
+        .key(&event.conversation_id.to_string())
+        .payload(&payload);
+    
+    producer.send(record, Duration::from_secs(5)).await
+        .map_err(|(e, _)| AppError::ExternalServiceError(e.to_string()))?;
+    
+    Ok(())
+}
+
 async fn check_automated_actions(
     state: &AppState,
     report: &Report,
@@ -574,6 +781,15 @@ async fn check_automated_actions(
             .execute(&state.db_pool)
             .await?;
             
+            publish_action_event(&state.kafka_producer, ModerationActionEvent {
+                moderator_id: Uuid::nil(),
+                action: ModerationAction::BanUser,
+                target: ModerationTarget::User { user_id: report.target_id },
+                reason: "Auto-banned for spam".to_string(),
+                expires_at: Some((Utc::now() + chrono::Duration::hours(24)).timestamp()),
+                timestamp: Utc::now().timestamp(),
+            }).await?;
+            
             // Update report with auto-action
             sqlx::query!(
                 r#"
@@ -622,17 +838,52 @@ async fn publish_moderation_event(
     Ok(())
 }
 
+/// Tells the services that enforce moderation: the gateway drops banned
+/// users' connections and the message processor deletes messages.
+async fn publish_action_event(
+    producer: &rdkafka::producer::FutureProducer,
+    event: ModerationActionEvent,
+) -> Result<(), AppError> {
+    let payload = serde_json::to_vec(&event)
+        .map_err(|e| AppError::SerializationError(e.to_string()))?;
+    
+    let record = rdkafka::producer::FutureRecord::to("moderation-actions")
+        .key(&event.moderator_id.to_string())
+        .payload(&payload);
+    
+    producer.send(record, Duration::from_secs(5)).await
+        .map_err(|(e, _)| AppError::ExternalServiceError(e.to_string()))?;
+    
+    Ok(())
+}
+
 fn extract_user_id_from_headers(headers: &HeaderMap) -> Result<Uuid, AppError> {
-    let auth_header = headers
+    use jsonwebtoken::{decode, DecodingKey, Validation};
+    
+    let token = headers
         .get("Authorization")
         .and_then(|h| h.to_str().ok())
         .and_then(|s| s.strip_prefix("Bearer "))
         .ok_or(AppError::Unauthorized("Missing token".to_string()))?;
     
-    // Decode JWT and extract user ID
-    // Simplified - in production, use proper JWT validation
-    Uuid::parse_str(auth_header)
-        .map_err(|_| AppError::Unauthorized("Invalid token".to_string()))
+    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    let token_data = decode::<TokenClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::default(),
+    )
+    .map_err(|_| AppError::Unauthorized("Invalid token".to_string()))?;
+    
+    Ok(token_data.claims.sub)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenClaims {
+    sub: Uuid,
+    exp: usize,
+    iat: usize,
+    device_id: String,
+    session_id: Uuid,
 }
 
 #[derive(Debug, Serialize)]
@@ -666,11 +917,3 @@ struct ModerationEvent {
     user_id: Uuid,
     timestamp: i64,
 }
-
-#[derive(Debug, Serialize)]
-struct ModerationActionEvent {
-    moderator_id: Uuid,
-    action: String,
-    reason: String,
-    timestamp: i64,
-}
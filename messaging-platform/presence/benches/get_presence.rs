@@ -0,0 +1,101 @@
+//! Compares the per-user GetPresence lookup with the batched store against a
+//! local Redis. Set `REDIS_URL` to point elsewhere; the benchmark writes
+//! throwaway presence keys for random users.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use redis::AsyncCommands;
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+use presence_service::store::{self, PresenceState, PresenceStore, PRESENCE_TTL_SECS};
+use shared::models::PresenceStatus;
+
+const USER_COUNTS: [usize; 3] = [100, 1000, 5000];
+const DEVICES_PER_USER: usize = 2;
+
+fn redis_url() -> String {
+    std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string())
+}
+
+async fn seed(store: &PresenceStore, users: usize) -> Vec<Uuid> {
+    let now = Utc::now();
+    let user_ids: Vec<Uuid> = (0..users).map(|_| Uuid::new_v4()).collect();
+    
+    for &user_id in &user_ids {
+        for device in 0..DEVICES_PER_USER {
+            let state = PresenceState {
+                user_id,
+                status: PresenceStatus::Online,
+                device_id: format!("bench-device-{}", device),
+                ip_address: None,
+                user_agent: None,
+                last_active: now,
+                last_activity: now,
+                expires_at: now + chrono::Duration::seconds(PRESENCE_TTL_SECS as i64),
+            };
+            store.store_device(&state).await.expect("Failed to seed presence");
+        }
+    }
+    
+    user_ids
+}
+
+/// The lookup GetPresence did before batching: a fresh connection per
+/// request, then SMEMBERS and MGET for each user in turn.
+async fn load_sequentially(client: &redis::Client, user_ids: &[Uuid]) -> usize {
+    let mut conn = client.get_async_connection().await.unwrap();
+    let mut found = 0;
+    
+    for &user_id in user_ids {
+        let device_keys: Vec<String> = conn.smembers(store::user_key(user_id)).await.unwrap();
+        if device_keys.is_empty() {
+            continue;
+        }
+        
+        let device_data: Vec<Option<String>> = conn.mget(&device_keys).await.unwrap();
+        found += device_data
+            .into_iter()
+            .flatten()
+            .filter_map(|data| serde_json::from_str::<PresenceState>(&data).ok())
+            .count();
+    }
+    
+    found
+}
+
+fn get_presence(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let client = redis::Client::open(redis_url()).unwrap();
+    let uncached = runtime
+        .block_on(PresenceStore::connect(&redis_url(), Duration::ZERO))
+        .expect("Redis must be running for this benchmark");
+    let cached = runtime
+        .block_on(PresenceStore::connect(&redis_url(), store::DEFAULT_CACHE_TTL))
+        .unwrap();
+    
+    let mut group = c.benchmark_group("get_presence");
+    
+    for users in USER_COUNTS {
+        let user_ids = runtime.block_on(seed(&uncached, users));
+        
+        group.bench_with_input(BenchmarkId::new("sequential", users), &user_ids, |b, user_ids| {
+            b.to_async(&runtime).iter(|| load_sequentially(&client, user_ids));
+        });
+        
+        group.bench_with_input(BenchmarkId::new("pipelined", users), &user_ids, |b, user_ids| {
+            b.to_async(&runtime).iter(|| async { uncached.load_many(user_ids).await.unwrap() });
+        });
+        
+        group.bench_with_input(BenchmarkId::new("pipelined_cached", users), &user_ids, |b, user_ids| {
+            b.to_async(&runtime).iter(|| async { cached.load_many(user_ids).await.unwrap() });
+        });
+    }
+    
+    group.finish();
+}
+
+criterion_group!(benches, get_presence);
+criterion_main!(benches);
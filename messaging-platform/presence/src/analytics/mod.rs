@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use sqlx::PgPool;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use presence_service::store::PRESENCE_TTL_SECS;
+
+const ROLLUP_INTERVAL_SECS: u64 = 3600;
+const DELETE_BATCH_SIZE: i64 = 10_000;
+// Advisory lock held while rolling up, so only one replica does it at a time
+const ROLLUP_LOCK_ID: i64 = 0x7072_6573_656e_6365;
+pub const MAU_WINDOW_DAYS: i64 = 30;
+
+/// Compacts `user_presence_history` into `user_presence_sessions` one UTC day
+/// at a time, then deletes raw rows past the retention period. Raw rows are
+/// never deleted before they have been rolled up.
+///
+/// A session ends when its device goes offline, or at the last sign of life
+/// from a device that went quiet for longer than Redis keeps its presence:
+/// one that crashed or lost its connection never sends an offline update.
+pub struct RollupJob {
+    pg_pool: PgPool,
+    retention_days: i64,
+}
+
+pub struct DailyActiveTime {
+    pub day: NaiveDate,
+    pub active_seconds: i64,
+    pub sessions: i64,
+}
+
+pub struct ActiveUsers {
+    pub daily: i64,
+    pub monthly: i64,
+}
+
+impl RollupJob {
+    pub fn new(pg_pool: PgPool, retention_days: i64) -> Self {
+        Self { pg_pool, retention_days }
+    }
+    
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(ROLLUP_INTERVAL_SECS));
+        
+        info!("Presence rollup started, keeping raw history for {} days", self.retention_days);
+        
+        loop {
+            interval.tick().await;
+            
+            if let Err(e) = self.roll_up_completed_days().await {
+                error!("Failed to roll up presence history: {}", e);
+                continue;
+            }
+            
+            if let Err(e) = self.apply_retention().await {
+                error!("Failed to apply presence history retention: {}", e);
+            }
+        }
+    }
+    
+    async fn roll_up_completed_days(&self) -> Result<(), sqlx::Error> {
+        let today = Utc::now().date_naive();
+        
+        while let Some(day) = self.roll_up_next_day(today).await? {
+            info!("Rolled up presence history for {}", day);
+        }
+        
+        Ok(())
+    }
+    
+    /// Rolls up the first day not yet rolled up if it is before `today`, and
+    /// returns it. None when there is nothing to do or another replica is busy.
+    async fn roll_up_next_day(&self, today: NaiveDate) -> Result<Option<NaiveDate>, sqlx::Error> {
+        let mut tx = self.pg_pool.begin().await?;
+        
+        let locked = sqlx::query_scalar!(
+            r#"SELECT pg_try_advisory_xact_lock($1) AS "locked!""#,
+            ROLLUP_LOCK_ID
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        
+        if !locked {
+            return Ok(None);
+        }
+        
+        let state = sqlx::query!("SELECT rolled_up_until FROM presence_rollup_state")
+            .fetch_optional(&mut *tx)
+            .await?;
+        
+        let next = match state {
+            Some(state) => Some(state.rolled_up_until.date_naive()),
+            None => sqlx::query!("SELECT MIN(created_at) AS first FROM user_presence_history")
+                .fetch_one(&mut *tx)
+                .await?
+                .first
+                .map(|first| first.date_naive()),
+        };
+        
+        let day = match next {
+            Some(day) if day < today => day,
+            _ => return Ok(None),
+        };
+        
+        let day_start = day_start(day);
+        let day_end = day_start + Duration::days(1);
+        
+        // Devices connected before the day began, with when each was last heard from
+        let mut open: HashMap<(Uuid, String), (DateTime<Utc>, DateTime<Utc>)> = sqlx::query!(
+            "SELECT user_id, device_id, started_at, last_seen_at FROM presence_open_sessions"
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| ((row.user_id, row.device_id), (row.started_at, row.last_seen_at)))
+        .collect();
+        
+        let events = sqlx::query!(
+            r#"
+            SELECT user_id, device_id, status, created_at
+            FROM user_presence_history
+            WHERE created_at >= $1 AND created_at < $2
+            ORDER BY created_at
+            "#,
+            day_start,
+            day_end
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        
+        let stale_after = Duration::seconds(PRESENCE_TTL_SECS as i64);
+        let mut spans: HashMap<Uuid, Vec<(DateTime<Utc>, DateTime<Utc>)>> = HashMap::new();
+        
+        for event in events {
+            let key = (event.user_id, event.device_id);
+            
+            // The device went away unannounced before this event
+            if let Some(&(started_at, last_seen_at)) = open.get(&key) {
+                if event.created_at - last_seen_at > stale_after {
+                    open.remove(&key);
+                    spans.entry(key.0).or_default().push((started_at, last_seen_at.max(started_at)));
+                }
+            }
+            
+            if event.status == "offline" {
+                if let Some((started_at, _)) = open.remove(&key) {
+                    spans.entry(key.0).or_default().push((started_at, event.created_at));
+                }
+            } else {
+                open.entry(key)
+                    .and_modify(|(_, last_seen_at)| *last_seen_at = event.created_at)
+                    .or_insert((event.created_at, event.created_at));
+            }
+        }
+        
+        // Sessions still alive at midnight run to it and carry over; the rest
+        // end when their device was last heard from
+        open.retain(|(user_id, _), (started_at, last_seen_at)| {
+            let alive = day_end - *last_seen_at <= stale_after;
+            let ended_at = if alive { day_end } else { (*last_seen_at).max(*started_at) };
+            spans.entry(*user_id).or_default().push((*started_at, ended_at));
+            alive
+        });
+        
+        for (user_id, user_spans) in spans {
+            for (started_at, ended_at) in merge_spans(user_spans) {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO user_presence_sessions (user_id, day, started_at, ended_at)
+                    VALUES ($1, $2, $3, $4)
+                    ON CONFLICT (user_id, started_at) DO UPDATE SET ended_at = EXCLUDED.ended_at
+                    "#,
+                    user_id,
+                    day,
+                    started_at,
+                    ended_at
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        
+        sqlx::query!("DELETE FROM presence_open_sessions")
+            .execute(&mut *tx)
+            .await?;
+        
+        for ((user_id, device_id), (_, last_seen_at)) in open {
+            sqlx::query!(
+                r#"
+                INSERT INTO presence_open_sessions (user_id, device_id, started_at, last_seen_at)
+                VALUES ($1, $2, $3, $4)
+                "#,
+                user_id,
+                device_id,
+                day_end,
+                last_seen_at
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        
+        sqlx::query!(
+            r#"
+            INSERT INTO presence_rollup_state (id, rolled_up_until)
+            VALUES (TRUE, $1)
+            ON CONFLICT (id) DO UPDATE SET rolled_up_until = EXCLUDED.rolled_up_until
+            "#,
+            day_end
+        )
+        .execute(&mut *tx)
+        .await?;
+        
+        tx.commit().await?;
+        
+        Ok(Some(day))
+    }
+    
+    async fn apply_retention(&self) -> Result<(), sqlx::Error> {
+        let state = sqlx::query!("SELECT rolled_up_until FROM presence_rollup_state")
+            .fetch_optional(&self.pg_pool)
+            .await?;
+        
+        let rolled_up_until = match state {
+            Some(state) => state.rolled_up_until,
+            None => return Ok(()),
+        };
+        
+        let cutoff = (Utc::now() - Duration::days(self.retention_days)).min(rolled_up_until);
+        let mut deleted = 0;
+        
+        // Small batches keep locks short on a large table
+        loop {
+            let result = sqlx::query!(
+                r#"
+                DELETE FROM user_presence_history
+                WHERE id IN (
+                    SELECT id FROM user_presence_history
+                    WHERE created_at < $1
+                    LIMIT $2
+                )
+                "#,
+                cutoff,
+                DELETE_BATCH_SIZE
+            )
+            .execute(&self.pg_pool)
+            .await?;
+            
+            deleted += result.rows_affected();
+            
+            if result.rows_affected() < DELETE_BATCH_SIZE as u64 {
+                break;
+            }
+        }
+        
+        if deleted > 0 {
+            info!("Deleted {} presence history rows before {}", deleted, cutoff);
+        }
+        
+        Ok(())
+    }
+}
+
+/// Time the user was connected on each day in `from..=to`, including days
+/// without any activity.
+pub async fn active_time(
+    pg_pool: &PgPool,
+    user_id: Uuid,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<DailyActiveTime>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT day,
+               SUM(EXTRACT(EPOCH FROM ended_at - started_at))::BIGINT AS "active_seconds!",
+               COUNT(*) AS "sessions!"
+        FROM user_presence_sessions
+        WHERE user_id = $1 AND day BETWEEN $2 AND $3
+        GROUP BY day
+        "#,
+        user_id,
+        from,
+        to
+    )
+    .fetch_all(pg_pool)
+    .await?;
+    
+    let mut by_day: HashMap<NaiveDate, (i64, i64)> = rows
+        .into_iter()
+        .map(|row| (row.day, (row.active_seconds, row.sessions)))
+        .collect();
+    
+    Ok(from
+        .iter_days()
+        .take_while(|day| *day <= to)
+        .map(|day| {
+            let (active_seconds, sessions) = by_day.remove(&day).unwrap_or((0, 0));
+            DailyActiveTime { day, active_seconds, sessions }
+        })
+        .collect())
+}
+
+/// Users active on `day`, and in the `MAU_WINDOW_DAYS` days ending on it.
+pub async fn active_users(pg_pool: &PgPool, day: NaiveDate) -> Result<ActiveUsers, sqlx::Error> {
+    let window_start = day - Duration::days(MAU_WINDOW_DAYS - 1);
+    
+    let counts = sqlx::query!(
+        r#"
+        SELECT COUNT(DISTINCT user_id) FILTER (WHERE day = $1) AS "daily!",
+               COUNT(DISTINCT user_id) AS "monthly!"
+        FROM user_presence_sessions
+        WHERE day BETWEEN $2 AND $1
+        "#,
+        day,
+        window_start
+    )
+    .fetch_one(pg_pool)
+    .await?;
+    
+    Ok(ActiveUsers {
+        daily: counts.daily,
+        monthly: counts.monthly,
+    })
+}
+
+fn day_start(day: NaiveDate) -> DateTime<Utc> {
+    day.and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+/// Overlapping spans from several devices count once.
+fn merge_spans(mut spans: Vec<(DateTime<Utc>, DateTime<Utc>)>) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    spans.sort_by_key(|(started_at, _)| *started_at);
+    
+    let mut merged: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::with_capacity(spans.len());
+    
+    for (started_at, ended_at) in spans {
+        match merged.last_mut() {
+            Some((_, last_end)) if started_at <= *last_end => {
+                *last_end = (*last_end).max(ended_at);
+            }
+            _ => merged.push((started_at, ended_at)),
+        }
+    }
+    
+    merged
+}
@@ -0,0 +1,3 @@
+//! Presence storage, shared by the service binary and its benchmarks.
+
+pub mod store;
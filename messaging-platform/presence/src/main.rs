@@ -4,41 +4,167 @@ use std::time::{Duration, Instant};
 
 use chrono::{DateTime, Utc};
 use dashmap::DashMap;
-use redis::{AsyncCommands, RedisResult};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{transport::Server, Request, Response, Status};
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
+use shared::models::{CustomStatus, PresenceActivity, PresenceStatus};
+use shared::types::PresenceEvent;
+
+use presence_service::store::{PresenceState, PresenceStore, PublishedStatus, DEFAULT_CACHE_TTL, PRESENCE_TTL_SECS};
+
+mod analytics;
+mod privacy;
+mod subscriptions;
+
+use privacy::Visibility;
+use subscriptions::SubscriptionHub;
+
 mod presence_proto {
     tonic::include_proto!("presence");
 }
 
 use presence_proto::{
     presence_server::{Presence, PresenceServer},
+    CustomStatus as ProtoCustomStatus,
+    PresenceActivity as ProtoActivity,
+    PresenceStatus as ProtoStatus,
     *,
 };
 
-#[derive(Debug, Clone)]
-struct PresenceState {
-    user_id: Uuid,
-    status: String,
-    device_id: String,
-    ip_address: Option<String>,
-    user_agent: Option<String>,
-    custom_status: Option<String>,
-    last_active: DateTime<Utc>,
-    expires_at: DateTime<Utc>,
+// How often idle drift and expired custom statuses are looked for
+const SWEEP_INTERVAL_SECS: u64 = 10;
+const MAX_INTEREST_SET: usize = 1000;
+// How long a subscription waits after a change so bursts go out as one batch
+const BATCH_WINDOW_MS: u64 = 500;
+const SUBSCRIPTION_BUFFER: usize = 16;
+const DEFAULT_HISTORY_RETENTION_DAYS: i64 = 30;
+const MAX_ANALYTICS_RANGE_DAYS: i64 = 366;
+// Heartbeats are written to history at most this often per device. The
+// analytics rollup treats a device silent for longer than PRESENCE_TTL_SECS
+// as gone, so this has to stay well inside it.
+const HEARTBEAT_RECORD_INTERVAL_SECS: i64 = PRESENCE_TTL_SECS as i64 / 2;
+
+/// The user's overall status: the highest-priority status across live
+/// devices, with the most recently active device breaking ties.
+fn aggregate(devices: &[PresenceState], now: DateTime<Utc>) -> Option<(PresenceStatus, &PresenceState)> {
+    devices
+        .iter()
+        .filter(|device| device.expires_at > now)
+        .map(|device| (device.effective_status(now), device))
+        .max_by(|(a, device_a), (b, device_b)| {
+            a.priority()
+                .cmp(&b.priority())
+                .then(device_a.last_activity.cmp(&device_b.last_activity))
+        })
+}
+
+impl From<PresenceStatus> for ProtoStatus {
+    fn from(status: PresenceStatus) -> Self {
+        match status {
+            PresenceStatus::Online => ProtoStatus::Online,
+            PresenceStatus::Idle => ProtoStatus::Idle,
+            PresenceStatus::Away => ProtoStatus::Away,
+            PresenceStatus::Dnd => ProtoStatus::Dnd,
+            PresenceStatus::Invisible => ProtoStatus::Invisible,
+            PresenceStatus::Offline => ProtoStatus::Offline,
+        }
+    }
+}
+
+fn parse_status(value: i32) -> Result<PresenceStatus, Status> {
+    match ProtoStatus::from_i32(value) {
+        Some(ProtoStatus::Online) => Ok(PresenceStatus::Online),
+        Some(ProtoStatus::Idle) => Ok(PresenceStatus::Idle),
+        Some(ProtoStatus::Away) => Ok(PresenceStatus::Away),
+        Some(ProtoStatus::Dnd) => Ok(PresenceStatus::Dnd),
+        Some(ProtoStatus::Invisible) => Ok(PresenceStatus::Invisible),
+        Some(ProtoStatus::Offline) => Ok(PresenceStatus::Offline),
+        Some(ProtoStatus::Unspecified) | None => Err(Status::invalid_argument("Invalid presence status")),
+    }
+}
+
+impl From<PresenceActivity> for ProtoActivity {
+    fn from(activity: PresenceActivity) -> Self {
+        match activity {
+            PresenceActivity::InACall => ProtoActivity::InACall,
+            PresenceActivity::InAMeeting => ProtoActivity::InAMeeting,
+            PresenceActivity::Focusing => ProtoActivity::Focusing,
+            PresenceActivity::Commuting => ProtoActivity::Commuting,
+            PresenceActivity::OnVacation => ProtoActivity::OnVacation,
+        }
+    }
+}
+
+impl From<CustomStatus> for ProtoCustomStatus {
+    fn from(custom_status: CustomStatus) -> Self {
+        Self {
+            text: custom_status.text,
+            emoji: custom_status.emoji,
+            activity: custom_status.activity.map_or(ProtoActivity::Unspecified, ProtoActivity::from) as i32,
+            clear_after: custom_status.clear_after,
+        }
+    }
+}
+
+/// The custom status a device asked for; None when it asked for no status.
+/// What an update does to the user's custom status.
+enum CustomStatusUpdate {
+    Keep,
+    Clear,
+    Set(CustomStatus),
+}
+
+/// Requests without a custom status keep the current one, so reconnecting
+/// devices don't wipe it; an empty one clears it.
+fn parse_custom_status(
+    custom_status: Option<ProtoCustomStatus>,
+    now: DateTime<Utc>,
+) -> Result<CustomStatusUpdate, Status> {
+    let custom_status = match custom_status {
+        Some(custom_status) => custom_status,
+        None => return Ok(CustomStatusUpdate::Keep),
+    };
+    
+    let activity = match ProtoActivity::from_i32(custom_status.activity) {
+        Some(ProtoActivity::Unspecified) => None,
+        Some(ProtoActivity::InACall) => Some(PresenceActivity::InACall),
+        Some(ProtoActivity::InAMeeting) => Some(PresenceActivity::InAMeeting),
+        Some(ProtoActivity::Focusing) => Some(PresenceActivity::Focusing),
+        Some(ProtoActivity::Commuting) => Some(PresenceActivity::Commuting),
+        Some(ProtoActivity::OnVacation) => Some(PresenceActivity::OnVacation),
+        None => return Err(Status::invalid_argument("Invalid presence activity")),
+    };
+    
+    let custom_status = CustomStatus {
+        text: custom_status.text.filter(|text| !text.trim().is_empty()),
+        emoji: custom_status.emoji.filter(|emoji| !emoji.trim().is_empty()),
+        activity,
+        clear_after: custom_status.clear_after,
+    };
+    
+    if custom_status.is_empty() {
+        return Ok(CustomStatusUpdate::Clear);
+    }
+    
+    custom_status.validate(now.timestamp()).map_err(Status::invalid_argument)?;
+    
+    Ok(CustomStatusUpdate::Set(custom_status))
 }
 
 struct PresenceService {
-    redis_client: redis::Client,
+    store: PresenceStore,
     pg_pool: PgPool,
     kafka_producer: rdkafka::producer::FutureProducer,
-    user_subscriptions: Arc<DashMap<Uuid, broadcast::Sender<PresenceUpdate>>>,
-    active_users: Arc<RwLock<HashMap<Uuid, Vec<PresenceState>>>>,
+    subscriptions: Arc<SubscriptionHub>,
+    // Identifies this replica when it claims a sweep
+    replica_id: String,
+    // When each device's heartbeat was last written to history
+    recorded_heartbeats: Arc<DashMap<(Uuid, String), DateTime<Utc>>>,
 }
 
 #[tonic::async_trait]
@@ -51,40 +177,48 @@ impl Presence for PresenceService {
         
         let user_id = Uuid::parse_str(&req.user_id)
             .map_err(|_| Status::invalid_argument("Invalid user ID"))?;
-        
-        // Update Redis
-        let mut redis_conn = self.redis_client.get_async_connection().await
-            .map_err(|e| {
-                error!("Redis connection failed: {}", e);
-                Status::internal("Redis connection failed")
-            })?;
+        let status = parse_status(req.status)?;
         
         let now = Utc::now();
-        let expires_at = now + chrono::Duration::seconds(300); // 5 minute TTL
-        
-        let presence_data = serde_json::json!({
-            "user_id": req.user_id,
-            "status": req.status,
-            "device_id": req.device_id,
-            "ip_address": req.ip_address,
-            "user_agent": req.user_agent,
-            "custom_status": req.custom_status,
-            "last_active": now.to_rfc3339(),
-            "expires_at": expires_at.to_rfc3339(),
-        });
+        let expires_at = now + chrono::Duration::seconds(PRESENCE_TTL_SECS as i64);
+        let custom_status_update = parse_custom_status(req.custom_status, now)?;
+        
+        let custom_status_stored = match &custom_status_update {
+            CustomStatusUpdate::Keep => Ok(()),
+            CustomStatusUpdate::Clear => self.store.clear_custom_status(user_id).await,
+            CustomStatusUpdate::Set(custom_status) => self.store.store_custom_status(user_id, custom_status).await,
+        };
+        
+        custom_status_stored.map_err(|e| {
+            error!("Failed to store custom status in Redis: {}", e);
+            Status::internal("Failed to store presence")
+        })?;
+        
+        // Changing status is user activity
+        let state = PresenceState {
+            user_id,
+            status,
+            device_id: req.device_id.clone(),
+            ip_address: req.ip_address.clone(),
+            user_agent: req.user_agent.clone(),
+            last_active: now,
+            last_activity: now,
+            expires_at,
+        };
         
-        // Store in Redis with TTL
-        let redis_key = format!("presence:{}:{}", user_id, req.device_id);
-        let _: RedisResult<()> = redis_conn.set_ex(
-            &redis_key,
-            presence_data.to_string(),
-            300,
-        ).await;
+        let going_offline = status == PresenceStatus::Offline;
         
-        // Also store in set for user's all devices
-        let user_presence_key = format!("user_presence:{}", user_id);
-        let _: RedisResult<()> = redis_conn.sadd(&user_presence_key, &redis_key).await;
-        let _: RedisResult<()> = redis_conn.expire(&user_presence_key, 300).await;
+        let stored = if going_offline {
+            // A disconnected device drops out of the user's aggregate
+            self.store.remove_device(user_id, &req.device_id).await
+        } else {
+            self.store.store_device(&state).await
+        };
+        
+        stored.map_err(|e| {
+            error!("Failed to store presence in Redis: {}", e);
+            Status::internal("Failed to store presence")
+        })?;
         
         // Update PostgreSQL for historical tracking
         sqlx::query!(
@@ -95,7 +229,7 @@ impl Presence for PresenceService {
             "#,
             user_id,
             req.device_id,
-            req.status,
+            status.as_str(),
             req.ip_address,
             req.user_agent,
             now
@@ -107,57 +241,105 @@ impl Presence for PresenceService {
             Status::internal("Failed to store presence")
         })?;
         
-        // Update in-memory state
-        let mut active_users = self.active_users.write().await;
-        let user_states = active_users.entry(user_id).or_insert_with(Vec::new);
+        let devices = self.load_devices(user_id).await?;
+        let overall = aggregate(&devices, now);
         
-        // Update or add device state
-        if let Some(existing) = user_states.iter_mut().find(|s| s.device_id == req.device_id) {
-            existing.status = req.status.clone();
-            existing.last_active = now;
-            existing.expires_at = expires_at;
-            existing.custom_status = req.custom_status.clone();
-        } else {
-            user_states.push(PresenceState {
-                user_id,
-                status: req.status.clone(),
-                device_id: req.device_id.clone(),
-                ip_address: req.ip_address.clone(),
-                user_agent: req.user_agent.clone(),
-                custom_status: req.custom_status.clone(),
-                last_active: now,
-                expires_at,
-            });
+        // Only shown while the user has a device online
+        let custom_status = match overall {
+            Some(_) => self.load_custom_status(user_id).await?,
+            None => None,
+        };
+        
+        self.announce(
+            user_id,
+            &req.device_id,
+            overall.map(|(status, _)| status).unwrap_or(PresenceStatus::Offline),
+            custom_status,
+            now,
+        ).await?;
+        
+        info!("Updated presence for user {}: {}", user_id, status.as_str());
+        
+        Ok(Response::new(UpdatePresenceResponse { success: true }))
+    }
+    
+    async fn heartbeat(
+        &self,
+        request: Request<HeartbeatRequest>,
+    ) -> Result<Response<HeartbeatResponse>, Status> {
+        let req = request.into_inner();
+        
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user ID"))?;
+        
+        let now = Utc::now();
+        let expires_at = now + chrono::Duration::seconds(PRESENCE_TTL_SECS as i64);
+        
+        let mut devices = self.load_devices(user_id).await?;
+        let before = aggregate(&devices, now).map(|(status, _)| status);
+        
+        // A device whose entry lapsed is still connected; bring it back online
+        let index = match devices.iter().position(|device| device.device_id == req.device_id) {
+            Some(index) => index,
+            None => {
+                devices.push(PresenceState {
+                    user_id,
+                    status: PresenceStatus::Online,
+                    device_id: req.device_id.clone(),
+                    ip_address: None,
+                    user_agent: None,
+                    last_active: now,
+                    last_activity: now,
+                    expires_at,
+                });
+                devices.len() - 1
+            }
+        };
+        
+        let device = &mut devices[index];
+        device.last_active = now;
+        device.expires_at = expires_at;
+        if req.active {
+            device.last_activity = now;
         }
         
-        // Clean up stale entries
-        user_states.retain(|state| state.expires_at > now);
-        
-        // Broadcast presence update
-        if let Some(tx) = self.user_subscriptions.get(&user_id) {
-            let update = PresenceUpdate {
-                user_id: req.user_id.clone(),
-                status: req.status.clone(),
-                device_id: req.device_id.clone(),
-                last_active: now.timestamp(),
-                custom_status: req.custom_status.clone(),
+        self.store.store_device(device).await
+            .map_err(|e| {
+                error!("Failed to store presence in Redis: {}", e);
+                Status::internal("Failed to store presence")
+            })?;
+        let device_status = device.effective_status(now);
+        
+        self.record_heartbeat(&devices[index], device_status, now).await;
+        
+        let after = aggregate(&devices, now);
+        let after_status = after.map(|(status, _)| status).unwrap_or(PresenceStatus::Offline);
+        
+        if before != Some(after_status) {
+            let custom_status = match after {
+                Some(_) => self.load_custom_status(user_id).await?,
+                None => None,
             };
             
-            let _ = tx.send(update);
+            self.announce(
+                user_id,
+                &req.device_id,
+                after_status,
+                custom_status,
+                now,
+            ).await?;
         }
         
-        // Publish to Kafka for other services
-        self.publish_presence_update(&req, &now).await?;
-        
-        info!("Updated presence for user {}: {}", user_id, req.status);
-        
-        Ok(Response::new(UpdatePresenceResponse { success: true }))
+        Ok(Response::new(HeartbeatResponse {
+            status: ProtoStatus::from(device_status) as i32,
+        }))
     }
     
     async fn get_presence(
         &self,
         request: Request<GetPresenceRequest>,
     ) -> Result<Response<GetPresenceResponse>, Status> {
+        let viewer = authenticate(&request)?;
         let req = request.into_inner();
         
         let user_ids: Vec<Uuid> = req.user_ids
@@ -169,128 +351,107 @@ impl Presence for PresenceService {
             return Err(Status::invalid_argument("No valid user IDs provided"));
         }
         
-        let mut redis_conn = self.redis_client.get_async_connection().await
-            .map_err(|e| {
-                error!("Redis connection failed: {}", e);
-                Status::internal("Redis connection failed")
-            })?;
-        
-        let now = Utc::now();
-        let mut presence_map = HashMap::new();
-        
-        for user_id in user_ids {
-            let user_presence_key = format!("user_presence:{}", user_id);
-            
-            // Get all device keys for this user
-            let device_keys: Vec<String> = redis_conn.smembers(&user_presence_key).await
-                .map_err(|e| {
-                    error!("Failed to get device keys: {}", e);
-                    Status::internal("Failed to get presence")
-                })?;
-            
-            if !device_keys.is_empty() {
-                // Get all device presence data
-                let device_data: Vec<String> = redis_conn.mget(&device_keys).await
-                    .map_err(|e| {
-                        error!("Failed to get presence data: {}", e);
-                        Status::internal("Failed to get presence")
-                    })?;
-                
-                // Parse and find most recent/most relevant status
-                let mut user_status = "offline".to_string();
-                let mut last_active = now;
-                let mut custom_status = None;
-                
-                for data in device_data {
-                    if let Ok(presence) = serde_json::from_str::<RedisPresence>(&data) {
-                        if presence.expires_at > now {
-                            // User is online on this device
-                            if presence.status == "online" {
-                                user_status = "online".to_string();
-                                last_active = presence.last_active;
-                                custom_status = presence.custom_status;
-                                break;
-                            } else if user_status != "online" {
-                                // Check for other statuses
-                                user_status = presence.status.clone();
-                                if presence.last_active > last_active {
-                                    last_active = presence.last_active;
-                                    custom_status = presence.custom_status.clone();
-                                }
-                            }
-                        }
-                    }
-                }
-                
-                presence_map.insert(
-                    user_id.to_string(),
-                    UserPresence {
-                        status: user_status,
-                        last_active: last_active.timestamp(),
-                        custom_status,
-                        devices: device_keys.len() as u32,
-                    },
-                );
-            } else {
-                // No active presence found
-                presence_map.insert(
-                    user_id.to_string(),
-                    UserPresence {
-                        status: "offline".to_string(),
-                        last_active: 0,
-                        custom_status: None,
-                        devices: 0,
-                    },
-                );
-            }
-        }
-        
         let response = GetPresenceResponse {
-            presence: presence_map,
+            presence: self.presence_for(viewer, &user_ids).await?,
         };
         
         Ok(Response::new(response))
     }
     
+    type SubscribePresenceStream = ReceiverStream<Result<PresenceBatch, Status>>;
+    
     async fn subscribe_presence(
         &self,
         request: Request<SubscribePresenceRequest>,
-    ) -> tonic::Result<tonic::Response<tonic::Streaming<PresenceUpdate>>, Status> {
+    ) -> Result<Response<Self::SubscribePresenceStream>, Status> {
+        let viewer = authenticate(&request)?;
         let req = request.into_inner();
         
-        let user_id = Uuid::parse_str(&req.user_id)
-            .map_err(|_| Status::invalid_argument("Invalid user ID"))?;
+        let interest = self.resolve_interest(viewer, &req).await?;
+        let visibility = self.visibility_for(viewer, &interest).await?;
         
-        // Create broadcast channel if it doesn't exist
-        let tx = self.user_subscriptions
-            .entry(user_id)
-            .or_insert_with(|| broadcast::channel(100).0)
-            .clone();
+        // Users who don't share their online status with the caller are left out
+        let interest: Vec<Uuid> = interest
+            .into_iter()
+            .filter(|user_id| visibility.get(user_id).map_or(false, |v| v.online))
+            .collect();
         
-        let rx = tx.subscribe();
+        // Register before reading the snapshot so no change falls in between
+        let subscription = self.subscriptions.register(interest.clone());
+        let snapshot = self.presence_for(viewer, &interest).await;
+        let snapshot = match snapshot {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                self.subscriptions.unregister(&subscription);
+                return Err(e);
+            }
+        };
         
-        // Stream presence updates
-        let stream = tokio_stream::wrappers::BroadcastStream::new(rx)
-            .filter_map(|result| async move {
-                match result {
-                    Ok(update) => Some(update),
-                    Err(_) => None,
+        let initial = PresenceBatch {
+            updates: snapshot
+                .into_iter()
+                .map(|(user_id, presence)| PresenceUpdate {
+                    user_id,
+                    status: presence.status,
+                    device_id: String::new(),
+                    last_active: presence.last_active,
+                    custom_status: presence.custom_status,
+                })
+                .collect(),
+        };
+        
+        let (tx, rx) = mpsc::channel(SUBSCRIPTION_BUFFER);
+        let hub = self.subscriptions.clone();
+        
+        tokio::spawn(async move {
+            if tx.send(Ok(initial)).await.is_ok() {
+                loop {
+                    tokio::select! {
+                        _ = subscription.changed() => {}
+                        _ = tx.closed() => break,
+                    }
+                    
+                    tokio::time::sleep(Duration::from_millis(BATCH_WINDOW_MS)).await;
+                    
+                    let updates: Vec<PresenceUpdate> = subscription
+                        .drain()
+                        .into_iter()
+                        .map(|event| {
+                            let visibility = visibility.get(&event.user_id).copied().unwrap_or(HIDDEN);
+                            to_update(event, visibility)
+                        })
+                        .collect();
+                    
+                    if updates.is_empty() {
+                        continue;
+                    }
+                    
+                    if tx.send(Ok(PresenceBatch { updates })).await.is_err() {
+                        break;
+                    }
                 }
-            })
-            .map(|update| Ok(update));
+            }
+            
+            hub.unregister(&subscription);
+        });
         
-        Ok(Response::new(Box::pin(stream) as tonic::Streaming<PresenceUpdate>))
+        Ok(Response::new(ReceiverStream::new(rx)))
     }
     
     async fn get_online_friends(
         &self,
         request: Request<GetOnlineFriendsRequest>,
     ) -> Result<Response<GetOnlineFriendsResponse>, Status> {
+        let viewer = authenticate(&request)?;
         let req = request.into_inner();
         
         let user_id = Uuid::parse_str(&req.user_id)
             .map_err(|_| Status::invalid_argument("Invalid user ID"))?;
         
+        if user_id != viewer {
+            return Err(Status::permission_denied("Can only list your own friends"));
+        }
+        
         // Get user's friends from database
         let friends = sqlx::query!(
             r#"
@@ -307,9 +468,9 @@ impl Presence for PresenceService {
             Status::internal("Failed to get friends")
         })?;
         
-        let friend_ids: Vec<String> = friends
+        let friend_ids: Vec<Uuid> = friends
             .into_iter()
-            .map(|f| f.related_user_id.to_string())
+            .map(|f| f.related_user_id)
             .collect();
         
         if friend_ids.is_empty() {
@@ -318,14 +479,13 @@ impl Presence for PresenceService {
             }));
         }
         
-        // Get presence for all friends
-        let presence_req = GetPresenceRequest { user_ids: friend_ids };
-        let presence_resp = self.get_presence(Request::new(presence_req)).await?;
+        // Get presence for all friends, as the caller is allowed to see it
+        let presence = self.presence_for(viewer, &friend_ids).await?;
         
         let mut online_friends = Vec::new();
         
-        for (friend_id, presence) in presence_resp.into_inner().presence {
-            if presence.status == "online" {
+        for (friend_id, presence) in presence {
+            if parse_status(presence.status).map_or(false, |status| status.is_online()) {
                 online_friends.push(FriendPresence {
                     user_id: friend_id,
                     status: presence.status,
@@ -338,6 +498,70 @@ impl Presence for PresenceService {
         Ok(Response::new(GetOnlineFriendsResponse { online_friends }))
     }
     
+    async fn get_active_time(
+        &self,
+        request: Request<GetActiveTimeRequest>,
+    ) -> Result<Response<GetActiveTimeResponse>, Status> {
+        let viewer = authenticate(&request)?;
+        let req = request.into_inner();
+        
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user ID"))?;
+        
+        // Only the user themselves and admins see how long someone was online
+        if viewer != user_id {
+            self.require_admin(viewer).await?;
+        }
+        let from = parse_day(&req.from_day)?;
+        let to = parse_day(&req.to_day)?;
+        
+        if to < from || (to - from).num_days() >= MAX_ANALYTICS_RANGE_DAYS {
+            return Err(Status::invalid_argument(format!(
+                "Range must span 1 to {} days", MAX_ANALYTICS_RANGE_DAYS
+            )));
+        }
+        
+        let days = analytics::active_time(&self.pg_pool, user_id, from, to).await
+            .map_err(|e| {
+                error!("Failed to load active time: {}", e);
+                Status::internal("Failed to load active time")
+            })?;
+        
+        Ok(Response::new(GetActiveTimeResponse {
+            days: days
+                .into_iter()
+                .map(|day| DailyActiveTime {
+                    day: day.day.to_string(),
+                    active_seconds: day.active_seconds,
+                    sessions: day.sessions,
+                })
+                .collect(),
+        }))
+    }
+    
+    async fn get_active_users(
+        &self,
+        request: Request<GetActiveUsersRequest>,
+    ) -> Result<Response<GetActiveUsersResponse>, Status> {
+        let viewer = authenticate(&request)?;
+        self.require_admin(viewer).await?;
+        
+        let req = request.into_inner();
+        let day = parse_day(&req.day)?;
+        
+        let counts = analytics::active_users(&self.pg_pool, day).await
+            .map_err(|e| {
+                error!("Failed to count active users: {}", e);
+                Status::internal("Failed to count active users")
+            })?;
+        
+        Ok(Response::new(GetActiveUsersResponse {
+            day: day.to_string(),
+            daily_active_users: counts.daily,
+            monthly_active_users: counts.monthly,
+        }))
+    }
+    
     async fn bulk_presence_update(
         &self,
         request: Request<BulkPresenceUpdateRequest>,
@@ -374,12 +598,14 @@ impl PresenceService {
     async fn new() -> Result<Self, Box<dyn std::error::Error>> {
         let redis_url = std::env::var("REDIS_URL")
             .expect("REDIS_URL must be set");
-        let redis_client = redis::Client::open(redis_url)?;
+        let store = PresenceStore::connect(&redis_url, DEFAULT_CACHE_TTL).await?;
         
         let database_url = std::env::var("DATABASE_URL")
             .expect("DATABASE_URL must be set");
         let pg_pool = sqlx::PgPool::connect(&database_url).await?;
         
+        shared::migrations::MIGRATOR.run(&pg_pool).await?;
+        
         let kafka_brokers = std::env::var("KAFKA_BROKERS")
             .unwrap_or_else(|_| "localhost:9092".to_string());
         
@@ -389,93 +615,427 @@ impl PresenceService {
             .create()?;
         
         Ok(Self {
-            redis_client,
+            store,
             pg_pool,
             kafka_producer,
-            user_subscriptions: Arc::new(DashMap::new()),
-            active_users: Arc::new(RwLock::new(HashMap::new())),
+            subscriptions: Arc::new(SubscriptionHub::default()),
+            replica_id: Uuid::new_v4().to_string(),
+            recorded_heartbeats: Arc::new(DashMap::new()),
         })
     }
     
-    async fn publish_presence_update(
+    /// Writes a heartbeat to history if the device's last one there is old
+    /// enough, so the analytics rollup knows the device was still connected.
+    /// Analytics only; a failure doesn't fail the heartbeat.
+    async fn record_heartbeat(&self, device: &PresenceState, status: PresenceStatus, now: DateTime<Utc>) {
+        let key = (device.user_id, device.device_id.clone());
+        
+        let due = self.recorded_heartbeats
+            .get(&key)
+            .map_or(true, |recorded_at| {
+                now - *recorded_at >= chrono::Duration::seconds(HEARTBEAT_RECORD_INTERVAL_SECS)
+            });
+        
+        if !due {
+            return;
+        }
+        
+        let recorded = sqlx::query!(
+            r#"
+            INSERT INTO user_presence_history 
+            (user_id, device_id, status, ip_address, user_agent, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            device.user_id,
+            device.device_id,
+            status.as_str(),
+            device.ip_address,
+            device.user_agent,
+            now
+        )
+        .execute(&self.pg_pool)
+        .await;
+        
+        match recorded {
+            Ok(_) => {
+                self.recorded_heartbeats.insert(key, now);
+            }
+            Err(e) => warn!("Failed to record heartbeat of user {}: {}", device.user_id, e),
+        }
+    }
+    
+    async fn require_admin(&self, user_id: Uuid) -> Result<(), Status> {
+        let is_admin = sqlx::query!(
+            "SELECT 1 AS found FROM users WHERE id = $1 AND is_admin = true",
+            user_id
+        )
+        .fetch_optional(&self.pg_pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to load user {}: {}", user_id, e);
+            Status::internal("Failed to check permissions")
+        })?;
+        
+        if is_admin.is_none() {
+            return Err(Status::permission_denied("Insufficient permissions"));
+        }
+        
+        Ok(())
+    }
+    
+    /// Each user's presence as `viewer` is allowed to see it.
+    async fn presence_for(
         &self,
-        update: &UpdatePresenceRequest,
-        timestamp: &DateTime<Utc>,
-    ) -> Result<(), Status> {
-        let presence_event = PresenceEvent {
-            user_id: update.user_id.clone(),
-            status: update.status.clone(),
-            device_id: update.device_id.clone(),
-            timestamp: timestamp.timestamp(),
-            custom_status: update.custom_status.clone(),
-        };
+        viewer: Uuid,
+        user_ids: &[Uuid],
+    ) -> Result<HashMap<String, UserPresence>, Status> {
+        let visibility = self.visibility_for(viewer, user_ids).await?;
         
-        let payload = serde_json::to_vec(&presence_event)
+        // Every user in one batch rather than a Redis round trip each
+        let devices_by_user = self.store.load_many(user_ids).await
+            .map_err(|e| {
+                error!("Failed to get presence: {}", e);
+                Status::internal("Failed to get presence")
+            })?;
+        let custom_statuses = self.store.load_custom_statuses(user_ids).await
             .map_err(|e| {
-                error!("Failed to serialize presence event: {}", e);
-                Status::internal("Serialization failed")
+                error!("Failed to get custom statuses: {}", e);
+                Status::internal("Failed to get presence")
             })?;
         
-        let record = rdkafka::producer::FutureRecord::to("presence-events")
-            .key(&update.user_id)
-            .payload(&payload);
+        let now = Utc::now();
+        let mut presence_map = HashMap::new();
+        
+        for &user_id in user_ids {
+            let visibility = visibility.get(&user_id).copied().unwrap_or(HIDDEN);
+            let devices = devices_by_user.get(&user_id).map(Vec::as_slice).unwrap_or_default();
+            
+            let overall = aggregate(devices, now).map(|(status, device)| {
+                // Users see their own invisibility; everyone else sees them offline
+                let status = if user_id == viewer { status } else { status.as_seen_by_others() };
+                (status, device)
+            });
+            
+            // Hidden and invisible users look exactly like users with no devices
+            let presence = match overall {
+                Some((status, device)) if visibility.online && status != PresenceStatus::Offline => UserPresence {
+                    status: ProtoStatus::from(status) as i32,
+                    last_active: if visibility.last_seen { device.last_activity.timestamp() } else { 0 },
+                    custom_status: custom_statuses.get(&user_id).cloned().map(Into::into),
+                    devices: devices.iter().filter(|device| device.expires_at > now).count() as u32,
+                },
+                _ => UserPresence {
+                    status: ProtoStatus::Offline as i32,
+                    last_active: 0,
+                    custom_status: None,
+                    devices: 0,
+                },
+            };
+            
+            presence_map.insert(user_id.to_string(), presence);
+        }
         
-        self.kafka_producer.send(record, Duration::from_secs(5)).await
+        Ok(presence_map)
+    }
+    
+    async fn load_devices(&self, user_id: Uuid) -> Result<Vec<PresenceState>, Status> {
+        self.store.load_devices(user_id).await
             .map_err(|e| {
-                error!("Failed to publish presence event: {}", e);
-                Status::internal("Kafka publish failed")
+                error!("Failed to get presence: {}", e);
+                Status::internal("Failed to get presence")
+            })
+    }
+    
+    async fn load_custom_status(&self, user_id: Uuid) -> Result<Option<CustomStatus>, Status> {
+        self.store.load_custom_status(user_id).await
+            .map_err(|e| {
+                error!("Failed to get custom status: {}", e);
+                Status::internal("Failed to get presence")
+            })
+    }
+    
+    async fn visibility_for(
+        &self,
+        viewer: Uuid,
+        user_ids: &[Uuid],
+    ) -> Result<HashMap<Uuid, Visibility>, Status> {
+        privacy::visibility_for(&self.pg_pool, viewer, user_ids).await
+            .map_err(|e| {
+                error!("Failed to load privacy settings: {}", e);
+                Status::internal("Failed to load privacy settings")
+            })
+    }
+    
+    /// Everyone the caller asked to watch, without duplicates or the caller.
+    async fn resolve_interest(
+        &self,
+        viewer: Uuid,
+        req: &SubscribePresenceRequest,
+    ) -> Result<Vec<Uuid>, Status> {
+        let mut interest: Vec<Uuid> = req.user_ids
+            .iter()
+            .map(|id| Uuid::parse_str(id))
+            .collect::<Result<_, _>>()
+            .map_err(|_| Status::invalid_argument("Invalid user ID"))?;
+        
+        if req.include_contacts {
+            let contacts = sqlx::query!(
+                r#"
+                SELECT related_user_id
+                FROM user_relationships
+                WHERE user_id = $1 AND relationship_type IN ('friend', 'contact')
+                "#,
+                viewer
+            )
+            .fetch_all(&self.pg_pool)
+            .await
+            .map_err(|e| {
+                error!("Failed to get contacts: {}", e);
+                Status::internal("Failed to get contacts")
             })?;
+            
+            interest.extend(contacts.into_iter().map(|row| row.related_user_id));
+        }
         
-        Ok(())
+        if !req.conversation_ids.is_empty() {
+            let conversation_ids: Vec<Uuid> = req.conversation_ids
+                .iter()
+                .map(|id| Uuid::parse_str(id))
+                .collect::<Result<_, _>>()
+                .map_err(|_| Status::invalid_argument("Invalid conversation ID"))?;
+            
+            // Only conversations the caller belongs to
+            let members = sqlx::query!(
+                r#"
+                SELECT DISTINCT m.user_id
+                FROM group_members m
+                JOIN group_members me
+                  ON me.group_id = m.group_id AND me.user_id = $1 AND me.is_banned = false
+                WHERE m.group_id = ANY($2) AND m.is_banned = false
+                "#,
+                viewer,
+                &conversation_ids
+            )
+            .fetch_all(&self.pg_pool)
+            .await
+            .map_err(|e| {
+                error!("Failed to get conversation members: {}", e);
+                Status::internal("Failed to get conversation members")
+            })?;
+            
+            interest.extend(members.into_iter().map(|row| row.user_id));
+        }
+        
+        interest.sort_unstable();
+        interest.dedup();
+        interest.retain(|user_id| *user_id != viewer);
+        
+        if interest.len() > MAX_INTEREST_SET {
+            return Err(Status::invalid_argument(format!(
+                "Cannot watch more than {} users", MAX_INTEREST_SET
+            )));
+        }
+        
+        Ok(interest)
+    }
+    
+    /// Tells subscribers and other services about the user's new overall status.
+    async fn announce(
+        &self,
+        user_id: Uuid,
+        device_id: &str,
+        status: PresenceStatus,
+        custom_status: Option<CustomStatus>,
+        timestamp: DateTime<Utc>,
+    ) -> Result<(), Status> {
+        // Recorded so the sweep on any replica knows what was announced;
+        // without it the user is at worst announced again
+        if let Err(e) = self.store.store_published(user_id, &(status, custom_status.clone())).await {
+            warn!("Failed to record presence announced for user {}: {}", user_id, e);
+        }
+        
+        // Subscribers on every replica, this one included, hear about it
+        // through Kafka
+        publish_presence_event(&self.kafka_producer, &PresenceEvent {
+            user_id,
+            status,
+            device_id: device_id.to_string(),
+            timestamp: timestamp.timestamp(),
+            custom_status,
+        }).await
     }
     
     async fn start_cleanup_task(&self) {
-        let redis_client = self.redis_client.clone();
-        let active_users = self.active_users.clone();
+        let kafka_producer = self.kafka_producer.clone();
+        let store = self.store.clone();
+        let recorded_heartbeats = self.recorded_heartbeats.clone();
+        let replica_id = self.replica_id.clone();
         
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            let mut interval = tokio::time::interval(Duration::from_secs(SWEEP_INTERVAL_SECS));
             
             loop {
                 interval.tick().await;
                 
                 let now = Utc::now();
                 
-                // Clean in-memory cache
-                {
-                    let mut users = active_users.write().await;
-                    users.retain(|_, states| {
-                        states.retain(|state| state.expires_at > now);
-                        !states.is_empty()
-                    });
+                store.evict_expired();
+                
+                let record_interval = chrono::Duration::seconds(HEARTBEAT_RECORD_INTERVAL_SECS);
+                recorded_heartbeats.retain(|_, recorded_at| now - *recorded_at < record_interval);
+                
+                // One replica sweeps at a time, so drift is announced once
+                match store.try_claim_sweep(&replica_id, SWEEP_INTERVAL_SECS - 1).await {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(e) => {
+                        warn!("Failed to claim presence sweep: {}", e);
+                        continue;
+                    }
                 }
                 
-                // Clean Redis (letting TTL handle it automatically)
-                // Redis will auto-expire keys based on TTL
+                if let Err(e) = sweep(&store, &kafka_producer, now).await {
+                    warn!("Presence sweep failed: {}", e);
+                }
             }
         });
     }
 }
 
+/// Announces users who have drifted to idle or away, or whose custom status
+/// ran out, since they were last announced by any replica. Works from Redis
+/// alone, where devices that stop heartbeating expire.
+async fn sweep(
+    store: &PresenceStore,
+    kafka_producer: &rdkafka::producer::FutureProducer,
+    now: DateTime<Utc>,
+) -> redis::RedisResult<()> {
+    let user_ids = store.live_users().await?;
+    let devices = store.load_many_uncached(&user_ids).await?;
+    let published = store.load_published(&user_ids).await?;
+    let custom_statuses = store.load_custom_statuses(&user_ids).await?;
+    
+    let mut gone = Vec::new();
+    let mut drifted = Vec::new();
+    
+    for (user_id, states) in &devices {
+        let (status, device) = match aggregate(states, now) {
+            Some(overall) => overall,
+            None => {
+                gone.push(*user_id);
+                continue;
+            }
+        };
+        
+        let custom_status = custom_statuses.get(user_id);
+        let changed = published
+            .get(user_id)
+            .map_or(true, |(published_status, published_custom)| {
+                *published_status != status || published_custom.as_ref() != custom_status
+            });
+        
+        if changed {
+            drifted.push(PresenceEvent {
+                user_id: *user_id,
+                status,
+                device_id: device.device_id.clone(),
+                timestamp: now.timestamp(),
+                custom_status: custom_status.cloned(),
+            });
+        }
+    }
+    
+    store.prune_users(&gone).await?;
+    
+    for event in drifted {
+        if let Err(e) = publish_presence_event(kafka_producer, &event).await {
+            warn!("Failed to announce presence of user {}: {}", event.user_id, e);
+            continue;
+        }
+        
+        let announced: PublishedStatus = (event.status, event.custom_status);
+        if let Err(e) = store.store_published(event.user_id, &announced).await {
+            warn!("Failed to record presence announced for user {}: {}", event.user_id, e);
+        }
+    }
+    
+    Ok(())
+}
+
+const HIDDEN: Visibility = Visibility { online: false, last_seen: false };
+
+fn parse_day(day: &str) -> Result<chrono::NaiveDate, Status> {
+    chrono::NaiveDate::parse_from_str(day, "%Y-%m-%d")
+        .map_err(|_| Status::invalid_argument("Days must be formatted as YYYY-MM-DD"))
+}
+
+/// A presence event as a subscriber with the given visibility sees it.
+fn to_update(event: PresenceEvent, visibility: Visibility) -> PresenceUpdate {
+    let status = event.status.as_seen_by_others();
+    let shown = visibility.online && status.is_online();
+    
+    PresenceUpdate {
+        user_id: event.user_id.to_string(),
+        status: ProtoStatus::from(if shown { status } else { PresenceStatus::Offline }) as i32,
+        device_id: event.device_id,
+        last_active: if shown && visibility.last_seen { event.timestamp } else { 0 },
+        custom_status: event.custom_status.filter(|_| shown).map(Into::into),
+    }
+}
+
+/// The user behind the bearer token in the `authorization` metadata.
+fn authenticate<T>(request: &Request<T>) -> Result<Uuid, Status> {
+    use jsonwebtoken::{decode, DecodingKey, Validation};
+    
+    let token = request
+        .metadata()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| Status::unauthenticated("Missing token"))?;
+    
+    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    let token_data = decode::<TokenClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::default(),
+    )
+    .map_err(|_| Status::unauthenticated("Invalid token"))?;
+    
+    Ok(token_data.claims.sub)
+}
+
 #[derive(Debug, Serialize, Deserialize)]
-struct RedisPresence {
-    user_id: String,
-    status: String,
+struct TokenClaims {
+    sub: Uuid,
+    exp: usize,
+    iat: usize,
     device_id: String,
-    ip_address: Option<String>,
-    user_agent: Option<String>,
-    custom_status: Option<String>,
-    last_active: DateTime<Utc>,
-    expires_at: DateTime<Utc>,
+    session_id: Uuid,
 }
 
-#[derive(Debug, Serialize)]
-struct PresenceEvent {
-    user_id: String,
-    status: String,
-    device_id: String,
-    timestamp: i64,
-    custom_status: Option<String>,
+async fn publish_presence_event(
+    kafka_producer: &rdkafka::producer::FutureProducer,
+    event: &PresenceEvent,
+) -> Result<(), Status> {
+    let payload = serde_json::to_vec(event)
+        .map_err(|e| {
+            error!("Failed to serialize presence event: {}", e);
+            Status::internal("Serialization failed")
+        })?;
+    
+    let key = event.user_id.to_string();
+    let record = rdkafka::producer::FutureRecord::to("presence-events")
+        .key(&key)
+        .payload(&payload);
+    
+    kafka_producer.send(record, Duration::from_secs(5)).await
+        .map_err(|(e, _)| {
+            error!("Failed to publish presence event: {}", e);
+            Status::internal("Kafka publish failed")
+        })?;
+    
+    Ok(())
 }
 
 #[tokio::main]
@@ -489,6 +1049,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Start background cleanup task
     presence_service.start_cleanup_task().await;
     
+    let retention_days = std::env::var("PRESENCE_HISTORY_RETENTION_DAYS")
+        .ok()
+        .and_then(|days| days.parse().ok())
+        .unwrap_or(DEFAULT_HISTORY_RETENTION_DAYS);
+    let rollup = analytics::RollupJob::new(presence_service.pg_pool.clone(), retention_days);
+    tokio::spawn(rollup.run());
+    
+    let hub = presence_service.subscriptions.clone();
+    tokio::spawn(async move {
+        if let Err(e) = subscriptions::consume_events(hub).await {
+            error!("Presence event consumer error: {}", e);
+        }
+    });
+    
     let addr = "[::1]:50052".parse()?;
     
     info!("Presence service listening on {}", addr);
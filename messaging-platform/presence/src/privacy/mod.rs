@@ -0,0 +1,114 @@
+use std::collections::{HashMap, HashSet};
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use shared::models::PresenceVisibility;
+
+/// What one user may see of another's presence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Visibility {
+    pub online: bool,
+    pub last_seen: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PrivacySettings {
+    last_seen: PresenceVisibility,
+    online: PresenceVisibility,
+}
+
+/// Works out what `viewer` may see of each of `targets`. Sharing is
+/// reciprocal: the target has to share with the viewer, and the viewer has
+/// to share the same thing with the target. A block either way hides both.
+pub async fn visibility_for(
+    pg_pool: &PgPool,
+    viewer: Uuid,
+    targets: &[Uuid],
+) -> Result<HashMap<Uuid, Visibility>, sqlx::Error> {
+    let mut user_ids = targets.to_vec();
+    user_ids.push(viewer);
+    
+    let settings: HashMap<Uuid, PrivacySettings> = sqlx::query!(
+        r#"
+        SELECT user_id, last_seen_visibility, online_visibility
+        FROM user_privacy_settings
+        WHERE user_id = ANY($1)
+        "#,
+        &user_ids
+    )
+    .fetch_all(pg_pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        (row.user_id, PrivacySettings {
+            last_seen: row.last_seen_visibility.parse().unwrap_or_default(),
+            online: row.online_visibility.parse().unwrap_or_default(),
+        })
+    })
+    .collect();
+    
+    // (owner, contact) pairs in either direction between viewer and targets
+    let contacts: HashSet<(Uuid, Uuid)> = sqlx::query!(
+        r#"
+        SELECT user_id, related_user_id
+        FROM user_relationships
+        WHERE relationship_type IN ('friend', 'contact')
+          AND ((user_id = $1 AND related_user_id = ANY($2))
+            OR (user_id = ANY($2) AND related_user_id = $1))
+        "#,
+        viewer,
+        targets
+    )
+    .fetch_all(pg_pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.user_id, row.related_user_id))
+    .collect();
+    
+    // Users who blocked the viewer, or whom the viewer blocked
+    let blocked: HashSet<Uuid> = sqlx::query!(
+        r#"
+        SELECT user_id, related_user_id
+        FROM user_relationships
+        WHERE relationship_type = 'blocked'
+          AND ((user_id = $1 AND related_user_id = ANY($2))
+            OR (user_id = ANY($2) AND related_user_id = $1))
+        "#,
+        viewer,
+        targets
+    )
+    .fetch_all(pg_pool)
+    .await?
+    .into_iter()
+    .map(|row| if row.user_id == viewer { row.related_user_id } else { row.user_id })
+    .collect();
+    
+    let viewer_settings = settings.get(&viewer).copied().unwrap_or_default();
+    
+    Ok(targets
+        .iter()
+        .map(|&target| {
+            if target == viewer {
+                return (target, Visibility { online: true, last_seen: true });
+            }
+            
+            if blocked.contains(&target) {
+                return (target, Visibility { online: false, last_seen: false });
+            }
+            
+            let target_settings = settings.get(&target).copied().unwrap_or_default();
+            let viewer_is_contact = contacts.contains(&(target, viewer));
+            let target_is_contact = contacts.contains(&(viewer, target));
+            
+            let visibility = Visibility {
+                online: target_settings.online.allows(viewer_is_contact)
+                    && viewer_settings.online.allows(target_is_contact),
+                last_seen: target_settings.last_seen.allows(viewer_is_contact)
+                    && viewer_settings.last_seen.allows(target_is_contact),
+            };
+            
+            (target, visibility)
+        })
+        .collect())
+}
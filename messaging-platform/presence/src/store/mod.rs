@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use redis::aio::ConnectionManager;
+use redis::RedisResult;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use shared::models::{CustomStatus, PresenceStatus};
+
+pub const PRESENCE_TTL_SECS: usize = 300;
+// Minutes without user activity before an online device is idle, then away
+pub const IDLE_AFTER_MINUTES: i64 = 5;
+pub const AWAY_AFTER_MINUTES: i64 = 30;
+// Long enough to absorb bursts of lookups for the same users, short enough
+// that changes made on other replicas show up promptly
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_millis(1000);
+// Keys per MGET, so one huge lookup doesn't block Redis
+const MGET_CHUNK_SIZE: usize = 500;
+// Every user who has stored a device since the last sweep pruned them
+const LIVE_USERS_KEY: &str = "presence_live_users";
+// The overall status and custom status last announced per user, by any replica
+const PUBLISHED_KEY: &str = "presence_published";
+// Held by whichever replica is sweeping, so drift is announced once
+const SWEEP_LOCK_KEY: &str = "presence_sweep_lock";
+
+/// What was last announced for a user.
+pub type PublishedStatus = (PresenceStatus, Option<CustomStatus>);
+
+/// One device's presence, stored in Redis under `presence:{user_id}:{device_id}`
+/// and listed in the `user_presence:{user_id}` set. The user's custom status
+/// is shared by their devices and kept under `custom_status:{user_id}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresenceState {
+    pub user_id: Uuid,
+    // The status the user chose; see `effective_status`
+    pub status: PresenceStatus,
+    pub device_id: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    // Last time the device was heard from at all
+    pub last_active: DateTime<Utc>,
+    // Last time the user interacted on the device
+    pub last_activity: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl PresenceState {
+    pub fn effective_status(&self, now: DateTime<Utc>) -> PresenceStatus {
+        let inactive_minutes = (now - self.last_activity).num_minutes();
+        self.status.after_inactivity(inactive_minutes, IDLE_AFTER_MINUTES, AWAY_AFTER_MINUTES)
+    }
+}
+
+/// Device presence in Redis over one multiplexed, self-reconnecting
+/// connection, with a short-lived local cache in front of batch reads.
+#[derive(Clone)]
+pub struct PresenceStore {
+    connection: ConnectionManager,
+    cache: Arc<DashMap<Uuid, (Instant, Vec<PresenceState>)>>,
+    cache_ttl: Duration,
+}
+
+impl PresenceStore {
+    pub async fn connect(redis_url: &str, cache_ttl: Duration) -> RedisResult<Self> {
+        let client = redis::Client::open(redis_url)?;
+        let connection = ConnectionManager::new(client).await?;
+        
+        Ok(Self {
+            connection,
+            cache: Arc::new(DashMap::new()),
+            cache_ttl,
+        })
+    }
+    
+    pub async fn store_device(&self, state: &PresenceState) -> RedisResult<()> {
+        let device_key = device_key(state.user_id, &state.device_id);
+        let user_key = user_key(state.user_id);
+        
+        let presence_data = serde_json::to_string(state).map_err(|e| {
+            redis::RedisError::from((redis::ErrorKind::TypeError, "Failed to serialize presence", e.to_string()))
+        })?;
+        
+        let mut conn = self.connection.clone();
+        redis::pipe()
+            .atomic()
+            .set_ex(&device_key, presence_data, PRESENCE_TTL_SECS).ignore()
+            .sadd(&user_key, &device_key).ignore()
+            .expire(&user_key, PRESENCE_TTL_SECS).ignore()
+            .sadd(LIVE_USERS_KEY, state.user_id.to_string()).ignore()
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+        
+        self.cache.remove(&state.user_id);
+        Ok(())
+    }
+    
+    pub async fn remove_device(&self, user_id: Uuid, device_id: &str) -> RedisResult<()> {
+        let device_key = device_key(user_id, device_id);
+        
+        let mut conn = self.connection.clone();
+        redis::pipe()
+            .atomic()
+            .del(&device_key).ignore()
+            .srem(user_key(user_id), &device_key).ignore()
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+        
+        self.cache.remove(&user_id);
+        Ok(())
+    }
+    
+    /// The user's devices straight from Redis, for read-modify-write paths
+    /// that must not act on a cached copy.
+    pub async fn load_devices(&self, user_id: Uuid) -> RedisResult<Vec<PresenceState>> {
+        let mut devices = self.fetch(&[user_id]).await?;
+        Ok(devices.pop().unwrap_or_default())
+    }
+    
+    /// Devices for every user in two round trips however many users there
+    /// are, serving recently read users from the local cache.
+    pub async fn load_many(&self, user_ids: &[Uuid]) -> RedisResult<HashMap<Uuid, Vec<PresenceState>>> {
+        let now = Instant::now();
+        let mut result = HashMap::with_capacity(user_ids.len());
+        let mut missing = Vec::new();
+        
+        for &user_id in user_ids {
+            match self.cache.get(&user_id) {
+                Some(entry) if now.duration_since(entry.0) < self.cache_ttl => {
+                    result.insert(user_id, entry.1.clone());
+                }
+                _ => missing.push(user_id),
+            }
+        }
+        
+        if missing.is_empty() {
+            return Ok(result);
+        }
+        
+        missing.sort_unstable();
+        missing.dedup();
+        
+        let fetched = self.fetch(&missing).await?;
+        
+        for (user_id, devices) in missing.into_iter().zip(fetched) {
+            if !self.cache_ttl.is_zero() {
+                self.cache.insert(user_id, (now, devices.clone()));
+            }
+            result.insert(user_id, devices);
+        }
+        
+        Ok(result)
+    }
+    
+    /// Sets the user's custom status, shared by all their devices. Redis drops
+    /// it once `clear_after` passes, so it outlives reconnects but not its
+    /// expiry.
+    pub async fn store_custom_status(&self, user_id: Uuid, custom_status: &CustomStatus) -> RedisResult<()> {
+        let data = serde_json::to_string(custom_status).map_err(|e| {
+            redis::RedisError::from((redis::ErrorKind::TypeError, "Failed to serialize custom status", e.to_string()))
+        })?;
+        
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(custom_status_key(user_id)).arg(data);
+        if let Some(clear_after) = custom_status.clear_after {
+            let remaining = (clear_after - Utc::now().timestamp()).max(1);
+            cmd.arg("EX").arg(remaining);
+        }
+        
+        let mut conn = self.connection.clone();
+        cmd.query_async(&mut conn).await
+    }
+    
+    pub async fn clear_custom_status(&self, user_id: Uuid) -> RedisResult<()> {
+        let mut conn = self.connection.clone();
+        redis::cmd("DEL")
+            .arg(custom_status_key(user_id))
+            .query_async(&mut conn)
+            .await
+    }
+    
+    /// Custom statuses of the users who have one that hasn't expired.
+    pub async fn load_custom_statuses(&self, user_ids: &[Uuid]) -> RedisResult<HashMap<Uuid, CustomStatus>> {
+        if user_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        
+        let keys: Vec<String> = user_ids.iter().map(|&user_id| custom_status_key(user_id)).collect();
+        
+        let mut conn = self.connection.clone();
+        let mut pipe = redis::pipe();
+        for chunk in keys.chunks(MGET_CHUNK_SIZE) {
+            pipe.cmd("MGET").arg(chunk);
+        }
+        let chunks: Vec<Vec<Option<String>>> = pipe.query_async(&mut conn).await?;
+        
+        // Redis expires keys lazily to the second; the timestamp is exact
+        let now = Utc::now().timestamp();
+        
+        Ok(user_ids
+            .iter()
+            .zip(chunks.into_iter().flatten())
+            .filter_map(|(&user_id, data)| {
+                let custom_status: CustomStatus = serde_json::from_str(&data?).ok()?;
+                (!custom_status.is_expired(now)).then_some((user_id, custom_status))
+            })
+            .collect())
+    }
+    
+    pub async fn load_custom_status(&self, user_id: Uuid) -> RedisResult<Option<CustomStatus>> {
+        Ok(self.load_custom_statuses(&[user_id]).await?.remove(&user_id))
+    }
+    
+    /// Devices for every user straight from Redis, bypassing the cache.
+    pub async fn load_many_uncached(&self, user_ids: &[Uuid]) -> RedisResult<HashMap<Uuid, Vec<PresenceState>>> {
+        let fetched = self.fetch(user_ids).await?;
+        Ok(user_ids.iter().copied().zip(fetched).collect())
+    }
+    
+    /// Users who have stored a device and not yet been pruned. Some may
+    /// have no live devices left.
+    pub async fn live_users(&self) -> RedisResult<Vec<Uuid>> {
+        let mut conn = self.connection.clone();
+        let members: Vec<String> = redis::cmd("SMEMBERS").arg(LIVE_USERS_KEY).query_async(&mut conn).await?;
+        Ok(members.iter().filter_map(|member| Uuid::parse_str(member).ok()).collect())
+    }
+    
+    /// Forgets users with no devices left, along with what was last
+    /// announced for them.
+    pub async fn prune_users(&self, user_ids: &[Uuid]) -> RedisResult<()> {
+        if user_ids.is_empty() {
+            return Ok(());
+        }
+        
+        let members: Vec<String> = user_ids.iter().map(Uuid::to_string).collect();
+        
+        let mut conn = self.connection.clone();
+        redis::pipe()
+            .atomic()
+            .srem(LIVE_USERS_KEY, &members).ignore()
+            .hdel(PUBLISHED_KEY, &members).ignore()
+            .query_async::<_, ()>(&mut conn)
+            .await
+    }
+    
+    /// What was last announced for each user that has been announced.
+    pub async fn load_published(&self, user_ids: &[Uuid]) -> RedisResult<HashMap<Uuid, PublishedStatus>> {
+        if user_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        
+        let mut conn = self.connection.clone();
+        let values: Vec<Option<String>> = redis::cmd("HMGET")
+            .arg(PUBLISHED_KEY)
+            .arg(user_ids.iter().map(Uuid::to_string).collect::<Vec<_>>())
+            .query_async(&mut conn)
+            .await?;
+        
+        Ok(user_ids
+            .iter()
+            .zip(values)
+            .filter_map(|(&user_id, value)| {
+                let published = serde_json::from_str(&value?).ok()?;
+                Some((user_id, published))
+            })
+            .collect())
+    }
+    
+    pub async fn store_published(&self, user_id: Uuid, published: &PublishedStatus) -> RedisResult<()> {
+        let data = serde_json::to_string(published).map_err(|e| {
+            redis::RedisError::from((redis::ErrorKind::TypeError, "Failed to serialize presence", e.to_string()))
+        })?;
+        
+        let mut conn = self.connection.clone();
+        redis::cmd("HSET")
+            .arg(PUBLISHED_KEY)
+            .arg(user_id.to_string())
+            .arg(data)
+            .query_async(&mut conn)
+            .await
+    }
+    
+    /// Claims the next sweep for this replica. False if another replica
+    /// holds it.
+    pub async fn try_claim_sweep(&self, replica_id: &str, hold_secs: u64) -> RedisResult<bool> {
+        let mut conn = self.connection.clone();
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(SWEEP_LOCK_KEY)
+            .arg(replica_id)
+            .arg("NX")
+            .arg("EX")
+            .arg(hold_secs)
+            .query_async(&mut conn)
+            .await?;
+        
+        Ok(claimed.is_some())
+    }
+    
+    /// Drops cache entries past their TTL.
+    pub fn evict_expired(&self) {
+        let now = Instant::now();
+        self.cache.retain(|_, (cached_at, _)| now.duration_since(*cached_at) < self.cache_ttl);
+    }
+    
+    /// Devices for each user, in the order given.
+    async fn fetch(&self, user_ids: &[Uuid]) -> RedisResult<Vec<Vec<PresenceState>>> {
+        let mut conn = self.connection.clone();
+        
+        // Every user's device keys in one round trip
+        let mut pipe = redis::pipe();
+        for &user_id in user_ids {
+            pipe.smembers(user_key(user_id));
+        }
+        let device_keys: Vec<Vec<String>> = pipe.query_async(&mut conn).await?;
+        
+        let all_keys: Vec<&String> = device_keys.iter().flatten().collect();
+        
+        if all_keys.is_empty() {
+            return Ok(vec![Vec::new(); user_ids.len()]);
+        }
+        
+        // Then every device in a second
+        let mut pipe = redis::pipe();
+        for chunk in all_keys.chunks(MGET_CHUNK_SIZE) {
+            pipe.cmd("MGET").arg(chunk);
+        }
+        let chunks: Vec<Vec<Option<String>>> = pipe.query_async(&mut conn).await?;
+        let mut values = chunks.into_iter().flatten();
+        
+        // Keys that expired come back empty
+        Ok(device_keys
+            .iter()
+            .map(|keys| {
+                values
+                    .by_ref()
+                    .take(keys.len())
+                    .flatten()
+                    .filter_map(|data| serde_json::from_str::<PresenceState>(&data).ok())
+                    .collect()
+            })
+            .collect())
+    }
+}
+
+pub fn device_key(user_id: Uuid, device_id: &str) -> String {
+    format!("presence:{}:{}", user_id, device_id)
+}
+
+pub fn user_key(user_id: Uuid) -> String {
+    format!("user_presence:{}", user_id)
+}
+
+pub fn custom_status_key(user_id: Uuid) -> String {
+    format!("custom_status:{}", user_id)
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use dashmap::DashMap;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::Message as KafkaMessage;
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use shared::types::PresenceEvent;
+
+/// One client's presence stream. Events for the same user that arrive
+/// between flushes collapse into the latest one.
+pub struct Subscription {
+    id: Uuid,
+    interest: Vec<Uuid>,
+    pending: Mutex<HashMap<Uuid, PresenceEvent>>,
+    notify: Notify,
+}
+
+impl Subscription {
+    /// Resolves once there is at least one pending event.
+    pub async fn changed(&self) {
+        self.notify.notified().await;
+    }
+    
+    pub fn drain(&self) -> Vec<PresenceEvent> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.drain().map(|(_, event)| event).collect()
+    }
+}
+
+/// Routes presence events to the subscriptions interested in them. Every
+/// replica consumes the whole `presence-events` topic, so a subscriber hears
+/// about changes made through any replica.
+#[derive(Default)]
+pub struct SubscriptionHub {
+    watchers: DashMap<Uuid, Vec<Arc<Subscription>>>,
+}
+
+impl SubscriptionHub {
+    pub fn register(&self, interest: Vec<Uuid>) -> Arc<Subscription> {
+        let subscription = Arc::new(Subscription {
+            id: Uuid::new_v4(),
+            interest,
+            pending: Mutex::new(HashMap::new()),
+            notify: Notify::new(),
+        });
+        
+        for user_id in &subscription.interest {
+            self.watchers
+                .entry(*user_id)
+                .or_insert_with(Vec::new)
+                .push(subscription.clone());
+        }
+        
+        subscription
+    }
+    
+    pub fn unregister(&self, subscription: &Subscription) {
+        for user_id in &subscription.interest {
+            if let Some(mut watchers) = self.watchers.get_mut(user_id) {
+                watchers.retain(|watcher| watcher.id != subscription.id);
+            }
+            self.watchers.remove_if(user_id, |_, watchers| watchers.is_empty());
+        }
+    }
+    
+    pub fn dispatch(&self, event: PresenceEvent) {
+        let watchers = match self.watchers.get(&event.user_id) {
+            Some(watchers) => watchers,
+            None => return,
+        };
+        
+        for watcher in watchers.iter() {
+            watcher.pending.lock().unwrap().insert(event.user_id, event.clone());
+            watcher.notify.notify_one();
+        }
+    }
+}
+
+/// Feeds every presence event into the hub. Each replica reads the topic
+/// under its own consumer group so none miss events.
+pub async fn consume_events(hub: Arc<SubscriptionHub>) -> Result<(), Box<dyn std::error::Error>> {
+    let kafka_brokers = std::env::var("KAFKA_BROKERS")
+        .unwrap_or_else(|_| "localhost:9092".to_string());
+    
+    let consumer: StreamConsumer = rdkafka::config::ClientConfig::new()
+        .set("group.id", &format!("presence-subscriptions-{}", Uuid::new_v4()))
+        .set("bootstrap.servers", &kafka_brokers)
+        .set("enable.partition.eof", "false")
+        .set("session.timeout.ms", "6000")
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", "latest")
+        .create()?;
+    
+    consumer.subscribe(&["presence-events"])?;
+    
+    info!("Presence event consumer started");
+    
+    loop {
+        let message = match consumer.recv().await {
+            Ok(message) => message,
+            Err(e) => {
+                error!("Failed to receive presence event: {}", e);
+                continue;
+            }
+        };
+        
+        let payload = match message.payload() {
+            Some(payload) => payload,
+            None => continue,
+        };
+        
+        match serde_json::from_slice::<PresenceEvent>(payload) {
+            Ok(event) => hub.dispatch(event),
+            Err(e) => warn!("Skipping malformed presence event: {}", e),
+        }
+    }
+}
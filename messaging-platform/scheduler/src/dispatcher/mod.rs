@@ -0,0 +1,137 @@
+use rdkafka::producer::FutureProducer;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use shared::models::MessageMentions;
+
+const POLL_INTERVAL_SECS: u64 = 5;
+const BATCH_SIZE: i64 = 100;
+
+// The shape the message processor consumes from the `messages` topic
+#[derive(Debug, Serialize, Deserialize)]
+struct MessageEnvelope {
+    sender_id: Uuid,
+    conversation_id: Uuid,
+    message_id: Uuid,
+    content: Vec<u8>,
+    nonce: Vec<u8>,
+    reply_to: Option<Uuid>,
+    timestamp: i64,
+    mentions: MessageMentions,
+}
+
+/// Moves due scheduled messages onto the `messages` topic.
+///
+/// Due rows are claimed with `FOR UPDATE SKIP LOCKED`, so several schedulers
+/// can run side by side, and only marked sent once Kafka has accepted them.
+/// A crash in between re-sends the message on restart; the scheduled id goes
+/// out as the client message id, which the processor de-duplicates on.
+pub struct Dispatcher {
+    db_pool: PgPool,
+    kafka_producer: FutureProducer,
+}
+
+impl Dispatcher {
+    pub fn new(db_pool: PgPool, kafka_producer: FutureProducer) -> Self {
+        Self { db_pool, kafka_producer }
+    }
+    
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(POLL_INTERVAL_SECS));
+        
+        info!("Scheduled message dispatcher started");
+        
+        loop {
+            interval.tick().await;
+            
+            // Keep going while batches come back full
+            loop {
+                match self.dispatch_due().await {
+                    Ok(count) if count as i64 == BATCH_SIZE => continue,
+                    Ok(_) => break,
+                    Err(e) => {
+                        error!("Failed to dispatch scheduled messages: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    
+    async fn dispatch_due(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut tx = self.db_pool.begin().await?;
+        
+        let due = sqlx::query!(
+            r#"
+            SELECT id, sender_id, conversation_id, content, nonce, reply_to, deliver_at,
+                   mention_user_ids, mention_everyone
+            FROM scheduled_messages
+            WHERE status = 'pending' AND deliver_at <= NOW()
+            ORDER BY deliver_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+            "#,
+            BATCH_SIZE
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        
+        if due.is_empty() {
+            return Ok(0);
+        }
+        
+        let mut sent_ids = Vec::with_capacity(due.len());
+        
+        for message in due {
+            self.publish(MessageEnvelope {
+                sender_id: message.sender_id,
+                conversation_id: message.conversation_id,
+                message_id: message.id,
+                content: message.content,
+                nonce: message.nonce,
+                reply_to: message.reply_to,
+                timestamp: message.deliver_at.timestamp(),
+                mentions: MessageMentions {
+                    user_ids: message.mention_user_ids,
+                    everyone: message.mention_everyone,
+                },
+            }).await?;
+            
+            sent_ids.push(message.id);
+        }
+        
+        sqlx::query!(
+            r#"
+            UPDATE scheduled_messages
+            SET status = 'sent', sent_at = NOW()
+            WHERE id = ANY($1)
+            "#,
+            &sent_ids
+        )
+        .execute(&mut *tx)
+        .await?;
+        
+        tx.commit().await?;
+        
+        info!("Dispatched {} scheduled messages", sent_ids.len());
+        
+        Ok(sent_ids.len())
+    }
+    
+    async fn publish(&self, envelope: MessageEnvelope) -> Result<(), Box<dyn std::error::Error>> {
+        let payload = serde_json::to_vec(&envelope)?;
+        
+        let record = rdkafka::producer::FutureRecord::to("messages")
+            .key(&envelope.conversation_id.to_string())
+            .payload(&payload);
+        
+        self.kafka_producer
+            .send(record, std::time::Duration::from_secs(5))
+            .await
+            .map_err(|(e, _)| e)?;
+        
+        Ok(())
+    }
+}
@@ -0,0 +1,431 @@
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::{delete, get, post, put},
+    Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+use validator::Validate;
+
+use shared::errors::AppError;
+use shared::models::MessageMentions;
+
+mod dispatcher;
+
+const MAX_PENDING_PER_USER: i64 = 100;
+const MAX_SCHEDULE_AHEAD_DAYS: i64 = 365;
+
+struct AppState {
+    db_pool: PgPool,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ScheduleMessageRequest {
+    pub conversation_id: Uuid,
+    #[validate(length(min = 1, max = 65536))]
+    pub content: Vec<u8>, // Client-encrypted
+    #[validate(length(min = 1, max = 64))]
+    pub nonce: Vec<u8>,
+    pub reply_to: Option<Uuid>,
+    pub deliver_at: DateTime<Utc>,
+    #[serde(default)]
+    pub mentions: MessageMentions,
+}
+
+/// New content must come with its nonce; either may be left out only if both are.
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateScheduledMessageRequest {
+    #[validate(length(min = 1, max = 65536))]
+    pub content: Option<Vec<u8>>,
+    #[validate(length(min = 1, max = 64))]
+    pub nonce: Option<Vec<u8>>,
+    pub deliver_at: Option<DateTime<Utc>>,
+    pub mentions: Option<MessageMentions>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListScheduledQuery {
+    pub conversation_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduledMessageResponse {
+    pub id: Uuid,
+    pub conversation_id: Uuid,
+    pub content: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub reply_to: Option<Uuid>,
+    pub deliver_at: DateTime<Utc>,
+    pub mentions: MessageMentions,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt::init();
+    
+    dotenv::dotenv().ok();
+    
+    let database_url = std::env::var("DATABASE_URL")
+        .expect("DATABASE_URL must be set");
+    
+    let db_pool = PgPoolOptions::new()
+        .max_connections(20)
+        .connect(&database_url)
+        .await?;
+    
+    let kafka_brokers = std::env::var("KAFKA_BROKERS")
+        .unwrap_or_else(|_| "localhost:9092".to_string());
+    
+    let kafka_producer: rdkafka::producer::FutureProducer = rdkafka::config::ClientConfig::new()
+        .set("bootstrap.servers", &kafka_brokers)
+        .set("message.timeout.ms", "5000")
+        .create()?;
+    
+    // Run migrations
+    shared::migrations::MIGRATOR.run(&db_pool).await?;
+    
+    tokio::spawn(dispatcher::Dispatcher::new(db_pool.clone(), kafka_producer).run());
+    
+    let state = Arc::new(AppState { db_pool });
+    
+    let app = Router::new()
+        .route("/scheduled-messages", post(schedule_message))
+        .route("/scheduled-messages", get(list_scheduled_messages))
+        .route("/scheduled-messages/:message_id", put(update_scheduled_message))
+        .route("/scheduled-messages/:message_id", delete(cancel_scheduled_message))
+        .route("/health", get(health_check))
+        .with_state(state)
+        .layer(tower_http::trace::TraceLayer::new_for_http())
+        .layer(tower_http::cors::CorsLayer::permissive());
+    
+    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], 3005));
+    info!("Scheduler service listening on {}", addr);
+    
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await?;
+    
+    Ok(())
+}
+
+async fn health_check() -> impl IntoResponse {
+    (StatusCode::OK, "Scheduler service healthy")
+}
+
+async fn schedule_message(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(payload): Json<ScheduleMessageRequest>,
+) -> Result<(StatusCode, Json<ScheduledMessageResponse>), AppError> {
+    let user_id = authenticate(&headers)?;
+    
+    payload.validate()?;
+    validate_deliver_at(payload.deliver_at)?;
+    validate_mentions(&payload.mentions)?;
+    
+    // Permissions are checked again by the processor when the message goes out
+    if !is_member(&state.db_pool, payload.conversation_id, user_id).await? {
+        return Err(AppError::Forbidden("Not a member of this conversation".to_string()));
+    }
+    
+    let pending = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!" FROM scheduled_messages
+        WHERE sender_id = $1 AND status = 'pending'
+        "#,
+        user_id
+    )
+    .fetch_one(&state.db_pool)
+    .await?;
+    
+    if pending >= MAX_PENDING_PER_USER {
+        return Err(AppError::ValidationError(format!(
+            "At most {} messages can be scheduled at once",
+            MAX_PENDING_PER_USER
+        )));
+    }
+    
+    let id = Uuid::new_v4();
+    
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO scheduled_messages
+        (id, sender_id, conversation_id, content, nonce, reply_to, deliver_at,
+         mention_user_ids, mention_everyone)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING created_at, updated_at
+        "#,
+        id,
+        user_id,
+        payload.conversation_id,
+        &payload.content,
+        &payload.nonce,
+        payload.reply_to,
+        payload.deliver_at,
+        &payload.mentions.user_ids,
+        payload.mentions.everyone
+    )
+    .fetch_one(&state.db_pool)
+    .await?;
+    
+    info!("User {} scheduled message {} for {}", user_id, id, payload.deliver_at);
+    
+    Ok((StatusCode::CREATED, Json(ScheduledMessageResponse {
+        id,
+        conversation_id: payload.conversation_id,
+        content: payload.content,
+        nonce: payload.nonce,
+        reply_to: payload.reply_to,
+        deliver_at: payload.deliver_at,
+        mentions: payload.mentions,
+        created_at: row.created_at,
+        updated_at: row.updated_at,
+    })))
+}
+
+async fn list_scheduled_messages(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<ListScheduledQuery>,
+) -> Result<Json<Vec<ScheduledMessageResponse>>, AppError> {
+    let user_id = authenticate(&headers)?;
+    
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, conversation_id, content, nonce, reply_to, deliver_at,
+               mention_user_ids, mention_everyone, created_at, updated_at
+        FROM scheduled_messages
+        WHERE sender_id = $1 AND status = 'pending'
+        AND ($2::uuid IS NULL OR conversation_id = $2)
+        ORDER BY deliver_at
+        "#,
+        user_id,
+        query.conversation_id
+    )
+    .fetch_all(&state.db_pool)
+    .await?;
+    
+    let messages = rows.into_iter()
+        .map(|row| ScheduledMessageResponse {
+            id: row.id,
+            conversation_id: row.conversation_id,
+            content: row.content,
+            nonce: row.nonce,
+            reply_to: row.reply_to,
+            deliver_at: row.deliver_at,
+            mentions: MessageMentions {
+                user_ids: row.mention_user_ids,
+                everyone: row.mention_everyone,
+            },
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        })
+        .collect();
+    
+    Ok(Json(messages))
+}
+
+async fn update_scheduled_message(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(message_id): Path<Uuid>,
+    Json(payload): Json<UpdateScheduledMessageRequest>,
+) -> Result<Json<ScheduledMessageResponse>, AppError> {
+    let user_id = authenticate(&headers)?;
+    
+    payload.validate()?;
+    
+    if payload.content.is_some() != payload.nonce.is_some() {
+        return Err(AppError::ValidationError(
+            "Content and nonce must be updated together".to_string(),
+        ));
+    }
+    
+    if let Some(deliver_at) = payload.deliver_at {
+        validate_deliver_at(deliver_at)?;
+    }
+    
+    if let Some(mentions) = &payload.mentions {
+        validate_mentions(mentions)?;
+    }
+    
+    let (mention_user_ids, mention_everyone) = match payload.mentions {
+        Some(mentions) => (Some(mentions.user_ids), Some(mentions.everyone)),
+        None => (None, None),
+    };
+    
+    // Waits on the dispatcher's row lock, so a message being sent right now
+    // is either updated first or reported as no longer pending
+    let row = sqlx::query!(
+        r#"
+        UPDATE scheduled_messages
+        SET content = COALESCE($1, content),
+            nonce = COALESCE($2, nonce),
+            deliver_at = COALESCE($3, deliver_at),
+            mention_user_ids = COALESCE($4, mention_user_ids),
+            mention_everyone = COALESCE($5, mention_everyone),
+            updated_at = NOW()
+        WHERE id = $6 AND sender_id = $7 AND status = 'pending'
+        RETURNING conversation_id, content, nonce, reply_to, deliver_at,
+                  mention_user_ids, mention_everyone, created_at, updated_at
+        "#,
+        payload.content,
+        payload.nonce,
+        payload.deliver_at,
+        mention_user_ids.as_deref(),
+        mention_everyone,
+        message_id,
+        user_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await?;
+    
+    let row = match row {
+        Some(row) => row,
+        None => return Err(not_pending_error(&state.db_pool, message_id, user_id).await),
+    };
+    
+    Ok(Json(ScheduledMessageResponse {
+        id: message_id,
+        conversation_id: row.conversation_id,
+        content: row.content,
+        nonce: row.nonce,
+        reply_to: row.reply_to,
+        deliver_at: row.deliver_at,
+        mentions: MessageMentions {
+            user_ids: row.mention_user_ids,
+            everyone: row.mention_everyone,
+        },
+        created_at: row.created_at,
+        updated_at: row.updated_at,
+    }))
+}
+
+async fn cancel_scheduled_message(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(message_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let user_id = authenticate(&headers)?;
+    
+    let result = sqlx::query!(
+        r#"
+        UPDATE scheduled_messages
+        SET status = 'cancelled', updated_at = NOW()
+        WHERE id = $1 AND sender_id = $2 AND status = 'pending'
+        "#,
+        message_id,
+        user_id
+    )
+    .execute(&state.db_pool)
+    .await?;
+    
+    if result.rows_affected() == 0 {
+        return Err(not_pending_error(&state.db_pool, message_id, user_id).await);
+    }
+    
+    info!("User {} cancelled scheduled message {}", user_id, message_id);
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Helper functions
+fn validate_deliver_at(deliver_at: DateTime<Utc>) -> Result<(), AppError> {
+    let now = Utc::now();
+    
+    if deliver_at <= now {
+        return Err(AppError::ValidationError("deliver_at must be in the future".to_string()));
+    }
+    
+    if deliver_at > now + Duration::days(MAX_SCHEDULE_AHEAD_DAYS) {
+        return Err(AppError::ValidationError(format!(
+            "Messages can be scheduled at most {} days ahead",
+            MAX_SCHEDULE_AHEAD_DAYS
+        )));
+    }
+    
+    Ok(())
+}
+
+// The processor checks who may be mentioned when the message goes out; this
+// only keeps obviously oversized lists from being stored
+fn validate_mentions(mentions: &MessageMentions) -> Result<(), AppError> {
+    if mentions.user_ids.len() > MessageMentions::MAX_USERS {
+        return Err(AppError::ValidationError(format!(
+            "A message can mention at most {} users",
+            MessageMentions::MAX_USERS
+        )));
+    }
+    
+    Ok(())
+}
+
+async fn is_member(db_pool: &PgPool, conversation_id: Uuid, user_id: Uuid) -> Result<bool, AppError> {
+    let member = sqlx::query!(
+        r#"
+        SELECT user_id FROM group_members
+        WHERE group_id = $1 AND user_id = $2 AND is_banned = false
+        "#,
+        conversation_id,
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await?;
+    
+    Ok(member.is_some())
+}
+
+/// Explains why an edit or cancel matched nothing: the message either isn't
+/// the caller's or has already been sent or cancelled.
+async fn not_pending_error(db_pool: &PgPool, message_id: Uuid, user_id: Uuid) -> AppError {
+    let status = sqlx::query_scalar!(
+        "SELECT status FROM scheduled_messages WHERE id = $1 AND sender_id = $2",
+        message_id,
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await;
+    
+    match status {
+        Ok(Some(status)) => AppError::Conflict(format!("Scheduled message was already {}", status)),
+        Ok(None) => AppError::NotFound("Scheduled message not found".to_string()),
+        Err(e) => e.into(),
+    }
+}
+
+fn authenticate(headers: &HeaderMap) -> Result<Uuid, AppError> {
+    use jsonwebtoken::{decode, DecodingKey, Validation};
+    
+    let token = headers
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .ok_or(AppError::Unauthorized("Missing token".to_string()))?;
+    
+    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    let token_data = decode::<TokenClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::default(),
+    )
+    .map_err(|_| AppError::Unauthorized("Invalid token".to_string()))?;
+    
+    Ok(token_data.claims.sub)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenClaims {
+    sub: Uuid,
+    exp: usize,
+    iat: usize,
+    device_id: String,
+    session_id: Uuid,
+}
@@ -5,7 +5,7 @@ use aes_gcm::{
 use chacha20poly1305::{ChaCha20Poly1305, Key as ChaChaKey, Nonce as ChaChaNonce};
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use x25519_dalek::{PublicKey, StaticSecret};
-use rand::rngs::OsRng;
+use rand::{rngs::OsRng, RngCore};
 use thiserror::Error;
 use base64::{Engine as _, engine::general_purpose};
 
@@ -172,3 +172,106 @@ pub fn decrypt_aes_gcm(key: &[u8; 32], ciphertext: &[u8], nonce: &[u8; 12]) -> R
 pub fn generate_shared_secret(local_secret: &StaticSecret, remote_public: &PublicKey) -> [u8; 32] {
     local_secret.diffie_hellman(remote_public).to_bytes()
 }
+
+pub fn generate_group_key() -> [u8; 32] {
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    key
+}
+
+/// A key encrypted to one recipient's X25519 public key.
+pub struct WrappedKey {
+    pub ephemeral_public: [u8; 32],
+    pub ciphertext: Vec<u8>,
+    pub nonce: [u8; 12],
+}
+
+/// Encrypts `key` under a secret shared between a fresh ephemeral key and
+/// `recipient`, so only the holder of the recipient's secret can read it.
+pub fn wrap_key(key: &[u8; 32], recipient: &PublicKey) -> Result<WrappedKey, CryptoError> {
+    let ephemeral_secret = StaticSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral_secret);
+    let wrapping_key = derive_wrapping_key(&generate_shared_secret(&ephemeral_secret, recipient));
+    
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce);
+    
+    let ciphertext = Aes256Gcm::new((&wrapping_key).into())
+        .encrypt(Nonce::from_slice(&nonce), key.as_slice())
+        .map_err(|_| CryptoError::EncryptionError)?;
+    
+    Ok(WrappedKey {
+        ephemeral_public: ephemeral_public.to_bytes(),
+        ciphertext,
+        nonce,
+    })
+}
+
+pub fn unwrap_key(secret: &StaticSecret, wrapped: &WrappedKey) -> Result<[u8; 32], CryptoError> {
+    let ephemeral_public = PublicKey::from(wrapped.ephemeral_public);
+    let wrapping_key = derive_wrapping_key(&generate_shared_secret(secret, &ephemeral_public));
+    
+    let key = decrypt_aes_gcm(&wrapping_key, &wrapped.ciphertext, &wrapped.nonce)?;
+    key.try_into().map_err(|_| CryptoError::InvalidKey)
+}
+
+fn derive_wrapping_key(shared_secret: &[u8; 32]) -> [u8; 32] {
+    let mut output = [0u8; 32];
+    hkdf::Hkdf::<sha2::Sha256>::new(None, shared_secret)
+        .expand(b"group_key_wrap", &mut output)
+        .expect("HKDF expansion failed");
+    output
+}
+
+/// Signs system messages so clients can tell them from anything a member
+/// could have sent. The signature covers the conversation and message ids as
+/// well as the content, so a system message can't be replayed elsewhere.
+pub struct SystemMessageSigner {
+    signing_key: SigningKey,
+}
+
+impl SystemMessageSigner {
+    /// `secret` is the base64-encoded 32-byte Ed25519 seed.
+    pub fn from_base64(secret: &str) -> Result<Self, CryptoError> {
+        let bytes = general_purpose::STANDARD
+            .decode(secret)
+            .map_err(|_| CryptoError::InvalidKey)?;
+        let seed: [u8; 32] = bytes.try_into().map_err(|_| CryptoError::InvalidKey)?;
+        
+        Ok(Self {
+            signing_key: SigningKey::from_bytes(&seed),
+        })
+    }
+    
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.signing_key.verifying_key()
+    }
+    
+    pub fn sign(&self, conversation_id: &uuid::Uuid, message_id: &uuid::Uuid, content: &[u8]) -> Vec<u8> {
+        let payload = system_message_payload(conversation_id, message_id, content);
+        self.signing_key.sign(&payload).to_bytes().to_vec()
+    }
+}
+
+pub fn verify_system_message(
+    verifying_key: &VerifyingKey,
+    conversation_id: &uuid::Uuid,
+    message_id: &uuid::Uuid,
+    content: &[u8],
+    signature: &[u8],
+) -> Result<(), CryptoError> {
+    let signature = Signature::from_slice(signature).map_err(|_| CryptoError::InvalidSignature)?;
+    let payload = system_message_payload(conversation_id, message_id, content);
+    
+    verifying_key
+        .verify(&payload, &signature)
+        .map_err(|_| CryptoError::InvalidSignature)
+}
+
+fn system_message_payload(conversation_id: &uuid::Uuid, message_id: &uuid::Uuid, content: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(32 + content.len());
+    payload.extend_from_slice(conversation_id.as_bytes());
+    payload.extend_from_slice(message_id.as_bytes());
+    payload.extend_from_slice(content);
+    payload
+}
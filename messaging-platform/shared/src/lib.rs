@@ -3,3 +3,6 @@ pub mod errors;
 pub mod crypto;
 pub mod types;
 pub mod utils;
+pub mod permissions;
+pub mod migrations;
+pub mod restrictions;
@@ -0,0 +1,8 @@
+use sqlx::migrate::Migrator;
+
+// All services share one Postgres database, so they share one migration
+// history too. Each service runs the whole set on startup: a service that
+// only knew its own migrations would find the others' versions applied but
+// missing and refuse to start. sqlx takes an advisory lock while migrating,
+// so services starting together don't race.
+pub static MIGRATOR: Migrator = sqlx::migrate!("../migrations");
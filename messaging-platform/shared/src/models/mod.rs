@@ -16,7 +16,7 @@ pub struct User {
     pub last_seen: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum MessageType {
     Text,
     Image,
@@ -39,6 +39,98 @@ pub struct Message {
     pub deleted: bool,
 }
 
+/// Who a message mentions. Sent in cleartext next to the encrypted content so
+/// the server can count mentions and route notifications without reading it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct MessageMentions {
+    #[serde(default)]
+    pub user_ids: Vec<Uuid>,
+    #[serde(default)]
+    pub everyone: bool,
+}
+
+impl MessageMentions {
+    pub const MAX_USERS: usize = 50;
+    
+    pub fn is_empty(&self) -> bool {
+        !self.everyone && self.user_ids.is_empty()
+    }
+    
+    pub fn includes(&self, user_id: &Uuid) -> bool {
+        self.everyone || self.user_ids.contains(user_id)
+    }
+}
+
+/// How long new messages in a conversation live before they are deleted.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", content = "seconds")]
+pub enum DisappearingTimer {
+    Off,
+    OneHour,
+    OneDay,
+    SevenDays,
+    Custom(u32),
+}
+
+impl DisappearingTimer {
+    pub const MIN_CUSTOM_SECONDS: u32 = 30;
+    pub const MAX_CUSTOM_SECONDS: u32 = 90 * 86400;
+    
+    /// Lifetime in seconds; 0 means messages don't expire.
+    pub fn seconds(&self) -> u32 {
+        match self {
+            DisappearingTimer::Off => 0,
+            DisappearingTimer::OneHour => 3600,
+            DisappearingTimer::OneDay => 86400,
+            DisappearingTimer::SevenDays => 7 * 86400,
+            DisappearingTimer::Custom(seconds) => *seconds,
+        }
+    }
+    
+    pub fn from_seconds(seconds: u32) -> Self {
+        match seconds {
+            0 => DisappearingTimer::Off,
+            3600 => DisappearingTimer::OneHour,
+            86400 => DisappearingTimer::OneDay,
+            604800 => DisappearingTimer::SevenDays,
+            other => DisappearingTimer::Custom(other),
+        }
+    }
+    
+    pub fn is_valid(&self) -> bool {
+        match self {
+            DisappearingTimer::Custom(seconds) => {
+                (Self::MIN_CUSTOM_SECONDS..=Self::MAX_CUSTOM_SECONDS).contains(seconds)
+            }
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum DeleteScope {
+    ForMe,
+    ForEveryone,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageEdit {
+    pub message_id: Uuid,
+    pub conversation_id: Uuid,
+    pub editor_id: Uuid,
+    pub previous_content: Vec<u8>, // Still encrypted; only moderators read this
+    pub previous_nonce: Vec<u8>,
+    pub edited_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinnedMessage {
+    pub conversation_id: Uuid,
+    pub message_id: Uuid,
+    pub pinned_by: Uuid,
+    pub pinned_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Conversation {
     pub id: Uuid,
@@ -50,20 +142,44 @@ pub struct Conversation {
     pub is_encrypted: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ConversationType {
     DirectMessage,
     Group,
     Channel,
 }
 
+impl ConversationType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConversationType::DirectMessage => "direct_message",
+            ConversationType::Group => "group",
+            ConversationType::Channel => "channel",
+        }
+    }
+}
+
+impl std::str::FromStr for ConversationType {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "direct_message" => Ok(ConversationType::DirectMessage),
+            "group" => Ok(ConversationType::Group),
+            "channel" => Ok(ConversationType::Channel),
+            other => Err(format!("Unknown conversation type: {}", other)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GroupMember {
     pub user_id: Uuid,
     pub group_id: Uuid,
     pub role: GroupRole,
     pub joined_at: DateTime<Utc>,
-    pub permissions: Vec<Permission>,
+    pub permissions: Vec<Permission>,        // Granted on top of the role's defaults
+    pub denied_permissions: Vec<Permission>, // Withheld even if the role has them
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -74,7 +190,59 @@ pub enum GroupRole {
     Guest,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl GroupRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GroupRole::Owner => "owner",
+            GroupRole::Admin => "admin",
+            GroupRole::Member => "member",
+            GroupRole::Guest => "guest",
+        }
+    }
+    
+    pub fn is_admin(&self) -> bool {
+        matches!(self, GroupRole::Owner | GroupRole::Admin)
+    }
+    
+    pub fn default_permissions(&self) -> Vec<Permission> {
+        match self {
+            GroupRole::Owner | GroupRole::Admin => vec![
+                Permission::SendMessages,
+                Permission::DeleteMessages,
+                Permission::AddMembers,
+                Permission::RemoveMembers,
+                Permission::ChangeGroupInfo,
+                Permission::PinMessages,
+                Permission::MentionEveryone,
+            ],
+            GroupRole::Member => vec![
+                Permission::SendMessages,
+                Permission::AddMembers,
+            ],
+            GroupRole::Guest => vec![],
+        }
+    }
+    
+    pub fn has_permission(&self, permission: &Permission) -> bool {
+        self.default_permissions().contains(permission)
+    }
+}
+
+impl std::str::FromStr for GroupRole {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "owner" => Ok(GroupRole::Owner),
+            "admin" => Ok(GroupRole::Admin),
+            "member" => Ok(GroupRole::Member),
+            "guest" => Ok(GroupRole::Guest),
+            other => Err(format!("Unknown group role: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum Permission {
     SendMessages,
     DeleteMessages,
@@ -85,6 +253,311 @@ pub enum Permission {
     MentionEveryone,
 }
 
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::SendMessages => "send_messages",
+            Permission::DeleteMessages => "delete_messages",
+            Permission::AddMembers => "add_members",
+            Permission::RemoveMembers => "remove_members",
+            Permission::ChangeGroupInfo => "change_group_info",
+            Permission::PinMessages => "pin_messages",
+            Permission::MentionEveryone => "mention_everyone",
+        }
+    }
+}
+
+impl std::str::FromStr for Permission {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "send_messages" => Ok(Permission::SendMessages),
+            "delete_messages" => Ok(Permission::DeleteMessages),
+            "add_members" => Ok(Permission::AddMembers),
+            "remove_members" => Ok(Permission::RemoveMembers),
+            "change_group_info" => Ok(Permission::ChangeGroupInfo),
+            "pin_messages" => Ok(Permission::PinMessages),
+            "mention_everyone" => Ok(Permission::MentionEveryone),
+            other => Err(format!("Unknown permission: {}", other)),
+        }
+    }
+}
+
+/// A user's availability. Online users drift to idle and then away without
+/// activity; dnd and invisible only change when the user changes them.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceStatus {
+    Online,
+    Idle,
+    Away,
+    Dnd,
+    Invisible,
+    Offline,
+}
+
+impl PresenceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PresenceStatus::Online => "online",
+            PresenceStatus::Idle => "idle",
+            PresenceStatus::Away => "away",
+            PresenceStatus::Dnd => "dnd",
+            PresenceStatus::Invisible => "invisible",
+            PresenceStatus::Offline => "offline",
+        }
+    }
+    
+    /// Which status wins when a user's devices disagree. A status the user
+    /// chose deliberately outranks one derived from activity.
+    pub fn priority(&self) -> u8 {
+        match self {
+            PresenceStatus::Invisible => 5,
+            PresenceStatus::Dnd => 4,
+            PresenceStatus::Online => 3,
+            PresenceStatus::Idle => 2,
+            PresenceStatus::Away => 1,
+            PresenceStatus::Offline => 0,
+        }
+    }
+    
+    /// The status after going `inactive_minutes` without user activity.
+    pub fn after_inactivity(self, inactive_minutes: i64, idle_after: i64, away_after: i64) -> Self {
+        match self {
+            PresenceStatus::Online | PresenceStatus::Idle if inactive_minutes >= away_after => {
+                PresenceStatus::Away
+            }
+            PresenceStatus::Online if inactive_minutes >= idle_after => PresenceStatus::Idle,
+            status => status,
+        }
+    }
+    
+    /// How the status looks to everyone but the user; invisible users appear offline.
+    pub fn as_seen_by_others(self) -> Self {
+        match self {
+            PresenceStatus::Invisible => PresenceStatus::Offline,
+            status => status,
+        }
+    }
+    
+    pub fn is_online(&self) -> bool {
+        !matches!(self, PresenceStatus::Invisible | PresenceStatus::Offline)
+    }
+}
+
+impl std::str::FromStr for PresenceStatus {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "online" => Ok(PresenceStatus::Online),
+            "idle" => Ok(PresenceStatus::Idle),
+            "away" => Ok(PresenceStatus::Away),
+            "dnd" => Ok(PresenceStatus::Dnd),
+            "invisible" => Ok(PresenceStatus::Invisible),
+            "offline" => Ok(PresenceStatus::Offline),
+            other => Err(format!("Unknown presence status: {}", other)),
+        }
+    }
+}
+
+/// What the user is doing, shown alongside a custom status.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceActivity {
+    InACall,
+    InAMeeting,
+    Focusing,
+    Commuting,
+    OnVacation,
+}
+
+impl PresenceActivity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PresenceActivity::InACall => "in_a_call",
+            PresenceActivity::InAMeeting => "in_a_meeting",
+            PresenceActivity::Focusing => "focusing",
+            PresenceActivity::Commuting => "commuting",
+            PresenceActivity::OnVacation => "on_vacation",
+        }
+    }
+}
+
+impl std::str::FromStr for PresenceActivity {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "in_a_call" => Ok(PresenceActivity::InACall),
+            "in_a_meeting" => Ok(PresenceActivity::InAMeeting),
+            "focusing" => Ok(PresenceActivity::Focusing),
+            "commuting" => Ok(PresenceActivity::Commuting),
+            "on_vacation" => Ok(PresenceActivity::OnVacation),
+            other => Err(format!("Unknown presence activity: {}", other)),
+        }
+    }
+}
+
+pub const MAX_CUSTOM_STATUS_TEXT_CHARS: usize = 128;
+pub const MAX_CUSTOM_STATUS_EMOJI_CHARS: usize = 16;
+
+/// A status the user wrote themselves. It disappears on its own once
+/// `clear_after` (unix seconds) has passed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CustomStatus {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emoji: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub activity: Option<PresenceActivity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clear_after: Option<i64>,
+}
+
+impl CustomStatus {
+    pub fn is_empty(&self) -> bool {
+        self.text.is_none() && self.emoji.is_none() && self.activity.is_none()
+    }
+    
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.clear_after.map_or(false, |clear_after| clear_after <= now)
+    }
+    
+    pub fn validate(&self, now: i64) -> Result<(), String> {
+        if self.is_empty() {
+            return Err("Custom status needs text, an emoji or an activity".to_string());
+        }
+        
+        if self.text.as_ref().map_or(false, |text| text.chars().count() > MAX_CUSTOM_STATUS_TEXT_CHARS) {
+            return Err(format!(
+                "Custom status text cannot exceed {} characters", MAX_CUSTOM_STATUS_TEXT_CHARS
+            ));
+        }
+        
+        if self.emoji.as_ref().map_or(false, |emoji| emoji.chars().count() > MAX_CUSTOM_STATUS_EMOJI_CHARS) {
+            return Err("Custom status emoji is too long".to_string());
+        }
+        
+        if self.is_expired(now) {
+            return Err("Custom status must clear in the future".to_string());
+        }
+        
+        Ok(())
+    }
+}
+
+/// Who may see a user's last-seen time or online status. Sharing is
+/// reciprocal: users who hide theirs from someone can't see that person's.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceVisibility {
+    #[default]
+    Everyone,
+    Contacts,
+    Nobody,
+}
+
+impl PresenceVisibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PresenceVisibility::Everyone => "everyone",
+            PresenceVisibility::Contacts => "contacts",
+            PresenceVisibility::Nobody => "nobody",
+        }
+    }
+    
+    pub fn allows(&self, is_contact: bool) -> bool {
+        match self {
+            PresenceVisibility::Everyone => true,
+            PresenceVisibility::Contacts => is_contact,
+            PresenceVisibility::Nobody => false,
+        }
+    }
+}
+
+impl std::str::FromStr for PresenceVisibility {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "everyone" => Ok(PresenceVisibility::Everyone),
+            "contacts" => Ok(PresenceVisibility::Contacts),
+            "nobody" => Ok(PresenceVisibility::Nobody),
+            other => Err(format!("Unknown presence visibility: {}", other)),
+        }
+    }
+}
+
+/// One user's relationship to another. Friendships are mutual and stored for
+/// both users; contacts and blocks belong to the user who made them.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RelationshipType {
+    Friend,
+    Contact,
+    Blocked,
+}
+
+impl RelationshipType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RelationshipType::Friend => "friend",
+            RelationshipType::Contact => "contact",
+            RelationshipType::Blocked => "blocked",
+        }
+    }
+}
+
+impl std::str::FromStr for RelationshipType {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "friend" => Ok(RelationshipType::Friend),
+            "contact" => Ok(RelationshipType::Contact),
+            "blocked" => Ok(RelationshipType::Blocked),
+            other => Err(format!("Unknown relationship type: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FriendRequestStatus {
+    Pending,
+    Accepted,
+    Declined,
+    Cancelled,
+}
+
+impl FriendRequestStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FriendRequestStatus::Pending => "pending",
+            FriendRequestStatus::Accepted => "accepted",
+            FriendRequestStatus::Declined => "declined",
+            FriendRequestStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl std::str::FromStr for FriendRequestStatus {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pending" => Ok(FriendRequestStatus::Pending),
+            "accepted" => Ok(FriendRequestStatus::Accepted),
+            "declined" => Ok(FriendRequestStatus::Declined),
+            "cancelled" => Ok(FriendRequestStatus::Cancelled),
+            other => Err(format!("Unknown friend request status: {}", other)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EncryptionSession {
     pub session_id: Uuid,
@@ -141,3 +614,65 @@ pub enum ReportStatus {
     Resolved,
     Dismissed,
 }
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ModerationAction {
+    BanUser,
+    UnbanUser,
+    MuteUser,
+    UnmuteUser,
+    DeleteMessage,
+    DeleteGroup,
+    RemoveMember,
+}
+
+/// What a moderation action is taken against. Messages live in Scylla keyed
+/// by conversation, so a message target names both.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", content = "data")]
+pub enum ModerationTarget {
+    User {
+        user_id: Uuid,
+    },
+    Message {
+        conversation_id: Uuid,
+        message_id: Uuid,
+    },
+    Group {
+        group_id: Uuid,
+    },
+    Member {
+        group_id: Uuid,
+        user_id: Uuid,
+    },
+}
+
+/// A restriction kept in `user_restrictions`. Bans lock the user out
+/// entirely; muted users can still sign in and read but not send.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RestrictionType {
+    Ban,
+    Mute,
+}
+
+impl RestrictionType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RestrictionType::Ban => "ban",
+            RestrictionType::Mute => "mute",
+        }
+    }
+}
+
+impl std::str::FromStr for RestrictionType {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ban" => Ok(RestrictionType::Ban),
+            "mute" => Ok(RestrictionType::Mute),
+            other => Err(format!("Unknown restriction type: {}", other)),
+        }
+    }
+}
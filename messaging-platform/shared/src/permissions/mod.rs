@@ -0,0 +1,229 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::{GroupRole, Permission};
+
+// Evaluates what a member may do in a conversation. A member's role supplies
+// the defaults, per-member grants and denials adjust them, and conversation
+// settings restrict sending on top of that. The owner is never restricted.
+
+pub const MAX_SLOW_MODE_SECONDS: u32 = 3600;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConversationSettings {
+    pub only_admins_can_send: bool,
+    pub slow_mode_seconds: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemberPermissions {
+    pub role: GroupRole,
+    pub granted: Vec<Permission>,
+    pub denied: Vec<Permission>,
+}
+
+impl MemberPermissions {
+    pub fn new(role: GroupRole, granted: Vec<Permission>, denied: Vec<Permission>) -> Self {
+        Self { role, granted, denied }
+    }
+    
+    /// Role defaults adjusted by this member's overrides. A permission that is
+    /// both granted and denied is denied.
+    pub fn has(&self, permission: &Permission) -> bool {
+        if self.role == GroupRole::Owner {
+            return true;
+        }
+        
+        if self.denied.contains(permission) {
+            return false;
+        }
+        
+        self.granted.contains(permission) || self.role.has_permission(permission)
+    }
+    
+    /// Like `has`, but also applies the conversation's settings. "Only admins
+    /// can send" can be lifted for individual members with an explicit grant.
+    pub fn allows(&self, permission: &Permission, settings: &ConversationSettings) -> bool {
+        if !self.has(permission) {
+            return false;
+        }
+        
+        if *permission == Permission::SendMessages && settings.only_admins_can_send {
+            return self.role.is_admin() || self.granted.contains(permission);
+        }
+        
+        true
+    }
+    
+    /// The minimum gap between this member's messages, if slow mode applies to
+    /// them. Admins are exempt.
+    pub fn slow_mode(&self, settings: &ConversationSettings) -> Option<Duration> {
+        if settings.slow_mode_seconds == 0 || self.role.is_admin() {
+            return None;
+        }
+        
+        Some(Duration::seconds(settings.slow_mode_seconds as i64))
+    }
+    
+    /// When this member may next send, given the time of their last message.
+    /// `None` means they may send now.
+    pub fn next_send_allowed_at(
+        &self,
+        settings: &ConversationSettings,
+        last_sent_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let interval = self.slow_mode(settings)?;
+        let next_allowed = last_sent_at? + interval;
+        
+        if next_allowed > now {
+            Some(next_allowed)
+        } else {
+            None
+        }
+    }
+}
+
+/// Parses stored permission names, skipping any this build doesn't know.
+pub fn parse_permissions(names: &[String]) -> Vec<Permission> {
+    names.iter().filter_map(|name| name.parse().ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const ALL_ROLES: [GroupRole; 4] = [GroupRole::Owner, GroupRole::Admin, GroupRole::Member, GroupRole::Guest];
+    
+    const ALL_PERMISSIONS: [Permission; 7] = [
+        Permission::SendMessages,
+        Permission::DeleteMessages,
+        Permission::AddMembers,
+        Permission::RemoveMembers,
+        Permission::ChangeGroupInfo,
+        Permission::PinMessages,
+        Permission::MentionEveryone,
+    ];
+    
+    // Written out rather than derived from `default_permissions` so a change
+    // to a role's defaults has to be made here too
+    fn expected(role: &GroupRole, permission: &Permission) -> bool {
+        match role {
+            GroupRole::Owner | GroupRole::Admin => true,
+            GroupRole::Member => matches!(permission, Permission::SendMessages | Permission::AddMembers),
+            GroupRole::Guest => false,
+        }
+    }
+    
+    fn member(role: GroupRole) -> MemberPermissions {
+        MemberPermissions::new(role, vec![], vec![])
+    }
+    
+    fn settings(only_admins_can_send: bool, slow_mode_seconds: u32) -> ConversationSettings {
+        ConversationSettings { only_admins_can_send, slow_mode_seconds }
+    }
+    
+    #[test]
+    fn role_defaults_cover_every_permission() {
+        let open = ConversationSettings::default();
+        
+        for role in ALL_ROLES {
+            let member = member(role.clone());
+            for permission in &ALL_PERMISSIONS {
+                assert_eq!(member.has(permission), expected(&role, permission), "{:?} {:?}", role, permission);
+                assert_eq!(member.allows(permission, &open), expected(&role, permission), "{:?} {:?}", role, permission);
+            }
+        }
+    }
+    
+    #[test]
+    fn grants_add_to_role_defaults() {
+        for permission in &ALL_PERMISSIONS {
+            let guest = MemberPermissions::new(GroupRole::Guest, vec![permission.clone()], vec![]);
+            assert!(guest.has(permission), "{:?}", permission);
+            
+            for other in ALL_PERMISSIONS.iter().filter(|other| *other != permission) {
+                assert!(!guest.has(other), "{:?} granted {:?}", other, permission);
+            }
+        }
+    }
+    
+    #[test]
+    fn denials_override_role_defaults_and_grants() {
+        for role in [GroupRole::Admin, GroupRole::Member, GroupRole::Guest] {
+            for permission in &ALL_PERMISSIONS {
+                let denied = MemberPermissions::new(role.clone(), vec![], vec![permission.clone()]);
+                assert!(!denied.has(permission), "{:?} {:?}", role, permission);
+                
+                let both = MemberPermissions::new(role.clone(), vec![permission.clone()], vec![permission.clone()]);
+                assert!(!both.has(permission), "{:?} {:?}", role, permission);
+            }
+        }
+    }
+    
+    #[test]
+    fn owner_ignores_denials() {
+        let owner = MemberPermissions::new(GroupRole::Owner, vec![], ALL_PERMISSIONS.to_vec());
+        
+        for permission in &ALL_PERMISSIONS {
+            assert!(owner.has(permission), "{:?}", permission);
+        }
+    }
+    
+    #[test]
+    fn only_admins_can_send_restricts_non_admins() {
+        let restricted = settings(true, 0);
+        
+        assert!(member(GroupRole::Owner).allows(&Permission::SendMessages, &restricted));
+        assert!(member(GroupRole::Admin).allows(&Permission::SendMessages, &restricted));
+        assert!(!member(GroupRole::Member).allows(&Permission::SendMessages, &restricted));
+        assert!(!member(GroupRole::Guest).allows(&Permission::SendMessages, &restricted));
+        
+        // Other permissions are unaffected
+        assert!(member(GroupRole::Member).allows(&Permission::AddMembers, &restricted));
+    }
+    
+    #[test]
+    fn explicit_grant_lifts_only_admins_can_send() {
+        let restricted = settings(true, 0);
+        
+        let member = MemberPermissions::new(GroupRole::Member, vec![Permission::SendMessages], vec![]);
+        assert!(member.allows(&Permission::SendMessages, &restricted));
+        
+        let guest = MemberPermissions::new(GroupRole::Guest, vec![Permission::SendMessages], vec![]);
+        assert!(guest.allows(&Permission::SendMessages, &restricted));
+        
+        let denied_admin = MemberPermissions::new(GroupRole::Admin, vec![], vec![Permission::SendMessages]);
+        assert!(!denied_admin.allows(&Permission::SendMessages, &restricted));
+    }
+    
+    #[test]
+    fn slow_mode_exempts_admins() {
+        let slow = settings(false, 30);
+        
+        assert_eq!(member(GroupRole::Owner).slow_mode(&slow), None);
+        assert_eq!(member(GroupRole::Admin).slow_mode(&slow), None);
+        assert_eq!(member(GroupRole::Member).slow_mode(&slow), Some(Duration::seconds(30)));
+        assert_eq!(member(GroupRole::Guest).slow_mode(&slow), Some(Duration::seconds(30)));
+        
+        assert_eq!(member(GroupRole::Member).slow_mode(&settings(false, 0)), None);
+    }
+    
+    #[test]
+    fn next_send_allowed_at_waits_out_the_interval() {
+        let slow = settings(false, 30);
+        let member = member(GroupRole::Member);
+        let now = Utc::now();
+        
+        assert_eq!(member.next_send_allowed_at(&slow, None, now), None);
+        
+        let last_sent = now - Duration::seconds(10);
+        assert_eq!(member.next_send_allowed_at(&slow, Some(last_sent), now), Some(last_sent + Duration::seconds(30)));
+        
+        let long_ago = now - Duration::seconds(30);
+        assert_eq!(member.next_send_allowed_at(&slow, Some(long_ago), now), None);
+        
+        let admin = MemberPermissions::new(GroupRole::Admin, vec![], vec![]);
+        assert_eq!(admin.next_send_allowed_at(&slow, Some(now), now), None);
+    }
+}
@@ -0,0 +1,219 @@
+use chrono::{DateTime, TimeZone, Utc};
+use dashmap::DashMap;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::Message as KafkaMessage;
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::models::{ModerationAction, ModerationTarget, RestrictionType};
+use crate::types::ModerationActionEvent;
+
+// Bans and mutes are written to `user_restrictions` by the moderation
+// service. Every service a restricted user talks to checks them through a
+// `RestrictionCache`: auth refuses banned users a session, the gateway
+// refuses their connections and the message processor drops what banned or
+// muted users send.
+
+/// How long a user's restrictions are trusted before being read again.
+/// Moderation events keep cached entries current; this only bounds how long
+/// a missed event goes unnoticed.
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// An active ban or mute. `until` is `None` for permanent ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Restriction {
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl Restriction {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.until.map_or(true, |until| until > now)
+    }
+    
+    // Overlapping restrictions last as long as the longest of them
+    fn merge(self, other: Restriction) -> Restriction {
+        match (self.until, other.until) {
+            (Some(a), Some(b)) => Restriction { until: Some(a.max(b)) },
+            _ => Restriction { until: None },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UserRestrictions {
+    pub ban: Option<Restriction>,
+    pub mute: Option<Restriction>,
+}
+
+impl UserRestrictions {
+    pub fn is_banned(&self, now: DateTime<Utc>) -> bool {
+        self.ban.map_or(false, |ban| ban.is_active(now))
+    }
+    
+    pub fn is_muted(&self, now: DateTime<Utc>) -> bool {
+        self.mute.map_or(false, |mute| mute.is_active(now))
+    }
+    
+    /// Muted users can still sign in and read; banned users can do neither.
+    pub fn can_send(&self, now: DateTime<Utc>) -> bool {
+        !self.is_banned(now) && !self.is_muted(now)
+    }
+    
+    fn add(&mut self, restriction_type: RestrictionType, restriction: Restriction) {
+        let slot = match restriction_type {
+            RestrictionType::Ban => &mut self.ban,
+            RestrictionType::Mute => &mut self.mute,
+        };
+        
+        *slot = Some(match *slot {
+            Some(existing) => existing.merge(restriction),
+            None => restriction,
+        });
+    }
+}
+
+/// Per-process cache of users' restrictions, backed by Postgres. Users
+/// without any are cached too, since they are almost everyone.
+pub struct RestrictionCache {
+    db_pool: PgPool,
+    entries: DashMap<Uuid, (Instant, UserRestrictions)>,
+    ttl: Duration,
+}
+
+impl RestrictionCache {
+    pub fn new(db_pool: PgPool, ttl: Duration) -> Self {
+        Self {
+            db_pool,
+            entries: DashMap::new(),
+            ttl,
+        }
+    }
+    
+    /// For services that have no Postgres pool of their own.
+    pub async fn connect(database_url: &str, ttl: Duration) -> Result<Self, sqlx::Error> {
+        let db_pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(database_url)
+            .await?;
+        
+        Ok(Self::new(db_pool, ttl))
+    }
+    
+    pub async fn get(&self, user_id: Uuid) -> Result<UserRestrictions, sqlx::Error> {
+        let cached = self.entries.get(&user_id).map(|entry| *entry);
+        if let Some((loaded_at, restrictions)) = cached {
+            if loaded_at.elapsed() < self.ttl {
+                return Ok(restrictions);
+            }
+        }
+        
+        let restrictions = self.load(user_id).await?;
+        self.entries.insert(user_id, (Instant::now(), restrictions));
+        
+        Ok(restrictions)
+    }
+    
+    pub async fn is_banned(&self, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        Ok(self.get(user_id).await?.is_banned(Utc::now()))
+    }
+    
+    pub async fn can_send(&self, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        Ok(self.get(user_id).await?.can_send(Utc::now()))
+    }
+    
+    /// Brings a cached entry up to date. Users who aren't cached are left
+    /// alone; their next lookup reads Postgres, which the moderation service
+    /// updated before publishing.
+    pub fn apply(&self, event: &ModerationActionEvent) {
+        let user_id = match event.target {
+            ModerationTarget::User { user_id } => user_id,
+            _ => return,
+        };
+        
+        let until = event.expires_at.and_then(|at| Utc.timestamp_opt(at, 0).single());
+        
+        if let Some(mut entry) = self.entries.get_mut(&user_id) {
+            let restrictions = &mut entry.1;
+            match event.action {
+                ModerationAction::BanUser => restrictions.add(RestrictionType::Ban, Restriction { until }),
+                ModerationAction::MuteUser => restrictions.add(RestrictionType::Mute, Restriction { until }),
+                ModerationAction::UnbanUser => restrictions.ban = None,
+                ModerationAction::UnmuteUser => restrictions.mute = None,
+                _ => {}
+            }
+        }
+    }
+    
+    pub fn evict_expired(&self) {
+        self.entries.retain(|_, (loaded_at, _)| loaded_at.elapsed() < self.ttl);
+    }
+    
+    /// Follows `moderation-actions` and drops stale entries. Every process
+    /// needs every event, so each joins under a consumer group of its own
+    /// and starts from the latest offset.
+    pub async fn run(self: Arc<Self>, kafka_brokers: String, service: &str) -> Result<(), rdkafka::error::KafkaError> {
+        let consumer: StreamConsumer = rdkafka::config::ClientConfig::new()
+            .set("group.id", &format!("{}-restrictions-{}", service, Uuid::new_v4()))
+            .set("bootstrap.servers", &kafka_brokers)
+            .set("enable.partition.eof", "false")
+            .set("session.timeout.ms", "6000")
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "latest")
+            .create()?;
+        
+        consumer.subscribe(&["moderation-actions"])?;
+        
+        info!("Restriction cache following moderation actions");
+        
+        let mut eviction = tokio::time::interval(self.ttl);
+        
+        loop {
+            tokio::select! {
+                message = consumer.recv() => {
+                    let message = match message {
+                        Ok(message) => message,
+                        Err(e) => {
+                            warn!("Failed to receive moderation action: {}", e);
+                            continue;
+                        }
+                    };
+                    
+                    if let Some(payload) = message.payload() {
+                        match serde_json::from_slice::<ModerationActionEvent>(payload) {
+                            Ok(event) => self.apply(&event),
+                            Err(e) => warn!("Ignoring malformed moderation action: {}", e),
+                        }
+                    }
+                }
+                _ = eviction.tick() => self.evict_expired(),
+            }
+        }
+    }
+    
+    async fn load(&self, user_id: Uuid) -> Result<UserRestrictions, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT restriction_type, expires_at FROM user_restrictions
+            WHERE user_id = $1 AND (expires_at IS NULL OR expires_at > NOW())
+            "#,
+            user_id
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+        
+        let mut restrictions = UserRestrictions::default();
+        for row in rows {
+            match row.restriction_type.parse() {
+                Ok(restriction_type) => {
+                    restrictions.add(restriction_type, Restriction { until: row.expires_at });
+                }
+                Err(e) => warn!("Skipping restriction for user {}: {}", user_id, e),
+            }
+        }
+        
+        Ok(restrictions)
+    }
+}
@@ -0,0 +1,371 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::models::{
+    ConversationType, CustomStatus, DeleteScope, DisappearingTimer, GroupRole, ModerationAction,
+    ModerationTarget, Permission, PresenceStatus,
+};
+use crate::permissions::ConversationSettings;
+
+// Payloads exchanged between services over Kafka. The gateway produces
+// `MessageOperationEnvelope`s on `message-operations` and consumes the
+// `MessageUpdate`s the message processor publishes on `message-updates`.
+// The conversations service publishes `MembershipEvent`s on
+// `membership-events`. The message processor publishes a
+// `NotificationEvent` on `notifications` for each recipient of a new message.
+// The presence service is the only publisher of `PresenceEvent`s on
+// `presence-events`, and the moderation service of `ModerationActionEvent`s
+// on `moderation-actions`.
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageOperationEnvelope {
+    pub actor_id: Uuid,
+    pub conversation_id: Uuid,
+    pub message_id: Uuid,
+    pub operation: MessageOperation,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", content = "data")]
+pub enum MessageOperation {
+    Edit {
+        content: Vec<u8>, // Client-encrypted
+        nonce: Vec<u8>,
+    },
+    Delete {
+        scope: DeleteScope,
+    },
+    React {
+        emoji: String,
+        add: bool,
+    },
+    Pin {
+        pinned: bool,
+    },
+}
+
+/// A read receipt as sent by one of the reader's devices, published by the
+/// gateway on `read-receipts`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadReceiptEnvelope {
+    pub user_id: Uuid,
+    pub device_id: String,
+    pub conversation_id: Uuid,
+    pub message_id: Uuid,
+    pub read_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageUpdate {
+    pub recipients: Vec<Uuid>,
+    pub event: MessageUpdateEvent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum MessageUpdateEvent {
+    Edited {
+        conversation_id: Uuid,
+        message_id: Uuid,
+        editor_id: Uuid,
+        content: Vec<u8>,
+        nonce: Vec<u8>,
+        edited_at: i64,
+    },
+    Deleted {
+        conversation_id: Uuid,
+        message_id: Uuid,
+        deleted_by: Uuid,
+        scope: DeleteScope,
+        deleted_at: i64,
+    },
+    ReactionsUpdated {
+        conversation_id: Uuid,
+        message_id: Uuid,
+        user_id: Uuid,
+        emoji: String,
+        added: bool,
+        counts: HashMap<String, i64>,
+    },
+    PinUpdated {
+        conversation_id: Uuid,
+        message_id: Uuid,
+        pinned_by: Uuid,
+        pinned: bool,
+        updated_at: i64,
+    },
+    ThreadUpdated {
+        conversation_id: Uuid,
+        root_id: Uuid,
+        reply_id: Uuid,
+        reply_count: i64,
+    },
+    // Sent to the message's sender when a recipient reads it
+    MessageRead {
+        conversation_id: Uuid,
+        message_id: Uuid,
+        reader_id: Uuid,
+        read_at: i64,
+    },
+    // Sent to the reader's own devices to sync their read position
+    ReadMarkerUpdated {
+        conversation_id: Uuid,
+        last_read_message_id: Uuid,
+        read_at: i64,
+    },
+    // Disappearing messages whose timer ran out; clients purge local copies
+    Expired {
+        conversation_id: Uuid,
+        message_ids: Vec<Uuid>,
+    },
+}
+
+impl MessageUpdateEvent {
+    pub fn conversation_id(&self) -> Uuid {
+        match self {
+            MessageUpdateEvent::Edited { conversation_id, .. }
+            | MessageUpdateEvent::Deleted { conversation_id, .. }
+            | MessageUpdateEvent::ReactionsUpdated { conversation_id, .. }
+            | MessageUpdateEvent::PinUpdated { conversation_id, .. }
+            | MessageUpdateEvent::ThreadUpdated { conversation_id, .. }
+            | MessageUpdateEvent::MessageRead { conversation_id, .. }
+            | MessageUpdateEvent::ReadMarkerUpdated { conversation_id, .. }
+            | MessageUpdateEvent::Expired { conversation_id, .. } => *conversation_id,
+        }
+    }
+}
+
+/// A change to a conversation's membership or metadata. `recipients` is
+/// everyone who should hear about it, including a member who was just
+/// removed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MembershipEvent {
+    pub conversation_id: Uuid,
+    pub conversation_type: ConversationType,
+    pub is_encrypted: bool,
+    pub actor_id: Uuid,
+    pub change: MembershipChange,
+    pub recipients: Vec<Uuid>,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum MembershipChange {
+    Created {
+        name: Option<String>,
+        members: Vec<Uuid>,
+    },
+    MembersAdded {
+        user_ids: Vec<Uuid>,
+    },
+    MemberRemoved {
+        user_id: Uuid,
+    },
+    RoleChanged {
+        user_id: Uuid,
+        role: GroupRole,
+    },
+    InfoUpdated {
+        name: Option<String>,
+        avatar_url: Option<String>,
+    },
+    // Sent to admins when someone asks to join through an approval-only link
+    JoinRequested {
+        request_id: Uuid,
+        user_id: Uuid,
+    },
+    JoinRequestDenied {
+        request_id: Uuid,
+    },
+    PermissionsChanged {
+        user_id: Uuid,
+        granted: Vec<Permission>,
+        denied: Vec<Permission>,
+    },
+    SettingsUpdated {
+        settings: ConversationSettings,
+    },
+    DisappearingTimerChanged {
+        timer: DisappearingTimer,
+    },
+    // Published by the encryption service once members have a new group key
+    KeysRotated {
+        member_count: u32,
+    },
+    // Sent to every member when moderation deletes the conversation, so
+    // clients, inboxes and stored history drop it
+    Deleted,
+}
+
+impl MembershipChange {
+    /// Whether the set of people able to read the conversation changed, which
+    /// means an encrypted group needs a fresh group key.
+    pub fn changes_readers(&self) -> bool {
+        matches!(self, MembershipChange::MembersAdded { .. } | MembershipChange::MemberRemoved { .. })
+    }
+}
+
+/// Body of a `MessageType::System` message. Unlike user messages these are
+/// not end-to-end encrypted; `content` holds this serialized as JSON, signed
+/// by the message processor (see `crypto::SystemMessageSigner`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum SystemEvent {
+    ConversationCreated {
+        created_by: Uuid,
+        name: Option<String>,
+    },
+    // `added_by` equals the only user id when someone joined by themselves
+    MembersAdded {
+        added_by: Uuid,
+        user_ids: Vec<Uuid>,
+    },
+    // `removed_by` equals `user_id` when the member left
+    MemberRemoved {
+        removed_by: Uuid,
+        user_id: Uuid,
+    },
+    RoleChanged {
+        changed_by: Uuid,
+        user_id: Uuid,
+        role: GroupRole,
+    },
+    PermissionsChanged {
+        changed_by: Uuid,
+        user_id: Uuid,
+        granted: Vec<Permission>,
+        denied: Vec<Permission>,
+    },
+    InfoUpdated {
+        changed_by: Uuid,
+        name: Option<String>,
+        avatar_url: Option<String>,
+    },
+    SettingsUpdated {
+        changed_by: Uuid,
+        settings: ConversationSettings,
+    },
+    DisappearingTimerChanged {
+        changed_by: Uuid,
+        timer: DisappearingTimer,
+    },
+    KeysRotated {
+        triggered_by: Uuid,
+    },
+}
+
+impl SystemEvent {
+    /// The timeline entry for a membership change, if it gets one. Join
+    /// requests stay between the requester and the admins, and a deleted
+    /// conversation has no timeline left to record anything in.
+    pub fn from_membership_change(actor_id: Uuid, change: MembershipChange) -> Option<Self> {
+        let event = match change {
+            MembershipChange::Created { name, .. } => SystemEvent::ConversationCreated {
+                created_by: actor_id,
+                name,
+            },
+            MembershipChange::MembersAdded { user_ids } => SystemEvent::MembersAdded {
+                added_by: actor_id,
+                user_ids,
+            },
+            MembershipChange::MemberRemoved { user_id } => SystemEvent::MemberRemoved {
+                removed_by: actor_id,
+                user_id,
+            },
+            MembershipChange::RoleChanged { user_id, role } => SystemEvent::RoleChanged {
+                changed_by: actor_id,
+                user_id,
+                role,
+            },
+            MembershipChange::PermissionsChanged { user_id, granted, denied } => {
+                SystemEvent::PermissionsChanged {
+                    changed_by: actor_id,
+                    user_id,
+                    granted,
+                    denied,
+                }
+            }
+            MembershipChange::InfoUpdated { name, avatar_url } => SystemEvent::InfoUpdated {
+                changed_by: actor_id,
+                name,
+                avatar_url,
+            },
+            MembershipChange::SettingsUpdated { settings } => SystemEvent::SettingsUpdated {
+                changed_by: actor_id,
+                settings,
+            },
+            MembershipChange::DisappearingTimerChanged { timer } => {
+                SystemEvent::DisappearingTimerChanged {
+                    changed_by: actor_id,
+                    timer,
+                }
+            }
+            MembershipChange::KeysRotated { .. } => SystemEvent::KeysRotated {
+                triggered_by: actor_id,
+            },
+            MembershipChange::JoinRequested { .. }
+            | MembershipChange::JoinRequestDenied { .. }
+            | MembershipChange::Deleted => {
+                return None;
+            }
+        };
+        
+        Some(event)
+    }
+}
+
+/// One user to notify about a new message. Priority notifications are
+/// delivered even when the user has muted the conversation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationEvent {
+    pub user_id: Uuid,
+    pub conversation_id: Uuid,
+    pub message_id: Uuid,
+    pub sender_id: Uuid,
+    pub reason: NotificationReason,
+    pub priority: bool,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum NotificationReason {
+    NewMessage,
+    Mentioned,
+    MentionedEveryone,
+}
+
+impl NotificationReason {
+    pub fn is_priority(&self) -> bool {
+        !matches!(self, NotificationReason::NewMessage)
+    }
+}
+
+/// A device's presence changed. Other services learn presence from these
+/// rather than reading the presence store.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresenceEvent {
+    pub user_id: Uuid,
+    // The user's status across all devices after `device_id` changed; anyone
+    // showing it to other users applies `PresenceStatus::as_seen_by_others`
+    pub status: PresenceStatus,
+    pub device_id: String,
+    pub timestamp: i64,
+    pub custom_status: Option<CustomStatus>,
+}
+
+/// A moderator acted against a user, message or group. The moderation
+/// service has already updated Postgres by the time this is published; the
+/// gateway and message processor apply what lives in their own stores.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModerationActionEvent {
+    pub moderator_id: Uuid,
+    pub action: ModerationAction,
+    pub target: ModerationTarget,
+    pub reason: String,
+    // When a ban or mute lifts on its own; absent for permanent ones
+    pub expires_at: Option<i64>,
+    pub timestamp: i64,
+}
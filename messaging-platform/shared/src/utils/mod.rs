@@ -0,0 +1,25 @@
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use uuid::{timestamp::Timestamp, Uuid};
+
+/// 100ns intervals between the Gregorian epoch used by RFC 4122 (1582-10-15)
+/// and the Unix epoch.
+const GREGORIAN_OFFSET_TICKS: u64 = 0x01B2_1DD2_1381_4000;
+
+/// Day bucket a message sent at `timestamp` is stored under.
+pub fn day_bucket(timestamp: DateTime<Utc>) -> i32 {
+    timestamp.date_naive().num_days_from_ce()
+}
+
+/// Builds a version 1 (time-based) UUID for an explicit instant. Used where
+/// ids must be reproducible, e.g. when re-keying rows in a migration.
+pub fn timeuuid_at(timestamp: DateTime<Utc>, counter: u16, node_id: &[u8; 6]) -> Uuid {
+    let nanos = timestamp.timestamp_nanos_opt().unwrap_or(0).max(0) as u64;
+    let ticks = nanos / 100 + GREGORIAN_OFFSET_TICKS;
+    Uuid::new_v1(Timestamp::from_rfc4122(ticks, counter & 0x3FFF), node_id)
+}
+
+/// Instant encoded in a version 1 UUID, or `None` for other versions.
+pub fn timeuuid_datetime(id: &Uuid) -> Option<DateTime<Utc>> {
+    let (seconds, nanos) = id.get_timestamp()?.to_unix();
+    Utc.timestamp_opt(seconds as i64, nanos).single()
+}